@@ -59,6 +59,9 @@ pub fn TESTS(_args: &[&str]) -> Result<Vec<Box<dyn Testable>>, Box<dyn std::erro
         "--features=hashmap",
         "--features=hashmap,std",
         "--features=hashmap,std_io",
+        "--features=btree",
+        "--features=btree,std",
+        "--features=hashmap,btree",
     ];
     output.extend(features_list.iter().map(|feature| {
         Box::new(
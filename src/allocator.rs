@@ -0,0 +1,362 @@
+//! Allocator API used by the collections which allocate their own memory
+//!
+//! With the `unstable` feature, this re-exports `Allocator`, `Global` and
+//! `AllocError` from `alloc::alloc`, so any allocator implementing the
+//! nightly API can be used. On stable, a copy of that API is defined here
+//! instead, so the same code compiles with and without the feature.
+//!
+//! The collections take an allocator parameter defaulting to `Global`:
+//!
+//! - `TryLinkedList` and the btree `BTreeMap` and `BTreeSet` allocate their
+//!   nodes themselves, so any allocator can be used on stable too.
+//! - `TryVec` and `TryBox` wrap `Vec` and `Box`, whose allocator parameter is
+//!   unstable, so they can only be created in another allocator with the
+//!   `unstable` feature.
+//! - `TryHashMap` and `TryHashSet` hand the allocator to hashbrown through
+//!   `HashbrownAllocator`, so they can only use another allocator without
+//!   the `std` feature.
+//!
+//! A failed allocation is reported as a `TryReserveError` of the allocation
+//! error kind. It only carries the requested layout with the `unstable`
+//! feature, as stable can only get that error from a refused allocation,
+//! see `make_alloc_error`.
+//!
+//! # Examples
+//!
+//! ```
+//! # #![cfg_attr(feature = "unstable", feature(allocator_api))]
+//! use fallible_collections::allocator::Global;
+//! use fallible_collections::TryLinkedList;
+//!
+//! let mut list = TryLinkedList::new_in(Global);
+//! list.try_push_back(1).unwrap();
+//! assert_eq!(list.front(), Some(&1));
+//! ```
+use crate::{make_alloc_error, TryReserveError};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+#[cfg(feature = "unstable")]
+pub use alloc::alloc::{AllocError, Allocator, Global};
+
+#[cfg(not(feature = "unstable"))]
+pub use self::shim::{AllocError, Allocator, Global};
+
+#[cfg(not(feature = "unstable"))]
+mod shim {
+    use alloc::alloc::{alloc, alloc_zeroed, dealloc, realloc, Layout};
+    use core::fmt;
+    use core::ptr::{self, NonNull};
+
+    /// The error returned by an `Allocator` which could not allocate the
+    /// requested memory.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct AllocError;
+
+    impl fmt::Display for AllocError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("memory allocation failed")
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for AllocError {}
+
+    /// Stable copy of the nightly `core::alloc::Allocator` trait.
+    ///
+    /// # Safety
+    ///
+    /// The memory blocks returned by an allocator must stay valid until they
+    /// are deallocated, or the allocator and all of its clones are dropped.
+    /// A block allocated by an allocator can be deallocated by any of its
+    /// clones.
+    pub unsafe trait Allocator {
+        /// Allocates a block of memory fitting `layout`, which may be bigger
+        /// than the requested size.
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+        /// Behaves like `allocate`, but the memory is zeroed.
+        fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let ptr = self.allocate(layout)?;
+            unsafe { ptr::write_bytes(ptr.as_ptr() as *mut u8, 0, layout.size()) };
+            Ok(ptr)
+        }
+
+        /// Deallocates the memory block at `ptr`.
+        ///
+        /// # Safety
+        ///
+        /// `ptr` must have been allocated by this allocator with `layout`.
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+        /// Grows the memory block at `ptr`, moving it if needed. The block
+        /// is left untouched on error.
+        ///
+        /// # Safety
+        ///
+        /// `ptr` must have been allocated by this allocator with `old_layout`,
+        /// and `new_layout` must not be smaller than `old_layout`.
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let new_ptr = self.allocate(new_layout)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+            self.deallocate(ptr, old_layout);
+            Ok(new_ptr)
+        }
+
+        /// Behaves like `grow`, but the new memory is zeroed.
+        ///
+        /// # Safety
+        ///
+        /// Same as `grow`.
+        unsafe fn grow_zeroed(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let new_ptr = self.allocate_zeroed(new_layout)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, old_layout.size());
+            self.deallocate(ptr, old_layout);
+            Ok(new_ptr)
+        }
+
+        /// Shrinks the memory block at `ptr`, moving it if needed. The block
+        /// is left untouched on error.
+        ///
+        /// # Safety
+        ///
+        /// `ptr` must have been allocated by this allocator with `old_layout`,
+        /// and `new_layout` must not be bigger than `old_layout`.
+        unsafe fn shrink(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let new_ptr = self.allocate(new_layout)?;
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr() as *mut u8, new_layout.size());
+            self.deallocate(ptr, old_layout);
+            Ok(new_ptr)
+        }
+
+        /// Borrows this allocator, the reference being an allocator too.
+        #[inline(always)]
+        fn by_ref(&self) -> &Self
+        where
+            Self: Sized,
+        {
+            self
+        }
+    }
+
+    unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+        #[inline]
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).allocate(layout)
+        }
+
+        #[inline]
+        fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).allocate_zeroed(layout)
+        }
+
+        #[inline]
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            (**self).deallocate(ptr, layout)
+        }
+
+        #[inline]
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).grow(ptr, old_layout, new_layout)
+        }
+
+        #[inline]
+        unsafe fn grow_zeroed(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).grow_zeroed(ptr, old_layout, new_layout)
+        }
+
+        #[inline]
+        unsafe fn shrink(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            (**self).shrink(ptr, old_layout, new_layout)
+        }
+    }
+
+    /// The global memory allocator, as registered with `#[global_allocator]`.
+    #[derive(Copy, Clone, Default, Debug)]
+    pub struct Global;
+
+    impl Global {
+        fn alloc_impl(&self, layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError> {
+            let ptr = if layout.size() == 0 {
+                // a dangling but well aligned pointer
+                ptr::null_mut::<u8>().wrapping_add(layout.align())
+            } else if zeroed {
+                unsafe { alloc_zeroed(layout) }
+            } else {
+                unsafe { alloc(layout) }
+            };
+            let slice = ptr::slice_from_raw_parts_mut(ptr, layout.size());
+            NonNull::new(slice).ok_or(AllocError)
+        }
+    }
+
+    unsafe impl Allocator for Global {
+        #[inline]
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.alloc_impl(layout, false)
+        }
+
+        #[inline]
+        fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.alloc_impl(layout, true)
+        }
+
+        #[inline]
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            if layout.size() != 0 {
+                dealloc(ptr.as_ptr(), layout)
+            }
+        }
+
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            if old_layout.size() == 0 || old_layout.align() != new_layout.align() {
+                let new_ptr = self.allocate(new_layout)?;
+                ptr::copy_nonoverlapping(
+                    ptr.as_ptr(),
+                    new_ptr.as_ptr() as *mut u8,
+                    old_layout.size(),
+                );
+                self.deallocate(ptr, old_layout);
+                return Ok(new_ptr);
+            }
+            let new_ptr = realloc(ptr.as_ptr(), old_layout, new_layout.size());
+            let slice = ptr::slice_from_raw_parts_mut(new_ptr, new_layout.size());
+            NonNull::new(slice).ok_or(AllocError)
+        }
+    }
+}
+
+/// Allocates a block for `layout` in `alloc`. When the allocator failed, the
+/// error is an allocation error, which only carries `layout` with the
+/// `unstable` feature, see `make_alloc_error`.
+#[inline]
+pub(crate) fn try_allocate<A: Allocator + ?Sized>(
+    alloc: &A,
+    layout: Layout,
+) -> Result<NonNull<u8>, TryReserveError> {
+    match alloc.allocate(layout) {
+        Ok(ptr) => Ok(ptr.cast()),
+        Err(_) => Err(make_alloc_error(layout)),
+    }
+}
+
+/// Adapter implementing the allocator trait of hashbrown for an `Allocator`,
+/// so that `TryHashMap` and `TryHashSet` allocate their table in it. It
+/// shows up in the types of their iterators, and is not meant to be used
+/// directly.
+#[cfg(all(feature = "hashmap", not(feature = "std")))]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct HashbrownAllocator<A>(pub(crate) A);
+
+#[cfg(all(feature = "hashmap", not(feature = "std")))]
+unsafe impl<A: Allocator> allocator_api2::alloc::Allocator for HashbrownAllocator<A> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+        self.0
+            .allocate(layout)
+            .map_err(|_| allocator_api2::alloc::AllocError)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.deallocate(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Allocates at most `limit` live blocks from the global allocator.
+    pub(crate) struct Bounded {
+        live: Cell<usize>,
+        limit: usize,
+    }
+
+    impl Bounded {
+        pub(crate) fn new(limit: usize) -> Self {
+            Self {
+                live: Cell::new(0),
+                limit,
+            }
+        }
+
+        /// The number of blocks allocated and not freed yet.
+        pub(crate) fn live(&self) -> usize {
+            self.live.get()
+        }
+    }
+
+    unsafe impl Allocator for Bounded {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            if self.live.get() == self.limit {
+                return Err(AllocError);
+            }
+            let ptr = Global.allocate(layout)?;
+            self.live.set(self.live.get() + 1);
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn global() {
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = Global.allocate_zeroed(layout).unwrap();
+        assert_eq!(ptr.len(), layout.size());
+        let ptr: NonNull<[u64; 4]> = ptr.cast();
+        unsafe {
+            assert_eq!(*ptr.as_ptr(), [0; 4]);
+            Global.deallocate(ptr.cast(), layout);
+        }
+
+        // zero sized blocks are dangling but aligned
+        let layout = Layout::new::<[u64; 0]>();
+        let ptr = try_allocate(&Global, layout).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+        unsafe { Global.deallocate(ptr, layout) };
+
+        let layout = Layout::from_size_align(isize::MAX as usize - 7, 8).unwrap();
+        assert!(try_allocate(&Global, layout).is_err());
+    }
+}
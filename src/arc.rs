@@ -2,18 +2,26 @@
 #[cfg(not(feature = "unstable"))]
 use super::FallibleBox;
 use super::TryClone;
-use crate::TryReserveError;
-
+#[cfg(feature = "unstable")]
+use crate::make_try_reserve_error;
+use crate::{make_alloc_error, TryReserveError};
+use alloc::alloc::{alloc, dealloc, Layout};
 #[cfg(not(feature = "unstable"))]
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{self, AtomicUsize, Ordering};
 
 /// trait to implement Fallible Arc
 #[cfg_attr(
     not(feature = "unstable"),
     deprecated(
         since = "0.3.1",
-        note = "⚠️️️this function is not completely fallible, it can panic !, see [issue](https://github.com/vcombey/fallible_collections/issues/13). help wanted"
+        note = "⚠️️️this function is not completely fallible, it can panic !, see [issue](https://github.com/vcombey/fallible_collections/issues/13). use TryArc instead"
     )
 )]
 pub trait FallibleArc<T> {
@@ -35,14 +43,16 @@ impl<T> FallibleArc<T> for Arc<T> {
         }
         #[cfg(feature = "unstable")]
         {
-            use alloc::alloc::Layout;
-            use alloc::collections::TryReserveErrorKind;
+            // std doesn't tell the layout, but it allocates the counters
+            // followed by the value as a repr(C) struct, as ArcInner does
             Arc::try_new(t).map_err(|_e| {
-                TryReserveErrorKind::AllocError {
-                    layout: Layout::new::<Arc<T>>(), // This is bullshit
-                    non_exhaustive: (),
+                let layout = Layout::new::<[AtomicUsize; 2]>()
+                    .extend(Layout::new::<T>())
+                    .map(|(layout, _)| layout.pad_to_align());
+                match layout {
+                    Ok(layout) => make_alloc_error(layout),
+                    Err(_) => make_try_reserve_error(),
                 }
-                .into()
             })
         }
     }
@@ -55,8 +65,322 @@ impl<T: ?Sized> TryClone for Arc<T> {
     }
 }
 
+/// A soft limit on the amount of references, see std::sync::Arc
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+// the weak count includes one implicit weak reference hold by all the
+// strong references together, as in std. It is set to usize::MAX while
+// `get_mut` checks for uniqueness.
+#[repr(C)]
+struct ArcInner<T> {
+    strong: AtomicUsize,
+    weak: AtomicUsize,
+    data: T,
+}
+
+impl<T> ArcInner<T> {
+    #[inline]
+    fn inc(count: &AtomicUsize) {
+        let old = count.fetch_add(1, Ordering::Relaxed);
+        if old > MAX_REFCOUNT {
+            count.fetch_sub(1, Ordering::Relaxed);
+            panic!("reference count overflow");
+        }
+    }
+}
+
+/// TryArc is a thread-safe reference-counting pointer whose header and
+/// value are allocated in one fallible allocation, contrary to
+/// `Arc::from(Box<T>)` which reallocates.
+///
+/// As other types of this crate, it does not implement `Clone`, use
+/// `try_clone` which only bumps the reference count.
+pub struct TryArc<T> {
+    ptr: NonNull<ArcInner<T>>,
+    phantom: PhantomData<ArcInner<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for TryArc<T> {}
+unsafe impl<T: Send + Sync> Sync for TryArc<T> {}
+
+/// TryArcWeak is a version of `TryArc` that holds a non-owning reference
+/// to the managed allocation, see std::sync::Weak
+pub struct TryArcWeak<T> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for TryArcWeak<T> {}
+unsafe impl<T: Send + Sync> Sync for TryArcWeak<T> {}
+
+impl<T> TryArc<T> {
+    /// try creating a new TryArc, returning an error if allocation failed
+    #[inline]
+    pub fn try_new(data: T) -> Result<Self, TryReserveError> {
+        let ptr = Self::try_allocate()?;
+        unsafe {
+            ptr::write(
+                ptr.as_ptr(),
+                ArcInner {
+                    strong: AtomicUsize::new(1),
+                    weak: AtomicUsize::new(1),
+                    data,
+                },
+            );
+        }
+        Ok(Self::from_inner(ptr))
+    }
+
+    /// try creating a new TryArc with uninitialized contents
+    #[inline]
+    pub fn try_new_uninit() -> Result<TryArc<MaybeUninit<T>>, TryReserveError> {
+        TryArc::try_new(MaybeUninit::uninit())
+    }
+
+    fn try_allocate() -> Result<NonNull<ArcInner<T>>, TryReserveError> {
+        let layout = Layout::new::<ArcInner<T>>();
+        // the layout is never zero sized because of the counters
+        let ptr = unsafe { alloc(layout) } as *mut ArcInner<T>;
+        NonNull::new(ptr).ok_or_else(|| make_alloc_error(layout))
+    }
+
+    #[inline(always)]
+    fn from_inner(ptr: NonNull<ArcInner<T>>) -> Self {
+        Self {
+            ptr,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &ArcInner<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    fn is_unique(&self) -> bool {
+        // lock the weak count so no `TryArcWeak` can be upgraded meanwhile
+        if self
+            .inner()
+            .weak
+            .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            let unique = self.inner().strong.load(Ordering::Acquire) == 1;
+            self.inner().weak.store(1, Ordering::Release);
+            unique
+        } else {
+            false
+        }
+    }
+
+    /// Returns a mutable reference into the given `TryArc`, if there are
+    /// no other `TryArc` or `TryArcWeak` pointers to the same allocation.
+    #[inline]
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.is_unique() {
+            unsafe { Some(&mut (*this.ptr.as_ptr()).data) }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the inner value, if the `TryArc` has exactly one strong
+    /// reference, otherwise the same `TryArc` is given back.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        atomic::fence(Ordering::Acquire);
+        unsafe {
+            let data = ptr::read(&this.inner().data);
+            // drop the implicit weak reference of the strong ones
+            drop(TryArcWeak { ptr: this.ptr });
+            core::mem::forget(this);
+            Ok(data)
+        }
+    }
+
+    /// Creates a new `TryArcWeak` pointer to this allocation.
+    pub fn downgrade(this: &Self) -> TryArcWeak<T> {
+        let mut cur = this.inner().weak.load(Ordering::Relaxed);
+        loop {
+            // the weak count is locked by `is_unique`, spin
+            if cur == usize::MAX {
+                core::hint::spin_loop();
+                cur = this.inner().weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if cur > MAX_REFCOUNT {
+                panic!("reference count overflow");
+            }
+            match this.inner().weak.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return TryArcWeak { ptr: this.ptr },
+                Err(old) => cur = old,
+            }
+        }
+    }
+
+    #[inline]
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn weak_count(this: &Self) -> usize {
+        match this.inner().weak.load(Ordering::Acquire) {
+            // the weak count is locked, so it was 1 before
+            usize::MAX => 0,
+            cnt => cnt - 1,
+        }
+    }
+
+    /// Returns `true` if the two `TryArc`s point to the same allocation
+    #[inline(always)]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<T> TryArc<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// See std::sync::Arc::assume_init
+    #[inline]
+    pub unsafe fn assume_init(self) -> TryArc<T> {
+        let ptr = self.ptr.cast();
+        core::mem::forget(self);
+        TryArc::from_inner(ptr)
+    }
+}
+
+impl<T> TryClone for TryArc<T> {
+    /// Only bumps the reference count, this never fails
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        ArcInner::<T>::inc(&self.inner().strong);
+        Ok(Self::from_inner(self.ptr))
+    }
+}
+
+impl<T> Deref for TryArc<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner().data
+    }
+}
+
+impl<T> Drop for TryArc<T> {
+    fn drop(&mut self) {
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        unsafe {
+            ptr::drop_in_place(&mut (*self.ptr.as_ptr()).data);
+        }
+        // drop the implicit weak reference of the strong ones
+        drop(TryArcWeak { ptr: self.ptr });
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for TryArc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for TryArc<T> {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for TryArc<T> {}
+
+impl<T> TryArcWeak<T> {
+    #[inline(always)]
+    fn inner(&self) -> &ArcInner<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Attempts to upgrade the `TryArcWeak` pointer to a `TryArc`, returns
+    /// `None` if the inner value has since been dropped.
+    pub fn upgrade(&self) -> Option<TryArc<T>> {
+        let mut cur = self.inner().strong.load(Ordering::Relaxed);
+        loop {
+            if cur == 0 {
+                return None;
+            }
+            if cur > MAX_REFCOUNT {
+                panic!("reference count overflow");
+            }
+            match self.inner().strong.compare_exchange_weak(
+                cur,
+                cur + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(TryArc::from_inner(self.ptr)),
+                Err(old) => cur = old,
+            }
+        }
+    }
+
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.load(Ordering::Acquire)
+    }
+}
+
+impl<T> TryClone for TryArcWeak<T> {
+    /// Only bumps the weak reference count, this never fails
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        ArcInner::<T>::inc(&self.inner().weak);
+        Ok(Self { ptr: self.ptr })
+    }
+}
+
+impl<T> Drop for TryArcWeak<T> {
+    fn drop(&mut self) {
+        if self.inner().weak.fetch_sub(1, Ordering::Release) == 1 {
+            atomic::fence(Ordering::Acquire);
+            unsafe { dealloc(self.ptr.as_ptr() as *mut u8, Layout::new::<ArcInner<T>>()) }
+        }
+    }
+}
+
+impl<T> fmt::Debug for TryArcWeak<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn fallible_rc() {
         use std::sync::Arc;
@@ -68,4 +392,59 @@ mod test {
         let _y = Arc::clone(&x);
         assert!(Arc::get_mut(&mut x).is_none());
     }
+
+    #[test]
+    fn try_arc() {
+        let mut x = TryArc::try_new(3).unwrap();
+        *TryArc::get_mut(&mut x).unwrap() = 4;
+        assert_eq!(*x, 4);
+
+        let y = x.try_clone().unwrap();
+        assert_eq!(TryArc::strong_count(&x), 2);
+        assert!(TryArc::get_mut(&mut x).is_none());
+        assert!(TryArc::ptr_eq(&x, &y));
+        let x = TryArc::try_unwrap(x).unwrap_err();
+        drop(y);
+        assert_eq!(TryArc::try_unwrap(x), Ok(4));
+    }
+
+    #[test]
+    fn try_arc_weak() {
+        let mut x = TryArc::try_new(String::from("foo")).unwrap();
+        let weak = TryArc::downgrade(&x);
+        assert_eq!(TryArc::weak_count(&x), 1);
+        assert!(TryArc::get_mut(&mut x).is_none());
+        assert_eq!(*weak.upgrade().unwrap(), "foo");
+        drop(x);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    fn try_arc_threads() {
+        let counter = Arc::new(());
+        let x = TryArc::try_new(Arc::clone(&counter)).unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let x = x.try_clone().unwrap();
+                std::thread::spawn(move || {
+                    let weak = TryArc::downgrade(&x);
+                    assert!(weak.upgrade().is_some());
+                })
+            })
+            .collect();
+        drop(x);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn try_arc_uninit() {
+        let mut x = TryArc::<u32>::try_new_uninit().unwrap();
+        TryArc::get_mut(&mut x).unwrap().write(5);
+        let x = unsafe { x.assume_init() };
+        assert_eq!(*x, 5);
+    }
 }
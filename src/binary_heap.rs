@@ -0,0 +1,412 @@
+//! Implement a fallible BinaryHeap on top of TryVec
+use super::TryClone;
+use crate::vec::{TryFromIterator, TryVec};
+use crate::{AllocError, TryReserveError};
+use alloc::vec::Drain;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+/// TryBinaryHeap is a priority queue implemented with a binary heap stored in
+/// a `TryVec`, to provide support for fallible allocation. This will be a
+/// max-heap, use `core::cmp::Reverse` to get a min-heap.
+///
+/// See the crate documentation for more.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::TryBinaryHeap;
+///
+/// let mut heap = TryBinaryHeap::new();
+/// heap.try_push(1).unwrap();
+/// heap.try_push(5).unwrap();
+/// heap.try_push(2).unwrap();
+///
+/// assert_eq!(heap.peek(), Some(&5));
+/// assert_eq!(heap.len(), 3);
+/// assert_eq!(heap.into_sorted_vec().as_slice(), [1, 2, 5]);
+/// ```
+pub struct TryBinaryHeap<T> {
+    data: TryVec<T>,
+}
+
+impl<T> Default for TryBinaryHeap<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            data: TryVec::new(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryBinaryHeap<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.data.iter()).finish()
+    }
+}
+
+impl<T> TryBinaryHeap<T> {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.data.clear()
+    }
+
+    /// Removes all the elements of the heap, returning them in arbitrary
+    /// order. The allocation is kept for later use.
+    #[inline(always)]
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.data.drain(..)
+    }
+
+    /// Consumes the heap and returns the underlying vector in arbitrary order.
+    #[inline(always)]
+    pub fn into_vec(self) -> TryVec<T> {
+        self.data
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns an iterator visiting all the elements of the heap in arbitrary
+    /// order.
+    #[inline(always)]
+    pub fn iter(&self) -> alloc::slice::Iter<'_, T> {
+        self.data.as_slice().iter()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the greatest element of the heap, or `None` if it is empty.
+    #[inline(always)]
+    pub fn peek(&self) -> Option<&T> {
+        self.data.first()
+    }
+
+    /// Reserves room for at least `additional` more elements, the heap is left
+    /// unchanged on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.data.reserve(additional)
+    }
+
+    #[inline]
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Ok(Self {
+            data: TryVec::with_capacity(capacity)?,
+        })
+    }
+}
+
+impl<T: Ord> TryBinaryHeap<T> {
+    /// Consumes the heap and returns a vector in sorted (ascending) order.
+    /// The elements are sorted in place, so this never allocates.
+    pub fn into_sorted_vec(mut self) -> TryVec<T> {
+        let mut end = self.len();
+        while end > 1 {
+            end -= 1;
+            self.data.swap(0, end);
+            self.sift_down_range(0, end);
+        }
+        self.into_vec()
+    }
+
+    /// Removes the greatest element of the heap and returns it, or `None` if
+    /// it is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len();
+        if len > 1 {
+            self.data.swap(0, len - 1);
+        }
+        let item = self.data.pop()?;
+        self.sift_down_range(0, self.len());
+        Some(item)
+    }
+
+    /// Returns a mutable reference to the greatest element of the heap, or
+    /// `None` if it is empty. The heap is fixed when the returned `PeekMut`
+    /// is dropped, if the element was modified.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::TryBinaryHeap;
+    ///
+    /// let mut heap = TryBinaryHeap::new();
+    /// heap.try_push(1).unwrap();
+    /// heap.try_push(5).unwrap();
+    /// heap.try_push(2).unwrap();
+    /// if let Some(mut top) = heap.peek_mut() {
+    ///     *top = 0;
+    /// }
+    /// assert_eq!(heap.peek(), Some(&2));
+    /// ```
+    pub fn peek_mut(&mut self) -> Option<PeekMut<'_, T>> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(PeekMut {
+                heap: self,
+                sift: false,
+            })
+        }
+    }
+
+    /// Retains only the elements specified by the predicate, and restores
+    /// the heap order if any element was removed.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let len = self.len();
+        self.data.retain(|e| f(e));
+        if self.len() < len {
+            self.rebuild();
+        }
+    }
+
+    /// Moves all the elements of `other` into `self`, leaving `other` empty.
+    /// Both heaps are left unchanged on error (strong guarantee).
+    pub fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
+        // the elements of the smaller heap are pushed into the larger one
+        if self.len() < other.len() {
+            other.try_reserve(self.len())?;
+            core::mem::swap(self, other);
+        } else {
+            self.try_reserve(other.len())?;
+        }
+        let start = self.len();
+        // cannot fail, the room is reserved
+        self.data.append(&mut other.data)?;
+        for i in start..self.len() {
+            self.sift_up(i);
+        }
+        Ok(())
+    }
+
+    /// Pushes an element onto the heap. The heap is left unchanged on error
+    /// (strong guarantee).
+    pub fn try_push(&mut self, item: T) -> Result<(), TryReserveError> {
+        self.data.push(item)?;
+        self.sift_up(self.len() - 1);
+        Ok(())
+    }
+
+    /// Pushes an element onto the heap, giving back the element if the
+    /// allocation failed. The heap is left unchanged on error (strong
+    /// guarantee).
+    pub fn try_push_give_back(&mut self, item: T) -> Result<(), AllocError<T>> {
+        self.data.push_give_back(item)?;
+        self.sift_up(self.len() - 1);
+        Ok(())
+    }
+
+    /// Moves the element at `pos` up until its parent is not smaller.
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if self.data[pos] <= self.data[parent] {
+                break;
+            }
+            self.data.swap(pos, parent);
+            pos = parent;
+        }
+    }
+
+    /// Moves the element at `pos` down until its children in `..end` are not
+    /// greater.
+    fn sift_down_range(&mut self, mut pos: usize, end: usize) {
+        loop {
+            let mut child = 2 * pos + 1;
+            if child >= end {
+                break;
+            }
+            if child + 1 < end && self.data[child] < self.data[child + 1] {
+                child += 1;
+            }
+            if self.data[pos] >= self.data[child] {
+                break;
+            }
+            self.data.swap(pos, child);
+            pos = child;
+        }
+    }
+
+    /// Restores the heap order of the whole vector.
+    fn rebuild(&mut self) {
+        let len = self.len();
+        for pos in (0..len / 2).rev() {
+            self.sift_down_range(pos, len);
+        }
+    }
+}
+
+impl<T: TryClone> TryClone for TryBinaryHeap<T> {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Ok(Self {
+            data: self.data.try_clone()?,
+        })
+    }
+}
+
+impl<T: Ord> TryFromIterator<T> for TryBinaryHeap<T> {
+    fn try_from_iterator<I: IntoIterator<Item = T>>(iterator: I) -> Result<Self, TryReserveError> {
+        let mut heap = Self::new();
+        for item in iterator {
+            heap.data.push(item)?;
+        }
+        heap.rebuild();
+        Ok(heap)
+    }
+}
+
+impl<T> IntoIterator for TryBinaryHeap<T> {
+    type Item = T;
+    type IntoIter = alloc::vec::IntoIter<T>;
+
+    /// Consumes the heap, yielding its elements in arbitrary order.
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TryBinaryHeap<T> {
+    type Item = &'a T;
+    type IntoIter = alloc::slice::Iter<'a, T>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A mutable reference to the greatest element of a `TryBinaryHeap`, created
+/// by `TryBinaryHeap::peek_mut`.
+pub struct PeekMut<'a, T: Ord> {
+    heap: &'a mut TryBinaryHeap<T>,
+    // whether the element may have been modified
+    sift: bool,
+}
+
+impl<T: Ord + fmt::Debug> fmt::Debug for PeekMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PeekMut").field(&self.heap.data[0]).finish()
+    }
+}
+
+impl<T: Ord> PeekMut<'_, T> {
+    /// Removes the peeked element from the heap and returns it.
+    pub fn pop(mut this: Self) -> T {
+        // the heap is fixed by `pop`, not by the drop of `this`
+        this.sift = false;
+        match this.heap.pop() {
+            Some(item) => item,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl<T: Ord> Drop for PeekMut<'_, T> {
+    fn drop(&mut self) {
+        if self.sift {
+            let len = self.heap.len();
+            self.heap.sift_down_range(0, len);
+        }
+    }
+}
+
+impl<T: Ord> Deref for PeekMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.heap.data[0]
+    }
+}
+
+impl<T: Ord> DerefMut for PeekMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.sift = true;
+        &mut self.heap.data[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TryCollect;
+
+    #[test]
+    fn push_pop() {
+        let mut heap = TryBinaryHeap::try_with_capacity(4).unwrap();
+        for &x in &[3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5] {
+            heap.try_push(x).unwrap();
+        }
+        assert_eq!(heap.len(), 11);
+        assert_eq!(heap.peek(), Some(&9));
+        let mut popped = vec![];
+        while let Some(x) = heap.pop() {
+            popped.push(x);
+        }
+        assert_eq!(popped, [9, 6, 5, 5, 5, 4, 3, 3, 2, 1, 1]);
+        assert!(heap.peek().is_none());
+    }
+
+    #[test]
+    fn peek_mut() {
+        let mut heap: TryBinaryHeap<i32> = (0..10).try_collect().unwrap();
+        *heap.peek_mut().unwrap() -= 20;
+        assert_eq!(heap.peek(), Some(&8));
+        assert_eq!(PeekMut::pop(heap.peek_mut().unwrap()), 8);
+        // the heap is not reordered if the element was only read
+        assert_eq!(*heap.peek_mut().unwrap(), 7);
+        assert_eq!(
+            heap.into_sorted_vec().as_slice(),
+            [-11, 0, 1, 2, 3, 4, 5, 6, 7]
+        );
+    }
+
+    #[test]
+    fn append_retain_drain() {
+        let mut a: TryBinaryHeap<u32> = (0..5).try_collect().unwrap();
+        let mut b: TryBinaryHeap<u32> = (5..20).try_collect().unwrap();
+        a.try_append(&mut b).unwrap();
+        assert!(b.is_empty());
+        assert_eq!(a.len(), 20);
+
+        a.retain(|&x| x % 3 != 0);
+        let c = a.try_clone().unwrap();
+        assert_eq!(
+            c.into_sorted_vec().as_slice(),
+            [1, 2, 4, 5, 7, 8, 10, 11, 13, 14, 16, 17, 19]
+        );
+
+        let mut drained: Vec<u32> = a.drain().collect();
+        drained.sort_unstable();
+        assert_eq!(drained.len(), 13);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn try_reserve_oom() {
+        let mut heap = TryBinaryHeap::<u64>::new();
+        assert!(heap.try_reserve(usize::MAX).is_err());
+        assert!(heap.try_push(1).is_ok());
+    }
+}
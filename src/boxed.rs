@@ -1,10 +1,19 @@
 //! Implement Fallible Box
 use super::TryClone;
-use crate::TryReserveError;
+use crate::allocator::{Allocator, Global};
+use crate::{make_alloc_error, make_try_reserve_error, AllocError, TryReserveError};
+use alloc::alloc::{alloc, alloc_zeroed, Layout};
 use alloc::boxed::Box;
-use core::borrow::Borrow;
-use core::mem::ManuallyDrop;
-use core::ops::Deref;
+use core::borrow::{Borrow, BorrowMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+#[cfg(not(feature = "unstable"))]
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
 
 /// trait to implement Fallible Box
 pub trait FallibleBox<T> {
@@ -13,23 +22,185 @@ pub trait FallibleBox<T> {
     fn try_new(t: T) -> Result<Self, TryReserveError>
     where
         Self: Sized;
+    /// try creating a new box, giving back `t` if allocation failed
+    fn try_new_give_back(t: T) -> Result<Self, AllocError<T>>
+    where
+        Self: Sized;
+}
+
+/// trait to implement Fallible Box of slices
+pub trait FallibleBoxSlice<T> {
+    /// try creating a new boxed slice by copying `slice`
+    fn try_new_slice_from(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: Copy,
+        Self: Sized;
+    /// try creating a new boxed slice by trying to clone the elements of
+    /// `slice`
+    fn try_new_slice_from_no_copy(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: TryClone,
+        Self: Sized;
+    /// try creating a new boxed slice of `len` zeroed elements
+    fn try_new_zeroed_slice(len: usize) -> Result<Box<[MaybeUninit<T>]>, TryReserveError>;
+    /// try creating a new boxed slice of `len` uninitialized elements
+    fn try_new_uninit_slice(len: usize) -> Result<Box<[MaybeUninit<T>]>, TryReserveError>;
 }
+
+/// trait to implement Fallible Box of str
+pub trait FallibleBoxStr {
+    /// try creating a new boxed str by copying `s`
+    fn try_from_str(s: &str) -> Result<Self, TryReserveError>
+    where
+        Self: Sized;
+}
+
 /// TryBox is a thin wrapper around alloc::boxed::Box to provide support for
 /// fallible allocation.
 ///
 /// See the crate documentation for more.
-pub struct TryBox<T> {
+///
+/// The value is stored in the allocator `A`. The box can only be created in
+/// another allocator than `Global` with the `unstable` feature, as `Box`
+/// takes an allocator parameter only on nightly.
+pub struct TryBox<T: ?Sized, A: Allocator = Global> {
+    #[cfg(feature = "unstable")]
+    inner: Box<T, A>,
+    #[cfg(not(feature = "unstable"))]
     inner: Box<T>,
+    #[cfg(not(feature = "unstable"))]
+    alloc: PhantomData<A>,
 }
 
 impl<T> TryBox<T> {
     #[inline]
     pub fn try_new(t: T) -> Result<Self, TryReserveError> {
-        Ok(Self {
-            inner: <Box<T> as FallibleBox<T>>::try_new(t)?,
-        })
+        Ok(Self::from(<Box<T> as FallibleBox<T>>::try_new(t)?))
     }
 
+    /// try creating a new box, giving back `t` if allocation failed
+    #[inline]
+    pub fn try_new_give_back(t: T) -> Result<Self, AllocError<T>> {
+        Ok(Self::from(<Box<T> as FallibleBox<T>>::try_new_give_back(
+            t,
+        )?))
+    }
+
+    /// try creating a new box with uninitialized contents
+    #[inline]
+    pub fn try_new_uninit() -> Result<TryBox<MaybeUninit<T>>, TryReserveError> {
+        TryBox::try_new(MaybeUninit::uninit())
+    }
+
+    /// try creating a new pinned box, `T` can't be moved out of it
+    #[inline]
+    pub fn try_pin(t: T) -> Result<Pin<Self>, TryReserveError> {
+        Ok(Self::into_pin(Self::try_new(t)?))
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl<T, A: Allocator> TryBox<T, A> {
+    /// try creating a new box allocated in `alloc`
+    #[inline]
+    pub fn try_new_in(t: T, alloc: A) -> Result<Self, TryReserveError> {
+        match Box::try_new_in(t, alloc) {
+            Ok(inner) => Ok(Self { inner }),
+            Err(_) => Err(make_alloc_error(Layout::new::<T>())),
+        }
+    }
+
+    /// Returns a reference to the allocator of the box.
+    #[inline(always)]
+    pub fn allocator(b: &Self) -> &A {
+        Box::allocator(&b.inner)
+    }
+}
+
+impl<T, A: Allocator> TryBox<T, A> {
+    /// Consumes the box, returning the wrapped value
+    #[inline(always)]
+    pub fn into_inner(b: Self) -> T {
+        *b.inner
+    }
+}
+
+impl<T> TryBox<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// See std::boxed::Box::assume_init
+    #[inline(always)]
+    pub unsafe fn assume_init(self) -> TryBox<T> {
+        TryBox::from_raw(TryBox::into_raw(self) as *mut T)
+    }
+
+    /// Writes the value and converts to `TryBox<T>`
+    #[inline]
+    pub fn write(mut b: Self, value: T) -> TryBox<T> {
+        b.inner.write(value);
+        unsafe { b.assume_init() }
+    }
+}
+
+impl<T> TryBox<[T]> {
+    /// see FallibleBoxSlice::try_new_slice_from
+    #[inline]
+    pub fn try_new_slice_from(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: Copy,
+    {
+        Ok(Self::from(
+            <Box<[T]> as FallibleBoxSlice<T>>::try_new_slice_from(slice)?,
+        ))
+    }
+
+    /// see FallibleBoxSlice::try_new_slice_from_no_copy
+    #[inline]
+    pub fn try_new_slice_from_no_copy(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: TryClone,
+    {
+        Ok(Self::from(
+            <Box<[T]> as FallibleBoxSlice<T>>::try_new_slice_from_no_copy(slice)?,
+        ))
+    }
+
+    /// see FallibleBoxSlice::try_new_uninit_slice
+    #[inline]
+    pub fn try_new_uninit_slice(len: usize) -> Result<TryBox<[MaybeUninit<T>]>, TryReserveError> {
+        Ok(TryBox::from(
+            <Box<[T]> as FallibleBoxSlice<T>>::try_new_uninit_slice(len)?,
+        ))
+    }
+
+    /// see FallibleBoxSlice::try_new_zeroed_slice
+    #[inline]
+    pub fn try_new_zeroed_slice(len: usize) -> Result<TryBox<[MaybeUninit<T>]>, TryReserveError> {
+        Ok(TryBox::from(
+            <Box<[T]> as FallibleBoxSlice<T>>::try_new_zeroed_slice(len)?,
+        ))
+    }
+}
+
+impl<T> TryBox<[MaybeUninit<T>]> {
+    /// # Safety
+    ///
+    /// See std::boxed::Box::assume_init
+    #[inline(always)]
+    pub unsafe fn assume_init(self) -> TryBox<[T]> {
+        TryBox::from(assume_init_slice(self.inner))
+    }
+}
+
+impl TryBox<str> {
+    /// see FallibleBoxStr::try_from_str
+    #[inline]
+    pub fn try_from_str(s: &str) -> Result<Self, TryReserveError> {
+        Ok(Self::from(<Box<str> as FallibleBoxStr>::try_from_str(s)?))
+    }
+}
+
+impl<T: ?Sized> TryBox<T> {
     #[inline(always)]
     pub fn into_raw(b: TryBox<T>) -> *mut T {
         Box::into_raw(b.inner)
@@ -40,20 +211,102 @@ impl<T> TryBox<T> {
     /// See std::boxed::from_raw
     #[inline(always)]
     pub unsafe fn from_raw(raw: *mut T) -> Self {
-        Self {
-            inner: Box::from_raw(raw),
-        }
+        Self::from(Box::from_raw(raw))
+    }
+
+    /// Converts the box into a `Box`, this does not allocate
+    #[inline(always)]
+    pub fn into_box(b: Self) -> Box<T> {
+        b.inner
+    }
+
+    /// Converts the box into a box of an unsized type, eg a slice or a
+    /// trait object. As unsizing coercions are unstable on custom types,
+    /// the coercion is done on the inner `Box` by `f`, which must not
+    /// allocate:
+    ///
+    /// ```
+    /// use fallible_collections::TryBox;
+    /// use core::fmt::Debug;
+    ///
+    /// let b = TryBox::try_new(5).unwrap();
+    /// let b: TryBox<dyn Debug> = TryBox::unsize(b, |b| b as Box<_>);
+    /// let b = TryBox::try_new([1, 2, 3]).unwrap();
+    /// let b: TryBox<[i32]> = TryBox::unsize(b, |b| b as Box<_>);
+    /// assert_eq!(b.len(), 3);
+    /// ```
+    #[inline(always)]
+    pub fn unsize<U: ?Sized, F>(b: Self, f: F) -> TryBox<U>
+    where
+        F: FnOnce(Box<T>) -> Box<U>,
+    {
+        TryBox::from(f(b.inner))
+    }
+}
+
+impl<T: ?Sized, A: Allocator> TryBox<T, A> {
+    /// Consumes and leaks the box, see std::boxed::Box::leak
+    #[inline(always)]
+    pub fn leak<'a>(b: Self) -> &'a mut T
+    where
+        T: 'a,
+        A: 'a,
+    {
+        Box::leak(b.inner)
+    }
+
+    /// Converts the box into a `Pin<TryBox<T>>`, this does not allocate
+    #[inline(always)]
+    pub fn into_pin(b: Self) -> Pin<Self> {
+        // it's not possible to move or replace the insides of a
+        // `Pin<TryBox<T>>` when `T: !Unpin`, as for Box
+        unsafe { Pin::new_unchecked(b) }
     }
 }
 
-impl<T: TryClone> TryClone for TryBox<T> {
+impl<T: TryClone, A: Allocator + Clone> TryClone for TryBox<T, A> {
     fn try_clone(&self) -> Result<Self, TryReserveError> {
         let clone: T = (*self.inner).try_clone()?;
-        Self::try_new(clone)
+        #[cfg(feature = "unstable")]
+        {
+            Self::try_new_in(clone, Self::allocator(self).clone())
+        }
+        #[cfg(not(feature = "unstable"))]
+        {
+            Ok(Self {
+                inner: <Box<T> as FallibleBox<T>>::try_new(clone)?,
+                alloc: PhantomData,
+            })
+        }
+    }
+}
+
+impl<T: TryClone> TryClone for TryBox<[T]> {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Self::try_new_slice_from_no_copy(self)
+    }
+}
+
+impl TryClone for TryBox<str> {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Self::try_from_str(self)
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for TryBox<T> {
+    #[inline(always)]
+    fn from(inner: Box<T>) -> Self {
+        Self {
+            inner,
+            #[cfg(not(feature = "unstable"))]
+            alloc: PhantomData,
+        }
     }
 }
 
-impl<T> Deref for TryBox<T> {
+impl<T: ?Sized, A: Allocator> Deref for TryBox<T, A> {
     type Target = T;
 
     #[inline(always)]
@@ -62,15 +315,188 @@ impl<T> Deref for TryBox<T> {
     }
 }
 
+impl<T: ?Sized, A: Allocator> DerefMut for TryBox<T, A> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.deref_mut()
+    }
+}
+
+impl<T: ?Sized, A: Allocator> AsRef<T> for TryBox<T, A> {
+    #[inline(always)]
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized, A: Allocator> AsMut<T> for TryBox<T, A> {
+    #[inline(always)]
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Borrow<T> for TryBox<T, A> {
+    #[inline(always)]
+    fn borrow(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized, A: Allocator> BorrowMut<T> for TryBox<T, A> {
+    #[inline(always)]
+    fn borrow_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, A: Allocator> fmt::Debug for TryBox<T, A> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display, A: Allocator> fmt::Display for TryBox<T, A> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ?Sized + PartialEq, A: Allocator> PartialEq for TryBox<T, A> {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.eq(&other.inner)
+    }
+}
+
+impl<T: ?Sized + Eq, A: Allocator> Eq for TryBox<T, A> {}
+
+impl<T: ?Sized + PartialOrd, A: Allocator> PartialOrd for TryBox<T, A> {
+    #[inline(always)]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.inner.partial_cmp(&other.inner)
+    }
+}
+
+impl<T: ?Sized + Ord, A: Allocator> Ord for TryBox<T, A> {
+    #[inline(always)]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.inner.cmp(&other.inner)
+    }
+}
+
+impl<T: ?Sized + Hash, A: Allocator> Hash for TryBox<T, A> {
+    #[inline(always)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.hash(state)
+    }
+}
+
+/// Allocate `layout` with the global allocator as Box does, returning a
+/// dangling pointer for zero sized layouts.
+fn try_alloc<T>(layout: Layout, zeroed: bool) -> Result<NonNull<T>, TryReserveError> {
+    if layout.size() == 0 {
+        return Ok(NonNull::dangling());
+    }
+    let ptr = unsafe {
+        if zeroed {
+            alloc_zeroed(layout)
+        } else {
+            alloc(layout)
+        }
+    };
+    NonNull::new(ptr as *mut T).ok_or_else(|| make_alloc_error(layout))
+}
+
+fn try_new_slice<T>(len: usize, zeroed: bool) -> Result<Box<[MaybeUninit<T>]>, TryReserveError> {
+    let layout = Layout::array::<T>(len).map_err(|_| make_try_reserve_error())?;
+    let ptr = try_alloc::<MaybeUninit<T>>(layout, zeroed)?;
+    Ok(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len)) })
+}
+
+/// # Safety
+///
+/// all the elements of the slice must be initialized
+unsafe fn assume_init_slice<T>(b: Box<[MaybeUninit<T>]>) -> Box<[T]> {
+    Box::from_raw(Box::into_raw(b) as *mut [T])
+}
+
+/// Drop the initialized prefix of a slice on error
+struct InitGuard<'a, T> {
+    slice: &'a mut [MaybeUninit<T>],
+    init: usize,
+}
+
+impl<T> Drop for InitGuard<'_, T> {
+    fn drop(&mut self) {
+        for elem in &mut self.slice[..self.init] {
+            unsafe { ptr::drop_in_place(elem.as_mut_ptr()) }
+        }
+    }
+}
+
 impl<T> FallibleBox<T> for Box<T> {
     fn try_new(t: T) -> Result<Self, TryReserveError> {
-        let mut vec = alloc::vec::Vec::new();
-        vec.try_reserve_exact(1)?;
-        vec.push(t);
-        // try_reserve_exact doesn't promise the exact size, but into_boxed_slice does.
-        // in practice the size is going to be okay anyway, so it won't realloc.
-        let ptr: *mut T = ManuallyDrop::new(vec.into_boxed_slice()).as_mut_ptr();
-        Ok(unsafe { Box::from_raw(ptr) })
+        Self::try_new_give_back(t).map_err(TryReserveError::from)
+    }
+
+    fn try_new_give_back(t: T) -> Result<Self, AllocError<T>> {
+        match try_alloc::<T>(Layout::new::<T>(), false) {
+            Ok(ptr) => unsafe {
+                ptr::write(ptr.as_ptr(), t);
+                Ok(Box::from_raw(ptr.as_ptr()))
+            },
+            Err(e) => Err(AllocError::new(t, e)),
+        }
+    }
+}
+
+impl<T> FallibleBoxSlice<T> for Box<[T]> {
+    fn try_new_slice_from(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: Copy,
+    {
+        let mut b = try_new_slice::<T>(slice.len(), false)?;
+        unsafe {
+            ptr::copy_nonoverlapping(slice.as_ptr(), b.as_mut_ptr() as *mut T, slice.len());
+            Ok(assume_init_slice(b))
+        }
+    }
+
+    fn try_new_slice_from_no_copy(slice: &[T]) -> Result<Self, TryReserveError>
+    where
+        T: TryClone,
+    {
+        let mut b = try_new_slice::<T>(slice.len(), false)?;
+        let mut guard = InitGuard {
+            slice: &mut b,
+            init: 0,
+        };
+        for elem in slice {
+            guard.slice[guard.init] = MaybeUninit::new(elem.try_clone()?);
+            guard.init += 1;
+        }
+        core::mem::forget(guard);
+        Ok(unsafe { assume_init_slice(b) })
+    }
+
+    #[inline]
+    fn try_new_zeroed_slice(len: usize) -> Result<Box<[MaybeUninit<T>]>, TryReserveError> {
+        try_new_slice(len, true)
+    }
+
+    #[inline]
+    fn try_new_uninit_slice(len: usize) -> Result<Box<[MaybeUninit<T>]>, TryReserveError> {
+        try_new_slice(len, false)
+    }
+}
+
+impl FallibleBoxStr for Box<str> {
+    fn try_from_str(s: &str) -> Result<Self, TryReserveError> {
+        let b = <Box<[u8]> as FallibleBoxSlice<u8>>::try_new_slice_from(s.as_bytes())?;
+        Ok(unsafe { alloc::str::from_boxed_utf8_unchecked(b) })
     }
 }
 
@@ -98,12 +524,125 @@ mod tests {
     //     assert!(ptr.is_null());
     // }
 
+    #[test]
+    fn boxed_give_back() {
+        let b = <Box<_> as FallibleBox<_>>::try_new_give_back(String::from("foo")).unwrap();
+        assert_eq!(*b, "foo");
+        let b = TryBox::try_new_give_back(vec![1, 2]).unwrap();
+        assert_eq!(*b, [1, 2]);
+    }
+
     #[test]
     fn trybox_zst() {
         let b = <Box<_> as FallibleBox<_>>::try_new(()).expect("ok");
         assert_eq!(b, Box::new(()));
     }
 
+    #[test]
+    fn boxed_slice() {
+        let b = <Box<[_]> as FallibleBoxSlice<_>>::try_new_slice_from(&[1, 2, 3]).unwrap();
+        assert_eq!(&*b, &[1, 2, 3]);
+        let v = vec![vec![1], vec![2, 3]];
+        let b = <Box<[_]> as FallibleBoxSlice<_>>::try_new_slice_from_no_copy(&v).unwrap();
+        assert_eq!(&*b, &v[..]);
+        let b = <Box<[()]> as FallibleBoxSlice<_>>::try_new_slice_from(&[(); 8]).unwrap();
+        assert_eq!(b.len(), 8);
+        let b = <Box<[u8]> as FallibleBoxSlice<_>>::try_new_slice_from(&[]).unwrap();
+        assert!(b.is_empty());
+    }
+
+    #[test]
+    fn boxed_zeroed_slice() {
+        let b = <Box<[u64]> as FallibleBoxSlice<_>>::try_new_zeroed_slice(16).unwrap();
+        let b = unsafe { assume_init_slice(b) };
+        assert_eq!(&*b, &[0; 16]);
+        let mut b = <Box<[u64]> as FallibleBoxSlice<_>>::try_new_uninit_slice(2).unwrap();
+        b[0].write(1);
+        b[1].write(2);
+        assert_eq!(&*unsafe { assume_init_slice(b) }, &[1, 2]);
+    }
+
+    #[test]
+    fn boxed_slice_oom() {
+        assert!(<Box<[u64]> as FallibleBoxSlice<_>>::try_new_uninit_slice(usize::MAX).is_err());
+        assert!(
+            <Box<[u8]> as FallibleBoxSlice<_>>::try_new_zeroed_slice(isize::MAX as usize).is_err()
+        );
+    }
+
+    #[test]
+    fn boxed_slice_try_clone_error() {
+        use std::rc::Rc;
+        struct FailAt(usize, Rc<()>);
+        impl TryClone for FailAt {
+            fn try_clone(&self) -> Result<Self, TryReserveError> {
+                if self.0 == 2 {
+                    Err(make_try_reserve_error())
+                } else {
+                    Ok(FailAt(self.0, self.1.clone()))
+                }
+            }
+        }
+        let rc = Rc::new(());
+        let v: Vec<_> = (0..4).map(|i| FailAt(i, rc.clone())).collect();
+        assert!(<Box<[_]> as FallibleBoxSlice<_>>::try_new_slice_from_no_copy(&v).is_err());
+        // the two clones which succeeded have been dropped
+        assert_eq!(Rc::strong_count(&rc), 5);
+    }
+
+    #[test]
+    fn boxed_str() {
+        let b = <Box<str> as FallibleBoxStr>::try_from_str("foo").unwrap();
+        assert_eq!(&*b, "foo");
+    }
+
+    #[test]
+    fn trybox_deref_mut() {
+        let mut b = TryBox::try_new(5).unwrap();
+        *b = 3;
+        assert_eq!(*b, 3);
+        assert_eq!(TryBox::into_inner(b), 3);
+    }
+
+    #[test]
+    fn trybox_uninit() {
+        let b = TryBox::<u32>::try_new_uninit().unwrap();
+        let b = TryBox::write(b, 5);
+        assert_eq!(b, TryBox::try_new(5).unwrap());
+        let mut b = TryBox::<[u32]>::try_new_uninit_slice(2).unwrap();
+        b[0].write(1);
+        b[1].write(2);
+        let b = unsafe { b.assume_init() };
+        assert_eq!(&*b, &[1, 2]);
+        assert_eq!(b.try_clone().unwrap(), b);
+    }
+
+    #[test]
+    fn trybox_unsized() {
+        use core::fmt::Debug;
+        let b = TryBox::try_new(vec![1]).unwrap();
+        let b: TryBox<dyn Debug> = TryBox::unsize(b, |b| b as Box<_>);
+        assert_eq!(format!("{:?}", b), "[1]");
+        let b = TryBox::try_from_str("foo").unwrap();
+        assert_eq!(b.try_clone().unwrap(), b);
+        assert_eq!(&*TryBox::into_box(b), "foo");
+    }
+
+    #[test]
+    fn trybox_pin() {
+        let mut b = TryBox::try_pin(5).unwrap();
+        *b.as_mut() = 3;
+        assert_eq!(*b, 3);
+    }
+
+    #[test]
+    fn trybox_leak() {
+        let b = TryBox::try_new(5).unwrap();
+        let r: &'static mut i32 = TryBox::leak(b);
+        *r = 3;
+        unsafe { drop(TryBox::from_raw(r)) };
+    }
+
     struct NonCopyType;
 
     #[test]
@@ -118,4 +657,20 @@ mod tests {
             Some(TryBox::try_new(NonCopyType).unwrap());
         let _ref_option: Option<&NonCopyType> = try_box_option.as_deref();
     }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn trybox_custom_allocator() {
+        use crate::allocator::tests::Bounded;
+
+        let alloc = Bounded::new(1);
+        let b = TryBox::try_new_in(7u64, &alloc).unwrap();
+        assert_eq!(alloc.live(), 1);
+        assert!(TryBox::try_new_in(5, &alloc).is_err());
+        assert!(b.try_clone().is_err());
+        assert_eq!(TryBox::into_inner(b), 7);
+        assert_eq!(alloc.live(), 0);
+        let b = TryBox::try_new_in(5, &alloc).unwrap();
+        assert_eq!(*b, 5);
+    }
 }
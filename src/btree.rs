@@ -1,5 +1,5 @@
 //! Implement Fallible Btree, As there is no try_reserve methods on btree, I add no choice but to fork the std implementation and change return types.
-//! Currently this functionality is only available when building this crate with nightly and the `unstable` feature.
+//! This functionality is only available when building this crate with the `btree` feature.
 pub mod map;
 pub use map::BTreeMap;
 
@@ -1,13 +1,14 @@
-use crate::TryReserveError;
+use crate::allocator::{Allocator, Global};
+use crate::{AllocError, CollectionKind, ReserveError, TryFromIterator, TryReserveError};
 use core::borrow::Borrow;
 use core::cmp::Ordering;
 use core::fmt::Debug;
 use core::hash::{Hash, Hasher};
-use core::iter::{FromIterator, FusedIterator, Peekable};
+use core::iter::{FusedIterator, Peekable};
 use core::marker::PhantomData;
-use core::ops::Bound::{Excluded, Included, Unbounded};
+use core::ops::Bound::{self, Excluded, Included, Unbounded};
 use core::ops::{Index, RangeBounds};
-use core::{fmt, intrinsics, mem, ptr};
+use core::{fmt, hint, mem, ptr};
 
 use super::node::{self, marker, ForceResult::*, Handle, InsertResult::*, NodeRef};
 use super::search::{self, SearchResult::*};
@@ -120,37 +121,47 @@ use UnderflowResult::*;
 /// let stat = player_stats.entry("attack").or_insert(100);
 /// *stat += random_stat_buff();
 /// ```
-
-pub struct BTreeMap<K, V> {
+///
+/// The nodes of the tree are allocated in the allocator `A`, see
+/// [`new_in`](#method.new_in).
+pub struct BTreeMap<K, V, A: Allocator = Global> {
     root: node::Root<K, V>,
     length: usize,
+    // Free nodes reserved for the insertions, see `try_reserve`, and the allocator.
+    pool: node::NodePool<K, V, A>,
 }
 
-unsafe impl<#[may_dangle] K, #[may_dangle] V> Drop for BTreeMap<K, V> {
+impl<K, V, A: Allocator> Drop for BTreeMap<K, V, A> {
     fn drop(&mut self) {
-        unsafe {
-            drop(ptr::read(self).into_iter());
-        }
+        // The entries and the nodes of the tree are freed by an iterator borrowing the
+        // allocator, and the free nodes by the drop glue of the pool.
+        let root = mem::replace(&mut self.root, node::Root::shared_empty_root());
+        drop(IntoIter::new(root, self.length, self.pool.allocator()));
     }
 }
 
 use crate::TryClone;
 
-impl<K: TryClone, V: TryClone> TryClone for BTreeMap<K, V> {
-    fn try_clone(&self) -> Result<BTreeMap<K, V>, TryReserveError> {
-        fn clone_subtree<'a, K: TryClone, V: TryClone>(
+impl<K: TryClone, V: TryClone, A: Allocator + Clone> TryClone for BTreeMap<K, V, A> {
+    fn try_clone(&self) -> Result<BTreeMap<K, V, A>, TryReserveError> {
+        fn clone_subtree<'a, K, V, A>(
             node: node::NodeRef<marker::Immut<'a>, K, V, marker::LeafOrInternal>,
-        ) -> Result<BTreeMap<K, V>, TryReserveError>
+            alloc: &A,
+        ) -> Result<BTreeMap<K, V, A>, TryReserveError>
         where
-            K: 'a,
-            V: 'a,
+            K: TryClone + 'a,
+            V: TryClone + 'a,
+            A: Allocator + Clone,
         {
             match node.force() {
                 Leaf(leaf) => {
                     let mut out_tree = BTreeMap {
-                        root: node::Root::new_leaf()?,
+                        root: node::Root::shared_empty_root(),
                         length: 0,
+                        pool: node::NodePool::new_in(alloc.clone()),
                     };
+                    out_tree.pool.try_reserve(1, 0)?;
+                    out_tree.root = node::Root::new_leaf_from(&mut out_tree.pool);
 
                     {
                         let mut out_node = match out_tree.root.as_mut().force() {
@@ -171,10 +182,11 @@ impl<K: TryClone, V: TryClone> TryClone for BTreeMap<K, V> {
                     Ok(out_tree)
                 }
                 Internal(internal) => {
-                    let mut out_tree = clone_subtree(internal.first_edge().descend())?;
+                    let mut out_tree = clone_subtree(internal.first_edge().descend(), alloc)?;
+                    out_tree.pool.try_reserve(0, 1)?;
 
                     {
-                        let mut out_node = out_tree.root.push_level()?;
+                        let mut out_node = out_tree.root.push_level_from(&mut out_tree.pool);
                         let mut in_edge = internal.first_edge();
                         while let Ok(kv) = in_edge.right_kv() {
                             let (k, v) = kv.into_kv();
@@ -182,13 +194,14 @@ impl<K: TryClone, V: TryClone> TryClone for BTreeMap<K, V> {
 
                             let k = (*k).try_clone()?;
                             let v = (*v).try_clone()?;
-                            let subtree = clone_subtree(in_edge.descend())?;
+                            let subtree = clone_subtree(in_edge.descend(), alloc)?;
 
                             // We can't destructure subtree directly
                             // because BTreeMap implements Drop
                             let (subroot, sublength) = unsafe {
                                 let root = ptr::read(&subtree.root);
                                 let length = subtree.length;
+                                drop(ptr::read(&subtree.pool));
                                 mem::forget(subtree);
                                 (root, length)
                             };
@@ -203,100 +216,21 @@ impl<K: TryClone, V: TryClone> TryClone for BTreeMap<K, V> {
             }
         }
 
-        if self.len() == 0 {
+        if self.is_empty() {
             // Ideally we'd call `BTreeMap::new` here, but that has the `K:
             // Ord` constraint, which this method lacks.
             Ok(BTreeMap {
                 root: node::Root::shared_empty_root(),
                 length: 0,
+                pool: node::NodePool::new_in(self.pool.allocator().clone()),
             })
         } else {
-            clone_subtree(self.root.as_ref())
+            clone_subtree(self.root.as_ref(), self.pool.allocator())
         }
     }
 }
 
-impl<K: Clone, V: Clone> Clone for BTreeMap<K, V> {
-    fn clone(&self) -> BTreeMap<K, V> {
-        fn clone_subtree<'a, K: Clone, V: Clone>(
-            node: node::NodeRef<marker::Immut<'a>, K, V, marker::LeafOrInternal>,
-        ) -> BTreeMap<K, V>
-        where
-            K: 'a,
-            V: 'a,
-        {
-            match node.force() {
-                Leaf(leaf) => {
-                    let mut out_tree = BTreeMap {
-                        root: node::Root::new_leaf().expect("Out of Mem"),
-                        length: 0,
-                    };
-
-                    {
-                        let mut out_node = match out_tree.root.as_mut().force() {
-                            Leaf(leaf) => leaf,
-                            Internal(_) => unreachable!(),
-                        };
-
-                        let mut in_edge = leaf.first_edge();
-                        while let Ok(kv) = in_edge.right_kv() {
-                            let (k, v) = kv.into_kv();
-                            in_edge = kv.right_edge();
-
-                            out_node.push(k.clone(), v.clone());
-                            out_tree.length += 1;
-                        }
-                    }
-
-                    out_tree
-                }
-                Internal(internal) => {
-                    let mut out_tree = clone_subtree(internal.first_edge().descend());
-
-                    {
-                        let mut out_node = out_tree.root.push_level().expect("Out of Mem");
-                        let mut in_edge = internal.first_edge();
-                        while let Ok(kv) = in_edge.right_kv() {
-                            let (k, v) = kv.into_kv();
-                            in_edge = kv.right_edge();
-
-                            let k = (*k).clone();
-                            let v = (*v).clone();
-                            let subtree = clone_subtree(in_edge.descend());
-
-                            // We can't destructure subtree directly
-                            // because BTreeMap implements Drop
-                            let (subroot, sublength) = unsafe {
-                                let root = ptr::read(&subtree.root);
-                                let length = subtree.length;
-                                mem::forget(subtree);
-                                (root, length)
-                            };
-
-                            out_node.push(k, v, subroot);
-                            out_tree.length += 1 + sublength;
-                        }
-                    }
-
-                    out_tree
-                }
-            }
-        }
-
-        if self.len() == 0 {
-            // Ideally we'd call `BTreeMap::new` here, but that has the `K:
-            // Ord` constraint, which this method lacks.
-            BTreeMap {
-                root: node::Root::shared_empty_root(),
-                length: 0,
-            }
-        } else {
-            clone_subtree(self.root.as_ref())
-        }
-    }
-}
-
-impl<K, Q: ?Sized> super::Recover<Q> for BTreeMap<K, ()>
+impl<K, Q: ?Sized, A: Allocator + Clone> super::Recover<Q> for BTreeMap<K, (), A>
 where
     K: Borrow<Q> + Ord,
     Q: Ord,
@@ -316,6 +250,7 @@ where
                 OccupiedEntry {
                     handle,
                     length: &mut self.length,
+                    pool: &mut self.pool,
                     _marker: PhantomData,
                 }
                 .remove_kv()
@@ -334,6 +269,7 @@ where
                     key,
                     handle,
                     length: &mut self.length,
+                    pool: &mut self.pool,
                     _marker: PhantomData,
                 }
                 .try_insert(())?;
@@ -350,7 +286,6 @@ where
 ///
 /// [`iter`]: struct.BTreeMap.html#method.iter
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 pub struct Iter<'a, K: 'a, V: 'a> {
     range: Range<'a, K, V>,
     length: usize,
@@ -369,7 +304,6 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Iter<'_, K, V> {
 ///
 /// [`iter_mut`]: struct.BTreeMap.html#method.iter_mut
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 #[derive(Debug)]
 pub struct IterMut<'a, K: 'a, V: 'a> {
     range: RangeMut<'a, K, V>,
@@ -383,14 +317,14 @@ pub struct IterMut<'a, K: 'a, V: 'a> {
 ///
 /// [`into_iter`]: struct.BTreeMap.html#method.into_iter
 /// [`BTreeMap`]: struct.BTreeMap.html
-
-pub struct IntoIter<K, V> {
+pub struct IntoIter<K, V, A: Allocator = Global> {
     front: Handle<NodeRef<marker::Owned, K, V, marker::Leaf>, marker::Edge>,
     back: Handle<NodeRef<marker::Owned, K, V, marker::Leaf>, marker::Edge>,
     length: usize,
+    alloc: A,
 }
 
-impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntoIter<K, V> {
+impl<K: fmt::Debug, V: fmt::Debug, A: Allocator> fmt::Debug for IntoIter<K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let range = Range {
             front: self.front.reborrow(),
@@ -407,7 +341,6 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for IntoIter<K, V> {
 ///
 /// [`keys`]: struct.BTreeMap.html#method.keys
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 pub struct Keys<'a, K: 'a, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -425,7 +358,6 @@ impl<K: fmt::Debug, V> fmt::Debug for Keys<'_, K, V> {
 ///
 /// [`values`]: struct.BTreeMap.html#method.values
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 pub struct Values<'a, K: 'a, V: 'a> {
     inner: Iter<'a, K, V>,
 }
@@ -443,7 +375,6 @@ impl<K, V: fmt::Debug> fmt::Debug for Values<'_, K, V> {
 ///
 /// [`values_mut`]: struct.BTreeMap.html#method.values_mut
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 #[derive(Debug)]
 pub struct ValuesMut<'a, K: 'a, V: 'a> {
     inner: IterMut<'a, K, V>,
@@ -456,7 +387,6 @@ pub struct ValuesMut<'a, K: 'a, V: 'a> {
 ///
 /// [`range`]: struct.BTreeMap.html#method.range
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 pub struct Range<'a, K: 'a, V: 'a> {
     front: Handle<NodeRef<marker::Immut<'a>, K, V, marker::Leaf>, marker::Edge>,
     back: Handle<NodeRef<marker::Immut<'a>, K, V, marker::Leaf>, marker::Edge>,
@@ -475,7 +405,6 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Range<'_, K, V> {
 ///
 /// [`range_mut`]: struct.BTreeMap.html#method.range_mut
 /// [`BTreeMap`]: struct.BTreeMap.html
-
 pub struct RangeMut<'a, K: 'a, V: 'a> {
     front: Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge>,
     back: Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge>,
@@ -500,16 +429,15 @@ impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for RangeMut<'_, K, V> {
 ///
 /// [`BTreeMap`]: struct.BTreeMap.html
 /// [`entry`]: struct.BTreeMap.html#method.entry
-
-pub enum Entry<'a, K: 'a, V: 'a> {
+pub enum Entry<'a, K: 'a, V: 'a, A: Allocator = Global> {
     /// A vacant entry.
-    Vacant(VacantEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V, A>),
 
     /// An occupied entry.
-    Occupied(OccupiedEntry<'a, K, V>),
+    Occupied(OccupiedEntry<'a, K, V, A>),
 }
 
-impl<K: Debug + Ord, V: Debug> Debug for Entry<'_, K, V> {
+impl<K: Debug + Ord, V: Debug, A: Allocator> Debug for Entry<'_, K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Vacant(ref v) => f.debug_tuple("Entry").field(v).finish(),
@@ -522,17 +450,17 @@ impl<K: Debug + Ord, V: Debug> Debug for Entry<'_, K, V> {
 /// It is part of the [`Entry`] enum.
 ///
 /// [`Entry`]: enum.Entry.html
-
-pub struct VacantEntry<'a, K: 'a, V: 'a> {
+pub struct VacantEntry<'a, K: 'a, V: 'a, A: Allocator = Global> {
     key: K,
     handle: Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge>,
     length: &'a mut usize,
+    pool: &'a mut node::NodePool<K, V, A>,
 
     // Be invariant in `K` and `V`
     _marker: PhantomData<&'a mut (K, V)>,
 }
 
-impl<K: Debug + Ord, V> Debug for VacantEntry<'_, K, V> {
+impl<K: Debug + Ord, V, A: Allocator> Debug for VacantEntry<'_, K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("VacantEntry").field(self.key()).finish()
     }
@@ -542,17 +470,17 @@ impl<K: Debug + Ord, V> Debug for VacantEntry<'_, K, V> {
 /// It is part of the [`Entry`] enum.
 ///
 /// [`Entry`]: enum.Entry.html
-
-pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, A: Allocator = Global> {
     handle: Handle<NodeRef<marker::Mut<'a>, K, V, marker::LeafOrInternal>, marker::KV>,
 
     length: &'a mut usize,
+    pool: &'a mut node::NodePool<K, V, A>,
 
     // Be invariant in `K` and `V`
     _marker: PhantomData<&'a mut (K, V)>,
 }
 
-impl<K: Debug + Ord, V: Debug> Debug for OccupiedEntry<'_, K, V> {
+impl<K: Debug + Ord, V: Debug, A: Allocator> Debug for OccupiedEntry<'_, K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("OccupiedEntry")
             .field("key", self.key())
@@ -582,11 +510,62 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// // entries can now be inserted into the empty map
     /// map.insert(1, "a");
     /// ```
-
     pub fn new() -> BTreeMap<K, V> {
+        BTreeMap::new_in(Global)
+    }
+
+    /// Builds a map from an iterator of key-value pairs sorted by key, in linear
+    /// time. The tree is built bottom-up without searching it, which is much
+    /// faster than inserting the pairs one by one.
+    ///
+    /// If several pairs have equal keys, the value of the last one is kept.
+    /// If an allocation fails, the pairs already taken from `iter` and all the
+    /// nodes built so far are freed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys of `iter` are not in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let map = BTreeMap::try_from_sorted_iter((0..1000).map(|i| (i, i * 2))).unwrap();
+    /// assert_eq!(map.len(), 1000);
+    /// assert_eq!(map[&500], 1000);
+    ///
+    /// let map = BTreeMap::try_from_sorted_iter(vec![(1, "a"), (2, "b"), (2, "c")]).unwrap();
+    /// assert_eq!(map.len(), 2);
+    /// assert_eq!(map[&2], "c");
+    /// ```
+    pub fn try_from_sorted_iter<I>(iter: I) -> Result<Self, TryReserveError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        Self::try_from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<K: Ord, V, A: Allocator + Clone> BTreeMap<K, V, A> {
+    /// Makes a new empty BTreeMap whose nodes are allocated in `alloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "unstable", feature(allocator_api))]
+    /// use fallible_collections::allocator::Global;
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new_in(Global);
+    /// map.try_insert(1, "a").unwrap();
+    /// assert_eq!(map[&1], "a");
+    /// ```
+    pub fn new_in(alloc: A) -> BTreeMap<K, V, A> {
         BTreeMap {
             root: node::Root::shared_empty_root(),
             length: 0,
+            pool: node::NodePool::new_in(alloc),
         }
     }
 
@@ -604,9 +583,80 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// a.clear();
     /// assert!(a.is_empty());
     /// ```
-
     pub fn clear(&mut self) {
-        *self = BTreeMap::new();
+        let alloc = self.pool.allocator().clone();
+        *self = BTreeMap::new_in(alloc);
+    }
+
+    /// Reserves the nodes needed to insert at least `additional` more entries, so
+    /// that the next `additional` insertions do not allocate. The reserved nodes
+    /// are kept in a pool of the map until `shrink_to_fit` or `clear` is called.
+    /// The nodes freed by removals refill the pool up to the number of nodes
+    /// reserved, and are deallocated beyond it. The entries are left unchanged on
+    /// error, only the pool may have grown (strong guarantee).
+    ///
+    /// This walks all the nodes of the tree, so it is meant to be called ahead of
+    /// a sequence of insertions rather than before each of them.
+    ///
+    /// # Errors
+    ///
+    /// If the number of entries overflows `usize`, or the allocator reports a
+    /// failure, then an error is returned. The nodes allocated before the failure
+    /// are kept in the pool.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_reserve(100).unwrap();
+    /// for i in 0..100 {
+    ///     // no allocation happens here
+    ///     map.try_insert(i, i * 2).unwrap();
+    /// }
+    /// ```
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let len = self
+            .length
+            .checked_add(additional)
+            .ok_or_else(crate::make_try_reserve_error)?;
+        let (max_leaves, max_internals) = max_nodes(len);
+        let (leaves, internals) = self.node_counts();
+        let leaves = max_leaves.saturating_sub(leaves);
+        let internals = max_internals.saturating_sub(internals);
+        self.pool.keep(leaves, internals);
+        self.pool.try_reserve(leaves, internals)
+    }
+
+    /// Reserves the nodes needed by `additional` more entries, as
+    /// `try_reserve`, the error telling the length the map was grown to. The
+    /// nodes allocated before a failure are kept in the pool.
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.length.saturating_add(additional);
+        self.try_reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::BTreeMap)
+                .with_len(len)
+        })
+    }
+
+    /// Releases the free nodes kept in the pool of the map. The nodes freed by
+    /// later removals are deallocated until `try_reserve` is called again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_reserve(100).unwrap();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.shrink_to_fit();
+    /// assert_eq!(map[&1], "a");
+    /// ```
+    pub fn shrink_to_fit(&mut self) {
+        self.pool.release();
     }
 
     /// Returns a reference to the value corresponding to the key.
@@ -626,11 +676,10 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map.get(&1), Some(&"a"));
     /// assert_eq!(map.get(&2), None);
     /// ```
-
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         match search::search_tree(self.root.as_ref(), key) {
             Found(handle) => Some(handle.into_kv().1),
@@ -646,7 +695,6 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// # Examples
     ///
     /// ```
-    /// #![feature(map_get_key_value)]
     /// use std::collections::BTreeMap;
     ///
     /// let mut map = BTreeMap::new();
@@ -654,10 +702,10 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map.get_key_value(&1), Some((&1, &"a")));
     /// assert_eq!(map.get_key_value(&2), None);
     /// ```
-    pub fn get_key_value<Q: ?Sized>(&self, k: &Q) -> Option<(&K, &V)>
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         match search::search_tree(self.root.as_ref(), k) {
             Found(handle) => Some(handle.into_kv()),
@@ -665,6 +713,143 @@ impl<K: Ord, V> BTreeMap<K, V> {
         }
     }
 
+    /// Returns the first key-value pair in the map.
+    /// The key in this pair is the minimum key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// assert_eq!(map.first_key_value(), None);
+    /// map.try_insert(1, "b").unwrap();
+    /// map.try_insert(2, "a").unwrap();
+    /// assert_eq!(map.first_key_value(), Some((&1, &"b")));
+    /// ```
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        next_kv(first_leaf_edge(self.root.as_ref())).map(|kv| kv.into_kv())
+    }
+
+    /// Returns the first entry in the map for in-place manipulation.
+    /// The key of this entry is the minimum key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// if let Some(mut entry) = map.first_entry() {
+    ///     if *entry.key() > 0 {
+    ///         entry.insert("first");
+    ///     }
+    /// }
+    /// assert_eq!(*map.get(&1).unwrap(), "first");
+    /// assert_eq!(*map.get(&2).unwrap(), "b");
+    /// ```
+    pub fn first_entry(&mut self) -> Option<OccupiedEntry<'_, K, V, A>> {
+        let handle = next_kv(first_leaf_edge(self.root.as_mut()))?;
+        Some(OccupiedEntry {
+            handle,
+            length: &mut self.length,
+            pool: &mut self.pool,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Removes and returns the first element in the map.
+    /// The key of this element is the minimum key that was in the map.
+    ///
+    /// # Examples
+    ///
+    /// Draining elements in ascending order, while keeping a usable map each iteration.
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// while let Some((key, _val)) = map.pop_first() {
+    ///     assert!(map.iter().all(|(k, _v)| *k > key));
+    /// }
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.first_entry().map(|entry| entry.remove_entry())
+    }
+
+    /// Returns the last key-value pair in the map.
+    /// The key in this pair is the maximum key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "b").unwrap();
+    /// map.try_insert(2, "a").unwrap();
+    /// assert_eq!(map.last_key_value(), Some((&2, &"a")));
+    /// ```
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        prev_kv(last_leaf_edge(self.root.as_ref())).map(|kv| kv.into_kv())
+    }
+
+    /// Returns the last entry in the map for in-place manipulation.
+    /// The key of this entry is the maximum key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// if let Some(mut entry) = map.last_entry() {
+    ///     if *entry.key() > 0 {
+    ///         entry.insert("last");
+    ///     }
+    /// }
+    /// assert_eq!(*map.get(&1).unwrap(), "a");
+    /// assert_eq!(*map.get(&2).unwrap(), "last");
+    /// ```
+    pub fn last_entry(&mut self) -> Option<OccupiedEntry<'_, K, V, A>> {
+        let handle = prev_kv(last_leaf_edge(self.root.as_mut()))?;
+        Some(OccupiedEntry {
+            handle,
+            length: &mut self.length,
+            pool: &mut self.pool,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Removes and returns the last element in the map.
+    /// The key of this element is the maximum key that was in the map.
+    ///
+    /// # Examples
+    ///
+    /// Draining elements in descending order, while keeping a usable map each iteration.
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// while let Some((key, _val)) = map.pop_last() {
+    ///     assert!(map.iter().all(|(k, _v)| *k < key));
+    /// }
+    /// assert!(map.is_empty());
+    /// ```
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.last_entry().map(|entry| entry.remove_entry())
+    }
+
     /// Returns `true` if the map contains a value for the specified key.
     ///
     /// The key may be any borrowed form of the map's key type, but the ordering
@@ -682,12 +867,11 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map.contains_key(&1), true);
     /// assert_eq!(map.contains_key(&2), false);
     /// ```
-
     #[inline]
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         self.get(key).is_some()
     }
@@ -712,11 +896,10 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map[&1], "b");
     /// ```
     // See `get` for implementation notes, this is basically a copy-paste with mut's added
-
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         match search::search_tree(self.root.as_mut(), key) {
             Found(handle) => Some(handle.into_kv_mut().1),
@@ -724,7 +907,8 @@ impl<K: Ord, V> BTreeMap<K, V> {
         }
     }
 
-    /// Inserts a key-value pair into the map.
+    /// Inserts a key-value pair into the map. The map is left unchanged on
+    /// error (strong guarantee).
     ///
     /// If the map did not have this key present, `None` is returned.
     ///
@@ -750,7 +934,6 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map.insert(37, "c"), Some("b"));
     /// assert_eq!(map[&37], "c");
     /// ```
-
     pub fn try_insert(&mut self, key: K, value: V) -> Result<Option<V>, TryReserveError> {
         match self.try_entry(key)? {
             Occupied(mut entry) => Ok(Some(entry.insert(value))),
@@ -761,6 +944,33 @@ impl<K: Ord, V> BTreeMap<K, V> {
         }
     }
 
+    /// Inserts a key-value pair into the map, giving back the pair if an
+    /// allocation failed. The map is left unchanged in that case (strong
+    /// guarantee).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// assert_eq!(map.try_insert_give_back(37, "a").unwrap(), None);
+    /// assert_eq!(map.try_insert_give_back(37, "b").unwrap(), Some("a"));
+    /// ```
+    pub fn try_insert_give_back(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, AllocError<(K, V)>> {
+        if let Err(e) = self.ensure_root_is_owned() {
+            return Err(AllocError::new((key, value), e));
+        }
+        match self.owned_root_entry(key) {
+            Occupied(mut entry) => Ok(Some(entry.insert(value))),
+            Vacant(entry) => entry.try_insert_give_back(value).map(|_| None),
+        }
+    }
+
     /// Removes a key from the map, returning the value at the key if the key
     /// was previously in the map.
     ///
@@ -779,17 +989,17 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(map.remove(&1), Some("a"));
     /// assert_eq!(map.remove(&1), None);
     /// ```
-
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         match search::search_tree(self.root.as_mut(), key) {
             Found(handle) => Some(
                 OccupiedEntry {
                     handle,
                     length: &mut self.length,
+                    pool: &mut self.pool,
                     _marker: PhantomData,
                 }
                 .remove(),
@@ -800,56 +1010,159 @@ impl<K: Ord, V> BTreeMap<K, V> {
 
     /// Moves all elements from `other` into `Self`, leaving `other` empty.
     ///
+    /// All the nodes of the merged tree are allocated before any element is
+    /// moved, so on failure both `self` and `other` are left unchanged (strong
+    /// guarantee).
+    ///
     /// # Examples
     ///
     /// ```
-    /// use std::collections::BTreeMap;
+    /// use fallible_collections::btree::BTreeMap;
     ///
     /// let mut a = BTreeMap::new();
-    /// a.insert(1, "a");
-    /// a.insert(2, "b");
-    /// a.insert(3, "c");
+    /// a.try_insert(1, "a").unwrap();
+    /// a.try_insert(2, "b").unwrap();
     ///
     /// let mut b = BTreeMap::new();
-    /// b.insert(3, "d");
-    /// b.insert(4, "e");
-    /// b.insert(5, "f");
+    /// b.try_insert(2, "c").unwrap();
+    /// b.try_insert(3, "d").unwrap();
     ///
-    /// a.append(&mut b);
+    /// a.try_append(&mut b).unwrap();
     ///
-    /// assert_eq!(a.len(), 5);
-    /// assert_eq!(b.len(), 0);
-    ///
-    /// assert_eq!(a[&1], "a");
-    /// assert_eq!(a[&2], "b");
-    /// assert_eq!(a[&3], "d");
-    /// assert_eq!(a[&4], "e");
-    /// assert_eq!(a[&5], "f");
+    /// assert_eq!(a.len(), 3);
+    /// assert!(b.is_empty());
+    /// assert_eq!(a[&2], "c");
     /// ```
-
-    pub fn append(&mut self, other: &mut Self) {
+    pub fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
         // Do we have to append anything at all?
-        if other.len() == 0 {
-            return;
+        if other.is_empty() {
+            return Ok(());
         }
 
         // We can just swap `self` and `other` if `self` is empty.
-        if self.len() == 0 {
+        if self.is_empty() {
             mem::swap(self, other);
-            return;
+            return Ok(());
         }
 
+        // Allocate every node the new tree may need before consuming either map.
+        // The pool of `self` is kept for the new tree.
+        let (leaves, internals) = bulk_push_nodes_needed(self.len() + other.len());
+        self.pool.try_reserve(leaves, internals)?;
+        let alloc = self.pool.allocator().clone();
+        let mut left = mem::replace(self, BTreeMap::new_in(alloc.clone()));
+        mem::swap(&mut self.pool, &mut left.pool);
+
         // First, we merge `self` and `other` into a sorted sequence in linear time.
-        let self_iter = mem::replace(self, BTreeMap::new()).into_iter();
-        let other_iter = mem::replace(other, BTreeMap::new()).into_iter();
+        let self_iter = left.into_iter();
+        let other_iter = mem::replace(other, BTreeMap::new_in(alloc)).into_iter();
         let iter = MergeIter {
             left: self_iter.peekable(),
             right: other_iter.peekable(),
         };
 
         // Second, we build a tree from the sorted sequence in linear time.
-        self.from_sorted_iter(iter);
+        // All the nodes are in the pool, so this cannot fail.
+        let res = self.bulk_push(iter);
+        debug_assert!(res.is_ok());
         self.fix_right_edge();
+        Ok(())
+    }
+
+    /// Inserts all the key-value pairs of `iter` into the map, stopping at the
+    /// first allocation failure. The pairs inserted before the failure are kept
+    /// (basic guarantee).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_extend(vec![(1, "a"), (2, "b")]).unwrap();
+    /// assert_eq!(map.len(), 2);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = (K, V)>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), TryReserveError> {
+        for (key, value) in iter {
+            self.try_insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Same as `try_from_sorted_iter`, the nodes being allocated in `alloc`.
+    pub fn try_from_sorted_iter_in<I>(iter: I, alloc: A) -> Result<Self, TryReserveError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut iter = DedupSortedIter {
+            iter: iter.into_iter().peekable(),
+        };
+        let mut map = BTreeMap::new_in(alloc);
+        if iter.iter.peek().is_some() {
+            let res = map.bulk_push(iter);
+            // Rebalance even on failure, so that the map is dropped as a valid tree.
+            map.fix_right_edge();
+            res?;
+        }
+        Ok(map)
+    }
+
+    /// Retains only the elements specified by the predicate.
+    ///
+    /// In other words, remove all pairs `(k, v)` for which `f(&k, &mut v)` returns `false`.
+    /// The elements are visited in ascending key order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let mut map: BTreeMap<i32, i32> = (0..8).map(|x| (x, x * 10)).try_collect().unwrap();
+    /// // Keep only the elements with even-numbered keys.
+    /// map.retain(|&k, _| k % 2 == 0);
+    /// assert!(map.into_iter().eq(vec![(0, 0), (2, 20), (4, 40), (6, 60)]));
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.extract_if(|k, v| !f(k, v)).for_each(drop);
+    }
+
+    /// Creates an iterator that visits all elements in ascending key order and
+    /// uses a closure to determine if an element should be removed.
+    ///
+    /// If the closure returns `true`, the element is removed from the map and
+    /// yielded. If the closure returns `false`, or panics, the element remains
+    /// in the map and will not be yielded.
+    ///
+    /// If the returned `ExtractIf` is not exhausted, e.g. because it is dropped
+    /// without iterating or the iteration short-circuits, then the remaining
+    /// elements will be retained.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeMap;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let mut map: BTreeMap<i32, i32> = (0..8).map(|x| (x, x)).try_collect().unwrap();
+    /// let evens: Vec<_> = map.extract_if(|k, _v| k % 2 == 0).collect();
+    /// assert_eq!(evens, vec![(0, 0), (2, 2), (4, 4), (6, 6)]);
+    /// assert!(map.into_iter().eq(vec![(1, 1), (3, 3), (5, 5), (7, 7)]));
+    /// ```
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, F, A>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf {
+            cursor: self.lower_bound_mut(Unbounded),
+            pred,
+        }
     }
 
     /// Constructs a double-ended iterator over a sub-range of elements in the map.
@@ -881,10 +1194,9 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// }
     /// assert_eq!(Some((&5, &"b")), map.range(4..).next());
     /// ```
-
-    pub fn range<T: ?Sized, R>(&self, range: R) -> Range<'_, K, V>
+    pub fn range<T, R>(&self, range: R) -> Range<'_, K, V>
     where
-        T: Ord,
+        T: Ord + ?Sized,
         K: Borrow<T>,
         R: RangeBounds<T>,
     {
@@ -925,10 +1237,9 @@ impl<K: Ord, V> BTreeMap<K, V> {
     ///     println!("{} => {}", name, balance);
     /// }
     /// ```
-
-    pub fn range_mut<T: ?Sized, R>(&mut self, range: R) -> RangeMut<'_, K, V>
+    pub fn range_mut<T, R>(&mut self, range: R) -> RangeMut<'_, K, V>
     where
-        T: Ord,
+        T: Ord + ?Sized,
         K: Borrow<T>,
         R: RangeBounds<T>,
     {
@@ -943,7 +1254,160 @@ impl<K: Ord, V> BTreeMap<K, V> {
         }
     }
 
+    /// Returns a [`Cursor`] pointing at the gap before the smallest key
+    /// greater than the given bound.
+    ///
+    /// Passing `Bound::Included(x)` will return a cursor pointing to the
+    /// gap before the smallest key greater than or equal to `x`.
+    ///
+    /// Passing `Bound::Excluded(x)` will return a cursor pointing to the
+    /// gap before the smallest key greater than `x`.
+    ///
+    /// Passing `Bound::Unbounded` will return a cursor pointing to the
+    /// gap before the smallest key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::Bound;
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// map.try_insert(3, "c").unwrap();
+    /// map.try_insert(4, "d").unwrap();
+    ///
+    /// let cursor = map.lower_bound(Bound::Included(&2));
+    /// assert_eq!(cursor.peek_prev(), Some((&1, &"a")));
+    /// assert_eq!(cursor.peek_next(), Some((&2, &"b")));
+    ///
+    /// let cursor = map.lower_bound(Bound::Excluded(&2));
+    /// assert_eq!(cursor.peek_prev(), Some((&2, &"b")));
+    /// assert_eq!(cursor.peek_next(), Some((&3, &"c")));
+    /// ```
+    pub fn lower_bound<Q>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Cursor {
+            edge: lower_bound_edge(self.root.as_ref(), bound),
+        }
+    }
+
+    /// Returns a [`CursorMut`] pointing at the gap before the smallest key
+    /// greater than the given bound.
+    ///
+    /// See [`lower_bound`](#method.lower_bound) for the meaning of the bound.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::Bound;
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(4, "d").unwrap();
+    ///
+    /// let mut cursor = map.lower_bound_mut(Bound::Included(&2));
+    /// cursor.try_insert_after(3, "c").unwrap();
+    /// cursor.try_insert_after(2, "b").unwrap();
+    /// assert_eq!(cursor.remove_prev(), Some((1, "a")));
+    /// assert!(map.into_iter().eq(vec![(2, "b"), (3, "c"), (4, "d")]));
+    /// ```
+    pub fn lower_bound_mut<Q>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V, A>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        CursorMut {
+            edge: lower_bound_edge(self.root.as_mut(), bound),
+            length: &mut self.length,
+            pool: &mut self.pool,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a [`Cursor`] pointing at the gap after the greatest key
+    /// smaller than the given bound.
+    ///
+    /// Passing `Bound::Included(x)` will return a cursor pointing to the
+    /// gap after the greatest key smaller than or equal to `x`.
+    ///
+    /// Passing `Bound::Excluded(x)` will return a cursor pointing to the
+    /// gap after the greatest key smaller than `x`.
+    ///
+    /// Passing `Bound::Unbounded` will return a cursor pointing to the
+    /// gap after the greatest key in the map.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::Bound;
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(2, "b").unwrap();
+    /// map.try_insert(3, "c").unwrap();
+    /// map.try_insert(4, "d").unwrap();
+    ///
+    /// let cursor = map.upper_bound(Bound::Included(&3));
+    /// assert_eq!(cursor.peek_prev(), Some((&3, &"c")));
+    /// assert_eq!(cursor.peek_next(), Some((&4, &"d")));
+    ///
+    /// let cursor = map.upper_bound(Bound::Excluded(&3));
+    /// assert_eq!(cursor.peek_prev(), Some((&2, &"b")));
+    /// assert_eq!(cursor.peek_next(), Some((&3, &"c")));
+    /// ```
+    pub fn upper_bound<Q>(&self, bound: Bound<&Q>) -> Cursor<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Cursor {
+            edge: upper_bound_edge(self.root.as_ref(), bound),
+        }
+    }
+
+    /// Returns a [`CursorMut`] pointing at the gap after the greatest key
+    /// smaller than the given bound.
+    ///
+    /// See [`upper_bound`](#method.upper_bound) for the meaning of the bound.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::Bound;
+    /// use fallible_collections::btree::BTreeMap;
+    ///
+    /// let mut map = BTreeMap::new();
+    /// map.try_insert(1, "a").unwrap();
+    /// map.try_insert(4, "d").unwrap();
+    ///
+    /// let mut cursor = map.upper_bound_mut(Bound::Excluded(&4));
+    /// cursor.try_insert_before(2, "b").unwrap();
+    /// cursor.try_insert_before(3, "c").unwrap();
+    /// assert_eq!(cursor.remove_next(), Some((4, "d")));
+    /// assert!(map.into_iter().eq(vec![(1, "a"), (2, "b"), (3, "c")]));
+    /// ```
+    pub fn upper_bound_mut<Q>(&mut self, bound: Bound<&Q>) -> CursorMut<'_, K, V, A>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        CursorMut {
+            edge: upper_bound_edge(self.root.as_mut(), bound),
+            length: &mut self.length,
+            pool: &mut self.pool,
+            _marker: PhantomData,
+        }
+    }
+
     /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    /// The map is left unchanged on error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -961,27 +1425,42 @@ impl<K: Ord, V> BTreeMap<K, V> {
     ///
     /// assert_eq!(count["a"], 3);
     /// ```
-
-    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V>, TryReserveError> {
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V, A>, TryReserveError> {
         // FIXME(@porglezomp) Avoid allocating if we don't insert
         self.ensure_root_is_owned()?;
-        Ok(match search::search_tree(self.root.as_mut(), &key) {
+        Ok(self.owned_root_entry(key))
+    }
+
+    /// Same as `try_entry`, the root must already be owned.
+    fn owned_root_entry(&mut self, key: K) -> Entry<'_, K, V, A> {
+        debug_assert!(!self.root.is_shared_root());
+        match search::search_tree(self.root.as_mut(), &key) {
             Found(handle) => Occupied(OccupiedEntry {
                 handle,
                 length: &mut self.length,
+                pool: &mut self.pool,
                 _marker: PhantomData,
             }),
             GoDown(handle) => Vacant(VacantEntry {
                 key,
                 handle,
                 length: &mut self.length,
+                pool: &mut self.pool,
                 _marker: PhantomData,
             }),
-        })
+        }
     }
 
-    fn from_sorted_iter<I: Iterator<Item = (K, V)>>(&mut self, iter: I) {
-        self.ensure_root_is_owned().expect("Out Of Mem");
+    /// Pushes the sorted `iter` at the end of the map, taking the new nodes from the pool.
+    /// The nodes missing from the pool are allocated as they are needed, none are if the
+    /// pool holds the nodes given by `bulk_push_nodes_needed`. On failure, the pairs pushed
+    /// so far are kept, and the right edge has to be fixed as after a successful push.
+    fn bulk_push<I: Iterator<Item = (K, V)>>(&mut self, iter: I) -> Result<(), TryReserveError> {
+        let pool = &mut self.pool;
+        if self.root.is_shared_root() {
+            pool.try_reserve(1, 0)?;
+            self.root = node::Root::new_leaf_from(pool);
+        }
         let mut cur_node = last_leaf_edge(self.root.as_mut()).into_node();
         // Iterate through all key-value pairs, pushing them into nodes at the right level.
         for (key, value) in iter {
@@ -990,34 +1469,41 @@ impl<K: Ord, V> BTreeMap<K, V> {
                 cur_node.push(key, value);
             } else {
                 // No space left, go up and push there.
-                let mut open_node;
                 let mut test_node = cur_node.forget_type();
-                loop {
+                let open_node = loop {
                     match test_node.ascend() {
                         Ok(parent) => {
                             let parent = parent.into_node();
                             if parent.len() < node::CAPACITY {
                                 // Found a node with space left, push here.
-                                open_node = parent;
-                                break;
+                                break Ok(parent);
                             } else {
                                 // Go up again.
                                 test_node = parent.forget_type();
                             }
                         }
-                        Err(node) => {
-                            // We are at the top, create a new root node and push there.
-                            open_node = node.into_root_mut().push_level().expect("Out of Mem");
-                            break;
-                        }
+                        // We are at the top, a new root node is needed.
+                        Err(node) => break Err(node),
                     }
-                }
+                };
+
+                // Allocate the new right subtree, and the new root if any, before
+                // changing the tree.
+                let internals = match &open_node {
+                    Ok(parent) => parent.height() - 1,
+                    Err(root) => root.height() + 1,
+                };
+                pool.try_reserve(1, internals)?;
+                let mut open_node = match open_node {
+                    Ok(parent) => parent,
+                    Err(node) => node.into_root_mut().push_level_from(pool),
+                };
 
                 // Push key-value pair and new right subtree.
                 let tree_height = open_node.height() - 1;
-                let mut right_tree = node::Root::new_leaf().expect("Out of Mem");
+                let mut right_tree = node::Root::new_leaf_from(pool);
                 for _ in 0..tree_height {
-                    right_tree.push_level().expect("Out of Mem");
+                    right_tree.push_level_from(pool);
                 }
                 open_node.push(key, value, right_tree);
 
@@ -1027,6 +1513,7 @@ impl<K: Ord, V> BTreeMap<K, V> {
 
             self.length += 1;
         }
+        Ok(())
     }
 
     fn fix_right_edge(&mut self) {
@@ -1054,6 +1541,9 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// Splits the collection into two at the given key. Returns everything after the given key,
     /// including the key.
     ///
+    /// The nodes of the returned map are allocated before any element is moved, so the map
+    /// is left unchanged on error (strong guarantee).
+    ///
     /// # Examples
     ///
     /// Basic usage:
@@ -1080,21 +1570,24 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// assert_eq!(b[&17], "d");
     /// assert_eq!(b[&41], "e");
     /// ```
-
-    pub fn split_off<Q: ?Sized + Ord>(&mut self, key: &Q) -> Result<Self, TryReserveError>
+    pub fn split_off<Q>(&mut self, key: &Q) -> Result<Self, TryReserveError>
     where
+        Q: Ord + ?Sized,
         K: Borrow<Q>,
     {
+        let alloc = self.pool.allocator().clone();
         if self.is_empty() {
-            return Ok(Self::new());
+            return Ok(Self::new_in(alloc));
         }
 
         let total_num = self.len();
 
-        let mut right = Self::new();
-        right.root = node::Root::new_leaf()?;
-        for _ in 0..(self.root.as_ref().height()) {
-            right.root.push_level()?;
+        let height = self.root.as_ref().height();
+        let mut right = Self::new_in(alloc);
+        right.pool.try_reserve(1, height)?;
+        right.root = node::Root::new_leaf_from(&mut right.pool);
+        for _ in 0..height {
+            right.root.push_level_from(&mut right.pool);
         }
 
         {
@@ -1169,6 +1662,73 @@ impl<K: Ord, V> BTreeMap<K, V> {
         self.length = dfs(self.root.as_ref());
     }
 
+    /// Counts the leaf and internal nodes of the tree, not including the free
+    /// nodes of the pool.
+    fn node_counts(&self) -> (usize, usize) {
+        fn dfs<'a, K, V>(
+            node: NodeRef<marker::Immut<'a>, K, V, marker::LeafOrInternal>,
+            counts: &mut (usize, usize),
+        ) where
+            K: 'a,
+            V: 'a,
+        {
+            match node.force() {
+                Leaf(_) => counts.0 += 1,
+                Internal(node) => {
+                    counts.1 += 1;
+                    for i in 0..=node.len() {
+                        dfs(Handle::new_edge(node, i).descend(), counts);
+                    }
+                }
+            }
+        }
+
+        let mut counts = (0, 0);
+        if !self.root.is_shared_root() {
+            dfs(self.root.as_ref(), &mut counts);
+        }
+        counts
+    }
+
+    /// Panics if the tree is malformed: the keys must be strictly
+    /// increasing, their count must match `len`, and the nodes other than
+    /// the root must not be underfull.
+    #[cfg(test)]
+    pub(crate) fn check(&self)
+    where
+        K: Ord,
+    {
+        fn dfs<'a, K, V>(
+            node: NodeRef<marker::Immut<'a>, K, V, marker::LeafOrInternal>,
+            is_root: bool,
+        ) where
+            K: 'a,
+            V: 'a,
+        {
+            assert!(is_root || node.len() >= node::MIN_LEN - 1, "underfull node");
+            if let Internal(node) = node.force() {
+                for i in 0..=node.len() {
+                    dfs(Handle::new_edge(node, i).descend(), false);
+                }
+            }
+        }
+
+        if !self.root.is_shared_root() {
+            dfs(self.root.as_ref(), true);
+        }
+        let mut count = 0;
+        let mut keys = self.keys();
+        if let Some(mut prev) = keys.next() {
+            count += 1;
+            for key in keys {
+                assert!(prev < key, "keys out of order");
+                prev = key;
+                count += 1;
+            }
+        }
+        assert_eq!(count, self.len(), "wrong length");
+    }
+
     /// Removes empty levels on the top.
     fn fix_top(&mut self) {
         loop {
@@ -1178,7 +1738,7 @@ impl<K: Ord, V> BTreeMap<K, V> {
                     break;
                 }
             }
-            self.root.pop_level();
+            self.root.pop_level(&mut self.pool);
         }
     }
 
@@ -1192,7 +1752,7 @@ impl<K: Ord, V> BTreeMap<K, V> {
                 let mut last_kv = node.last_kv();
 
                 if last_kv.can_merge() {
-                    cur_node = last_kv.merge().descend();
+                    cur_node = last_kv.merge(&mut self.pool).descend();
                 } else {
                     let right_len = last_kv.reborrow().right_edge().descend().len();
                     // `MINLEN + 1` to avoid readjust if merge happens on the next level.
@@ -1218,7 +1778,7 @@ impl<K: Ord, V> BTreeMap<K, V> {
                 let mut first_kv = node.first_kv();
 
                 if first_kv.can_merge() {
-                    cur_node = first_kv.merge().descend();
+                    cur_node = first_kv.merge(&mut self.pool).descend();
                 } else {
                     let left_len = first_kv.reborrow().left_edge().descend().len();
                     if left_len < node::MIN_LEN + 1 {
@@ -1235,13 +1795,14 @@ impl<K: Ord, V> BTreeMap<K, V> {
     /// If the root node is the shared root node, allocate our own node.
     fn ensure_root_is_owned(&mut self) -> Result<(), TryReserveError> {
         if self.root.is_shared_root() {
-            self.root = node::Root::new_leaf()?;
+            self.pool.try_reserve(1, 0)?;
+            self.root = node::Root::new_leaf_from(&mut self.pool);
         }
         Ok(())
     }
 }
 
-impl<'a, K: 'a, V: 'a> IntoIterator for &'a BTreeMap<K, V> {
+impl<'a, K: 'a, V: 'a, A: Allocator> IntoIterator for &'a BTreeMap<K, V, A> {
     type Item = (&'a K, &'a V);
     type IntoIter = Iter<'a, K, V>;
 
@@ -1298,7 +1859,7 @@ impl<K, V> Clone for Iter<'_, K, V> {
     }
 }
 
-impl<'a, K: 'a, V: 'a> IntoIterator for &'a mut BTreeMap<K, V> {
+impl<'a, K: 'a, V: 'a, A: Allocator> IntoIterator for &'a mut BTreeMap<K, V, A> {
     type Item = (&'a K, &'a mut V);
     type IntoIter = IterMut<'a, K, V>;
 
@@ -1347,25 +1908,34 @@ impl<K, V> ExactSizeIterator for IterMut<'_, K, V> {
 
 impl<K, V> FusedIterator for IterMut<'_, K, V> {}
 
-impl<K, V> IntoIterator for BTreeMap<K, V> {
+impl<K, V, A: Allocator> IntoIterator for BTreeMap<K, V, A> {
     type Item = (K, V);
-    type IntoIter = IntoIter<K, V>;
+    type IntoIter = IntoIter<K, V, A>;
 
-    fn into_iter(self) -> IntoIter<K, V> {
-        let root1 = unsafe { ptr::read(&self.root).into_ref() };
-        let root2 = unsafe { ptr::read(&self.root).into_ref() };
-        let len = self.length;
-        mem::forget(self);
+    fn into_iter(self) -> IntoIter<K, V, A> {
+        let me = mem::ManuallyDrop::new(self);
+        let root = unsafe { ptr::read(&me.root) };
+        let pool = unsafe { ptr::read(&me.pool) };
+        IntoIter::new(root, me.length, pool.into_allocator())
+    }
+}
 
+impl<K, V, A: Allocator> IntoIter<K, V, A> {
+    /// Takes the ownership of the `length` entries and of the nodes of `root`,
+    /// which are deallocated in `alloc`.
+    fn new(root: node::Root<K, V>, length: usize, alloc: A) -> Self {
+        let root1 = unsafe { ptr::read(&root).into_ref() };
+        let root2 = root.into_ref();
         IntoIter {
             front: first_leaf_edge(root1),
             back: last_leaf_edge(root2),
-            length: len,
+            length,
+            alloc,
         }
     }
 }
 
-impl<K, V> Drop for IntoIter<K, V> {
+impl<K, V, A: Allocator> Drop for IntoIter<K, V, A> {
     fn drop(&mut self) {
         self.for_each(drop);
         unsafe {
@@ -1374,9 +1944,9 @@ impl<K, V> Drop for IntoIter<K, V> {
                 return;
             }
 
-            if let Some(first_parent) = leaf_node.deallocate_and_ascend() {
+            if let Some(first_parent) = leaf_node.deallocate_and_ascend(&self.alloc) {
                 let mut cur_node = first_parent.into_node();
-                while let Some(parent) = cur_node.deallocate_and_ascend() {
+                while let Some(parent) = cur_node.deallocate_and_ascend(&self.alloc) {
                     cur_node = parent.into_node()
                 }
             }
@@ -1384,7 +1954,7 @@ impl<K, V> Drop for IntoIter<K, V> {
     }
 }
 
-impl<K, V> Iterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> Iterator for IntoIter<K, V, A> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<(K, V)> {
@@ -1404,7 +1974,7 @@ impl<K, V> Iterator for IntoIter<K, V> {
                 return Some((k, v));
             }
             Err(last_edge) => unsafe {
-                unwrap_unchecked(last_edge.into_node().deallocate_and_ascend())
+                unwrap_unchecked(last_edge.into_node().deallocate_and_ascend(&self.alloc))
             },
         };
 
@@ -1417,7 +1987,8 @@ impl<K, V> Iterator for IntoIter<K, V> {
                     return Some((k, v));
                 }
                 Err(last_edge) => unsafe {
-                    cur_handle = unwrap_unchecked(last_edge.into_node().deallocate_and_ascend());
+                    cur_handle =
+                        unwrap_unchecked(last_edge.into_node().deallocate_and_ascend(&self.alloc));
                 },
             }
         }
@@ -1429,7 +2000,7 @@ impl<K, V> Iterator for IntoIter<K, V> {
     }
 }
 
-impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> DoubleEndedIterator for IntoIter<K, V, A> {
     fn next_back(&mut self) -> Option<(K, V)> {
         if self.length == 0 {
             return None;
@@ -1447,7 +2018,7 @@ impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
                 return Some((k, v));
             }
             Err(last_edge) => unsafe {
-                unwrap_unchecked(last_edge.into_node().deallocate_and_ascend())
+                unwrap_unchecked(last_edge.into_node().deallocate_and_ascend(&self.alloc))
             },
         };
 
@@ -1460,21 +2031,22 @@ impl<K, V> DoubleEndedIterator for IntoIter<K, V> {
                     return Some((k, v));
                 }
                 Err(last_edge) => unsafe {
-                    cur_handle = unwrap_unchecked(last_edge.into_node().deallocate_and_ascend());
+                    cur_handle =
+                        unwrap_unchecked(last_edge.into_node().deallocate_and_ascend(&self.alloc));
                 },
             }
         }
     }
 }
 
-impl<K, V> ExactSizeIterator for IntoIter<K, V> {
+impl<K, V, A: Allocator> ExactSizeIterator for IntoIter<K, V, A> {
     #[inline(always)]
     fn len(&self) -> usize {
         self.length
     }
 }
 
-impl<K, V> FusedIterator for IntoIter<K, V> {}
+impl<K, V, A: Allocator> FusedIterator for IntoIter<K, V, A> {}
 
 impl<'a, K, V> Iterator for Keys<'a, K, V> {
     type Item = &'a K;
@@ -1782,32 +2354,17 @@ impl<'a, K, V> RangeMut<'a, K, V> {
     }
 }
 
-impl<K: Ord, V> FromIterator<(K, V)> for BTreeMap<K, V> {
-    #[inline]
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> BTreeMap<K, V> {
+impl<K: Ord, V> TryFromIterator<(K, V)> for BTreeMap<K, V> {
+    fn try_from_iterator<T: IntoIterator<Item = (K, V)>>(
+        iterator: T,
+    ) -> Result<Self, TryReserveError> {
         let mut map = BTreeMap::new();
-        map.extend(iter);
-        map
-    }
-}
-
-impl<K: Ord, V> Extend<(K, V)> for BTreeMap<K, V> {
-    #[inline]
-    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
-        iter.into_iter().for_each(move |(k, v)| {
-            self.try_insert(k, v).expect("Out of Mem");
-        });
+        map.try_extend(iterator)?;
+        Ok(map)
     }
 }
 
-impl<'a, K: Ord + Copy, V: Copy> Extend<(&'a K, &'a V)> for BTreeMap<K, V> {
-    #[inline]
-    fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
-        self.extend(iter.into_iter().map(|(&key, &value)| (key, value)));
-    }
-}
-
-impl<K: Hash, V: Hash> Hash for BTreeMap<K, V> {
+impl<K: Hash, V: Hash, A: Allocator> Hash for BTreeMap<K, V, A> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         for elt in self {
             elt.hash(state);
@@ -1823,35 +2380,35 @@ impl<K: Ord, V> Default for BTreeMap<K, V> {
     }
 }
 
-impl<K: PartialEq, V: PartialEq> PartialEq for BTreeMap<K, V> {
-    fn eq(&self, other: &BTreeMap<K, V>) -> bool {
+impl<K: PartialEq, V: PartialEq, A: Allocator> PartialEq for BTreeMap<K, V, A> {
+    fn eq(&self, other: &BTreeMap<K, V, A>) -> bool {
         self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a == b)
     }
 }
 
-impl<K: Eq, V: Eq> Eq for BTreeMap<K, V> {}
+impl<K: Eq, V: Eq, A: Allocator> Eq for BTreeMap<K, V, A> {}
 
-impl<K: PartialOrd, V: PartialOrd> PartialOrd for BTreeMap<K, V> {
+impl<K: PartialOrd, V: PartialOrd, A: Allocator> PartialOrd for BTreeMap<K, V, A> {
     #[inline]
-    fn partial_cmp(&self, other: &BTreeMap<K, V>) -> Option<Ordering> {
+    fn partial_cmp(&self, other: &BTreeMap<K, V, A>) -> Option<Ordering> {
         self.iter().partial_cmp(other.iter())
     }
 }
 
-impl<K: Ord, V: Ord> Ord for BTreeMap<K, V> {
+impl<K: Ord, V: Ord, A: Allocator> Ord for BTreeMap<K, V, A> {
     #[inline]
-    fn cmp(&self, other: &BTreeMap<K, V>) -> Ordering {
+    fn cmp(&self, other: &BTreeMap<K, V, A>) -> Ordering {
         self.iter().cmp(other.iter())
     }
 }
 
-impl<K: Debug, V: Debug> Debug for BTreeMap<K, V> {
+impl<K: Debug, V: Debug, A: Allocator> Debug for BTreeMap<K, V, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl<K: Ord, Q: ?Sized, V> Index<&Q> for BTreeMap<K, V>
+impl<K: Ord, Q: ?Sized, V, A: Allocator + Clone> Index<&Q> for BTreeMap<K, V, A>
 where
     K: Borrow<Q>,
     Q: Ord,
@@ -1869,6 +2426,40 @@ where
     }
 }
 
+/// Returns the number of leaf and internal nodes that `bulk_push` takes to build a
+/// tree of `len` entries in an empty map.
+fn bulk_push_nodes_needed(len: usize) -> (usize, usize) {
+    // A node takes `CAPACITY` entries, the next one goes up to the parent and
+    // starts a new subtree on the right.
+    let mut passed = len / (node::CAPACITY + 1);
+    let leaves = 1 + passed;
+    let mut internals = 0;
+    while passed > 0 {
+        passed /= node::CAPACITY + 1;
+        internals += 1 + passed;
+    }
+    (leaves, internals)
+}
+
+/// Returns an upper bound of the number of leaf and internal nodes of a tree of
+/// `len` entries.
+fn max_nodes(len: usize) -> (usize, usize) {
+    if len == 0 {
+        return (0, 0);
+    } else if len <= node::CAPACITY {
+        return (1, 0);
+    }
+    // Splitting a full node leaves `MIN_LEN - 1` entries in the new right node,
+    // and removals never leave fewer, so every node but the root holds at least
+    // that many entries.
+    let min_len = node::MIN_LEN - 1;
+    let leaves = len / min_len + 1;
+    // Every internal node but the root has at least `min_len + 1` children, and
+    // the root at least 2.
+    let internals = (leaves - 2) / min_len + 1;
+    (leaves, internals)
+}
+
 fn first_leaf_edge<BorrowType, K, V>(
     mut node: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
 ) -> Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge> {
@@ -1895,7 +2486,98 @@ fn last_leaf_edge<BorrowType, K, V>(
     }
 }
 
-fn range_search<BorrowType, K, V, Q: ?Sized, R: RangeBounds<Q>>(
+/// Returns the key-value pair right after the leaf edge `edge`, if any.
+fn next_kv<BorrowType, K, V>(
+    edge: Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge>,
+) -> Option<Handle<NodeRef<BorrowType, K, V, marker::LeafOrInternal>, marker::KV>> {
+    let mut edge = edge.forget_node_type();
+    loop {
+        edge = match edge.right_kv() {
+            Ok(kv) => return Some(kv),
+            Err(last_edge) => last_edge.into_node().ascend().ok()?.forget_node_type(),
+        }
+    }
+}
+
+/// Returns the key-value pair right before the leaf edge `edge`, if any.
+fn prev_kv<BorrowType, K, V>(
+    edge: Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge>,
+) -> Option<Handle<NodeRef<BorrowType, K, V, marker::LeafOrInternal>, marker::KV>> {
+    let mut edge = edge.forget_node_type();
+    loop {
+        edge = match edge.left_kv() {
+            Ok(kv) => return Some(kv),
+            Err(first_edge) => first_edge.into_node().ascend().ok()?.forget_node_type(),
+        }
+    }
+}
+
+/// Returns the leaf edge right after the key-value pair `kv`.
+fn next_leaf_edge<BorrowType, K, V>(
+    kv: Handle<NodeRef<BorrowType, K, V, marker::LeafOrInternal>, marker::KV>,
+) -> Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge> {
+    match kv.force() {
+        Leaf(kv) => kv.right_edge(),
+        Internal(kv) => first_leaf_edge(kv.right_edge().descend()),
+    }
+}
+
+/// Returns the leaf edge right before the key-value pair `kv`.
+fn prev_leaf_edge<BorrowType, K, V>(
+    kv: Handle<NodeRef<BorrowType, K, V, marker::LeafOrInternal>, marker::KV>,
+) -> Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge> {
+    match kv.force() {
+        Leaf(kv) => kv.left_edge(),
+        Internal(kv) => last_leaf_edge(kv.left_edge().descend()),
+    }
+}
+
+/// Returns the leaf edge before the smallest key above `bound`.
+fn lower_bound_edge<BorrowType, K, V, Q>(
+    root: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
+    bound: Bound<&Q>,
+) -> Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge>
+where
+    Q: Ord + ?Sized,
+    K: Borrow<Q>,
+{
+    match bound {
+        Included(key) => match search::search_tree(root, key) {
+            Found(kv) => prev_leaf_edge(kv),
+            GoDown(edge) => edge,
+        },
+        Excluded(key) => match search::search_tree(root, key) {
+            Found(kv) => next_leaf_edge(kv),
+            GoDown(edge) => edge,
+        },
+        Unbounded => first_leaf_edge(root),
+    }
+}
+
+/// Returns the leaf edge after the greatest key below `bound`.
+fn upper_bound_edge<BorrowType, K, V, Q>(
+    root: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
+    bound: Bound<&Q>,
+) -> Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge>
+where
+    Q: Ord + ?Sized,
+    K: Borrow<Q>,
+{
+    match bound {
+        Included(key) => match search::search_tree(root, key) {
+            Found(kv) => next_leaf_edge(kv),
+            GoDown(edge) => edge,
+        },
+        Excluded(key) => match search::search_tree(root, key) {
+            Found(kv) => prev_leaf_edge(kv),
+            GoDown(edge) => edge,
+        },
+        Unbounded => last_leaf_edge(root),
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn range_search<BorrowType, K, V, Q, R: RangeBounds<Q>>(
     root1: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
     root2: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
     range: R,
@@ -1904,7 +2586,7 @@ fn range_search<BorrowType, K, V, Q: ?Sized, R: RangeBounds<Q>>(
     Handle<NodeRef<BorrowType, K, V, marker::Leaf>, marker::Edge>,
 )
 where
-    Q: Ord,
+    Q: Ord + ?Sized,
     K: Borrow<Q>,
 {
     match (range.start_bound(), range.end_bound()) {
@@ -1999,12 +2681,12 @@ unsafe fn unwrap_unchecked<T>(val: Option<T>) -> T {
         if cfg!(debug_assertions) {
             panic!("'unchecked' unwrap on None in BTreeMap");
         } else {
-            intrinsics::unreachable();
+            hint::unreachable_unchecked();
         }
     })
 }
 
-impl<K, V> BTreeMap<K, V> {
+impl<K, V, A: Allocator> BTreeMap<K, V, A> {
     /// Gets an iterator over the entries of the map, sorted by key.
     ///
     /// # Examples
@@ -2026,7 +2708,6 @@ impl<K, V> BTreeMap<K, V> {
     /// let (first_key, first_value) = map.iter().next().unwrap();
     /// assert_eq!((*first_key, *first_value), (1, "a"));
     /// ```
-
     pub fn iter(&self) -> Iter<'_, K, V> {
         Iter {
             range: Range {
@@ -2058,7 +2739,6 @@ impl<K, V> BTreeMap<K, V> {
     ///     }
     /// }
     /// ```
-
     pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
         let root1 = self.root.as_mut();
         let root2 = unsafe { ptr::read(&root1) };
@@ -2088,7 +2768,6 @@ impl<K, V> BTreeMap<K, V> {
     /// let keys: Vec<_> = a.keys().cloned().collect();
     /// assert_eq!(keys, [1, 2]);
     /// ```
-
     #[inline(always)]
     pub fn keys<'a>(&'a self) -> Keys<'a, K, V> {
         Keys { inner: self.iter() }
@@ -2110,7 +2789,6 @@ impl<K, V> BTreeMap<K, V> {
     /// let values: Vec<&str> = a.values().cloned().collect();
     /// assert_eq!(values, ["hello", "goodbye"]);
     /// ```
-
     #[inline(always)]
     pub fn values<'a>(&'a self) -> Values<'a, K, V> {
         Values { inner: self.iter() }
@@ -2137,7 +2815,6 @@ impl<K, V> BTreeMap<K, V> {
     /// assert_eq!(values, [String::from("hello!"),
     ///                     String::from("goodbye!")]);
     /// ```
-
     #[inline(always)]
     pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
         ValuesMut {
@@ -2159,7 +2836,6 @@ impl<K, V> BTreeMap<K, V> {
     /// a.insert(1, "a");
     /// assert_eq!(a.len(), 1);
     /// ```
-
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.length
@@ -2179,16 +2855,22 @@ impl<K, V> BTreeMap<K, V> {
     /// a.insert(1, "a");
     /// assert!(!a.is_empty());
     /// ```
-
     #[inline(always)]
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Returns a reference to the allocator of the map.
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        self.pool.allocator()
+    }
 }
 
-impl<'a, K: Ord, V> Entry<'a, K, V> {
+impl<'a, K: Ord, V, A: Allocator> Entry<'a, K, V, A> {
     /// Ensures a value is in the entry by inserting the default if empty, and returns
-    /// a mutable reference to the value in the entry.
+    /// a mutable reference to the value in the entry. The map is left unchanged on
+    /// error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -2200,7 +2882,6 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     ///
     /// assert_eq!(map["poneyland"], 12);
     /// ```
-
     pub fn or_try_insert(self, default: V) -> Result<&'a mut V, TryReserveError> {
         match self {
             Occupied(entry) => Ok(entry.into_mut()),
@@ -2209,7 +2890,8 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     }
 
     /// Ensures a value is in the entry by inserting the result of the default function if empty,
-    /// and returns a mutable reference to the value in the entry.
+    /// and returns a mutable reference to the value in the entry. The map is left unchanged on
+    /// error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -2223,7 +2905,6 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     ///
     /// assert_eq!(map["poneyland"], "hoho".to_string());
     /// ```
-
     pub fn or_try_insert_with<F: FnOnce() -> V>(
         self,
         default: F,
@@ -2244,7 +2925,6 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     /// let mut map: BTreeMap<&str, usize> = BTreeMap::new();
     /// assert_eq!(map.entry("poneyland").key(), &"poneyland");
     /// ```
-
     #[inline]
     pub fn key(&self) -> &K {
         match *self {
@@ -2273,7 +2953,6 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     ///    .or_insert(42);
     /// assert_eq!(map["poneyland"], 43);
     /// ```
-
     pub fn and_modify<F>(self, f: F) -> Self
     where
         F: FnOnce(&mut V),
@@ -2288,9 +2967,10 @@ impl<'a, K: Ord, V> Entry<'a, K, V> {
     }
 }
 
-impl<'a, K: Ord, V: Default> Entry<'a, K, V> {
+impl<'a, K: Ord, V: Default, A: Allocator> Entry<'a, K, V, A> {
     /// Ensures a value is in the entry by inserting the default value if empty,
-    /// and returns a mutable reference to the value in the entry.
+    /// and returns a mutable reference to the value in the entry. The map is
+    /// left unchanged on error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -2312,7 +2992,7 @@ impl<'a, K: Ord, V: Default> Entry<'a, K, V> {
     }
 }
 
-impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+impl<'a, K: Ord, V, A: Allocator> VacantEntry<'a, K, V, A> {
     /// Gets a reference to the key that would be used when inserting a value
     /// through the VacantEntry.
     ///
@@ -2324,7 +3004,6 @@ impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
     /// let mut map: BTreeMap<&str, usize> = BTreeMap::new();
     /// assert_eq!(map.entry("poneyland").key(), &"poneyland");
     /// ```
-
     #[inline(always)]
     pub fn key(&self) -> &K {
         &self.key
@@ -2350,7 +3029,8 @@ impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
     }
 
     /// Sets the value of the entry with the `VacantEntry`'s key,
-    /// and returns a mutable reference to it.
+    /// and returns a mutable reference to it. The map is left unchanged on
+    /// error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -2366,31 +3046,73 @@ impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
     ///
     /// assert_eq!(count["a"], 3);
     /// ```
+    pub fn try_insert(mut self, value: V) -> Result<&'a mut V, TryReserveError> {
+        self.reserve()?;
+        Ok(self.insert_reserved(value).1)
+    }
+
+    /// Sets the value of the entry with the `VacantEntry`'s key, and returns
+    /// a mutable reference to it. If an allocation failed, the key and the
+    /// value are given back and the map is left unchanged (strong guarantee).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::{map::Entry, BTreeMap};
+    ///
+    /// let mut map: BTreeMap<&str, usize> = BTreeMap::new();
+    ///
+    /// if let Entry::Vacant(v) = map.try_entry("poneyland").unwrap() {
+    ///     *v.try_insert_give_back(37).unwrap() += 1;
+    /// }
+    /// assert_eq!(map["poneyland"], 38);
+    /// ```
+    pub fn try_insert_give_back(mut self, value: V) -> Result<&'a mut V, AllocError<(K, V)>> {
+        match self.reserve() {
+            Ok(()) => Ok(self.insert_reserved(value).1),
+            Err(e) => Err(AllocError::new((self.key, value), e)),
+        }
+    }
+
+    /// Allocates ahead all the nodes needed by the insertion which are not
+    /// already in the pool of the map, so that the tree is not modified if an
+    /// allocation fails.
+    fn reserve(&mut self) -> Result<(), TryReserveError> {
+        let (leaves, internals) = self.handle.reborrow().into_node().nodes_needed_for_insert();
+        self.pool.try_reserve(leaves, internals)
+    }
 
-    pub fn try_insert(self, value: V) -> Result<&'a mut V, TryReserveError> {
+    /// Inserts the entry with the nodes taken from the pool of the map, returning
+    /// a pointer to the key which now lives in the map along with the value.
+    fn insert_reserved(self, value: V) -> (*const K, &'a mut V) {
         *self.length += 1;
+        let pool = self.pool;
 
-        let out_ptr;
+        let (key_ptr, val_ptr);
 
         let mut ins_k;
         let mut ins_v;
         let mut ins_edge;
 
-        let mut cur_parent = match self.handle.insert(self.key, value)? {
-            (Fit(handle), _) => return Ok(handle.into_kv_mut().1),
-            (Split(left, k, v, right), ptr) => {
+        let mut cur_parent = match self.handle.insert(self.key, value, pool) {
+            (Fit(handle), _) => {
+                let (k, v) = handle.into_kv_mut();
+                return (k, v);
+            }
+            (Split(left, k, v, right), (k_ptr, v_ptr)) => {
                 ins_k = k;
                 ins_v = v;
                 ins_edge = right;
-                out_ptr = ptr;
+                key_ptr = k_ptr;
+                val_ptr = v_ptr;
                 left.ascend().map_err(|n| n.into_root_mut())
             }
         };
 
         loop {
             match cur_parent {
-                Ok(parent) => match parent.insert(ins_k, ins_v, ins_edge)? {
-                    Fit(_) => return Ok(unsafe { &mut *out_ptr }),
+                Ok(parent) => match parent.insert(ins_k, ins_v, ins_edge, pool) {
+                    Fit(_) => return (key_ptr, unsafe { &mut *val_ptr }),
                     Split(left, k, v, right) => {
                         ins_k = k;
                         ins_v = v;
@@ -2399,15 +3121,15 @@ impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
                     }
                 },
                 Err(root) => {
-                    root.push_level()?.push(ins_k, ins_v, ins_edge);
-                    return Ok(unsafe { &mut *out_ptr });
+                    root.push_level_from(pool).push(ins_k, ins_v, ins_edge);
+                    return (key_ptr, unsafe { &mut *val_ptr });
                 }
             }
         }
     }
 }
 
-impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
+impl<'a, K: Ord, V, A: Allocator> OccupiedEntry<'a, K, V, A> {
     /// Gets a reference to the key in the entry.
     ///
     /// # Examples
@@ -2419,7 +3141,6 @@ impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
     /// map.entry("poneyland").or_insert(12);
     /// assert_eq!(map.entry("poneyland").key(), &"poneyland");
     /// ```
-
     #[inline]
     pub fn key(&self) -> &K {
         self.handle.reborrow().into_kv().0
@@ -2444,7 +3165,6 @@ impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
     /// // If now try to get the value, it will panic:
     /// // println!("{}", map["poneyland"]);
     /// ```
-
     #[inline]
     pub fn remove_entry(self) -> (K, V) {
         self.remove_kv()
@@ -2465,7 +3185,6 @@ impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
     ///     assert_eq!(o.get(), &12);
     /// }
     /// ```
-
     #[inline]
     pub fn get(&self) -> &V {
         self.handle.reborrow().into_kv().1
@@ -2601,19 +3320,19 @@ impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
         // Handle underflow
         let mut cur_node = small_leaf.forget_type();
         while cur_node.len() < node::CAPACITY / 2 {
-            match handle_underfull_node(cur_node) {
+            match handle_underfull_node(cur_node, self.pool) {
                 AtRoot => break,
-                EmptyParent(_) => unreachable!(),
+                EmptyParent => unreachable!(),
                 Merged(parent) => {
                     if parent.len() == 0 {
                         // We must be at the root
-                        parent.into_root_mut().pop_level();
+                        parent.into_root_mut().pop_level(self.pool);
                         break;
                     } else {
                         cur_node = parent.forget_type();
                     }
                 }
-                Stole(_) => break,
+                Stole => break,
             }
         }
 
@@ -2621,15 +3340,333 @@ impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
     }
 }
 
+/// A cursor over a `BTreeMap`.
+///
+/// A `Cursor` is like an iterator, except that it can freely seek back-and-forth.
+///
+/// Cursors always point to a gap between two elements in the map, and can
+/// operate on the two immediately adjacent elements.
+///
+/// A `Cursor` is created with the [`BTreeMap::lower_bound`] and [`BTreeMap::upper_bound`] methods.
+pub struct Cursor<'a, K: 'a, V: 'a> {
+    edge: Handle<NodeRef<marker::Immut<'a>, K, V, marker::Leaf>, marker::Edge>,
+}
+
+impl<K, V> Clone for Cursor<'_, K, V> {
+    fn clone(&self) -> Self {
+        Cursor { edge: self.edge }
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for Cursor<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Cursor")
+            .field(&self.peek_prev())
+            .field(&self.peek_next())
+            .finish()
+    }
+}
+
+impl<'a, K, V> Cursor<'a, K, V> {
+    /// Advances the cursor to the next gap, returning the key and value of the
+    /// element that it moved over.
+    ///
+    /// If the cursor is already at the end of the map then `None` is returned
+    /// and the cursor is not moved.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let kv = next_kv(self.edge)?;
+        self.edge = next_leaf_edge(kv);
+        Some(kv.into_kv())
+    }
+
+    /// Advances the cursor to the previous gap, returning the key and value of
+    /// the element that it moved over.
+    ///
+    /// If the cursor is already at the start of the map then `None` is returned
+    /// and the cursor is not moved.
+    pub fn prev(&mut self) -> Option<(&'a K, &'a V)> {
+        let kv = prev_kv(self.edge)?;
+        self.edge = prev_leaf_edge(kv);
+        Some(kv.into_kv())
+    }
+
+    /// Returns a reference to the key and value of the next element without
+    /// moving the cursor.
+    ///
+    /// If the cursor is at the end of the map then `None` is returned.
+    pub fn peek_next(&self) -> Option<(&'a K, &'a V)> {
+        next_kv(self.edge).map(|kv| kv.into_kv())
+    }
+
+    /// Returns a reference to the key and value of the previous element
+    /// without moving the cursor.
+    ///
+    /// If the cursor is at the start of the map then `None` is returned.
+    pub fn peek_prev(&self) -> Option<(&'a K, &'a V)> {
+        prev_kv(self.edge).map(|kv| kv.into_kv())
+    }
+}
+
+/// A cursor over a `BTreeMap` with editing operations.
+///
+/// A `Cursor` is like an iterator, except that it can freely seek back-and-forth, and can
+/// safely mutate the map during iteration. Inserting an element allocates like
+/// [`BTreeMap::try_insert`] does, and reports the failure the same way.
+///
+/// Cursors always point to a gap between two elements in the map, and can
+/// operate on the two immediately adjacent elements.
+///
+/// A `CursorMut` is created with the [`BTreeMap::lower_bound_mut`] and [`BTreeMap::upper_bound_mut`]
+/// methods.
+pub struct CursorMut<'a, K: 'a, V: 'a, A: Allocator = Global> {
+    edge: Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge>,
+    length: &'a mut usize,
+    pool: &'a mut node::NodePool<K, V, A>,
+
+    // Be invariant in `K` and `V`
+    _marker: PhantomData<&'a mut (K, V)>,
+}
+
+impl<K: Debug, V: Debug, A: Allocator> Debug for CursorMut<'_, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cursor = self.as_cursor();
+        f.debug_tuple("CursorMut")
+            .field(&cursor.peek_prev())
+            .field(&cursor.peek_next())
+            .finish()
+    }
+}
+
+impl<'a, K, V, A: Allocator> CursorMut<'a, K, V, A> {
+    /// Advances the cursor to the next gap, returning the key and value of the
+    /// element that it moved over.
+    ///
+    /// If the cursor is already at the end of the map then `None` is returned
+    /// and the cursor is not moved.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(&K, &mut V)> {
+        let kv = next_kv(unsafe { ptr::read(&self.edge) })?;
+        self.edge = next_leaf_edge(unsafe { ptr::read(&kv) });
+        let (k, v) = kv.into_kv_mut();
+        Some((k, v))
+    }
+
+    /// Advances the cursor to the previous gap, returning the key and value of
+    /// the element that it moved over.
+    ///
+    /// If the cursor is already at the start of the map then `None` is returned
+    /// and the cursor is not moved.
+    pub fn prev(&mut self) -> Option<(&K, &mut V)> {
+        let kv = prev_kv(unsafe { ptr::read(&self.edge) })?;
+        self.edge = prev_leaf_edge(unsafe { ptr::read(&kv) });
+        let (k, v) = kv.into_kv_mut();
+        Some((k, v))
+    }
+
+    /// Returns a reference to the key and value of the next element without
+    /// moving the cursor.
+    ///
+    /// If the cursor is at the end of the map then `None` is returned.
+    pub fn peek_next(&mut self) -> Option<(&K, &mut V)> {
+        let (k, v) = next_kv(unsafe { ptr::read(&self.edge) })?.into_kv_mut();
+        Some((k, v))
+    }
+
+    /// Returns a reference to the key and value of the previous element
+    /// without moving the cursor.
+    ///
+    /// If the cursor is at the start of the map then `None` is returned.
+    pub fn peek_prev(&mut self) -> Option<(&K, &mut V)> {
+        let (k, v) = prev_kv(unsafe { ptr::read(&self.edge) })?.into_kv_mut();
+        Some((k, v))
+    }
+
+    /// Returns a read-only cursor pointing to the same location as the
+    /// `CursorMut`.
+    pub fn as_cursor(&self) -> Cursor<'_, K, V> {
+        Cursor {
+            edge: self.edge.reborrow(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V, A: Allocator> CursorMut<'a, K, V, A> {
+    /// Inserts a new key-value pair into the map in the gap that the
+    /// cursor is currently pointing to.
+    ///
+    /// After the insertion the cursor will be pointing at the gap before the
+    /// newly inserted element. If the new nodes cannot be allocated, an error
+    /// is returned and the map is left unchanged (strong guarantee).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if:
+    /// - the given key compares less than or equal to the current element (if
+    ///   any).
+    /// - the given key compares greater than or equal to the next element (if
+    ///   any).
+    pub fn try_insert_after(&mut self, key: K, value: V) -> Result<(), TryReserveError> {
+        let key = self.try_insert(key, value)?;
+        self.seek(unsafe { &*key }, true);
+        Ok(())
+    }
+
+    /// Inserts a new key-value pair into the map in the gap that the
+    /// cursor is currently pointing to.
+    ///
+    /// After the insertion the cursor will be pointing at the gap after the
+    /// newly inserted element. If the new nodes cannot be allocated, an error
+    /// is returned and the map is left unchanged (strong guarantee).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if:
+    /// - the given key compares less than or equal to the current element (if
+    ///   any).
+    /// - the given key compares greater than or equal to the next element (if
+    ///   any).
+    pub fn try_insert_before(&mut self, key: K, value: V) -> Result<(), TryReserveError> {
+        let key = self.try_insert(key, value)?;
+        self.seek(unsafe { &*key }, false);
+        Ok(())
+    }
+
+    /// Removes the next element from the map.
+    ///
+    /// The removed element is returned, and the cursor position is unchanged.
+    pub fn remove_next(&mut self) -> Option<(K, V)> {
+        let handle = next_kv(unsafe { ptr::read(&self.edge) })?;
+        let (key, value) = OccupiedEntry {
+            handle,
+            length: &mut *self.length,
+            pool: &mut *self.pool,
+            _marker: PhantomData,
+        }
+        .remove_kv();
+        self.seek(&key, true);
+        Some((key, value))
+    }
+
+    /// Removes the preceding element from the map.
+    ///
+    /// The removed element is returned, and the cursor position is unchanged.
+    pub fn remove_prev(&mut self) -> Option<(K, V)> {
+        let handle = prev_kv(unsafe { ptr::read(&self.edge) })?;
+        let (key, value) = OccupiedEntry {
+            handle,
+            length: &mut *self.length,
+            pool: &mut *self.pool,
+            _marker: PhantomData,
+        }
+        .remove_kv();
+        self.seek(&key, false);
+        Some((key, value))
+    }
+
+    /// Inserts the pair in the gap of the cursor, returning a pointer to the key
+    /// in the map. The edge of the cursor is invalidated on success.
+    fn try_insert(&mut self, key: K, value: V) -> Result<*const K, TryReserveError> {
+        let cursor = self.as_cursor();
+        if let Some((prev, _)) = cursor.peek_prev() {
+            if key <= *prev {
+                panic!("key must be ordered above the previous element");
+            }
+        }
+        if let Some((next, _)) = cursor.peek_next() {
+            if key >= *next {
+                panic!("key must be ordered below the next element");
+            }
+        }
+
+        if self.edge.reborrow().into_node().is_shared_root() {
+            let root = unsafe { ptr::read(&self.edge) }.into_node().into_root_mut();
+            self.pool.try_reserve(1, 0)?;
+            *root = node::Root::new_leaf_from(self.pool);
+            self.edge = first_leaf_edge(root.as_mut());
+        }
+
+        let mut entry = VacantEntry {
+            key,
+            handle: unsafe { ptr::read(&self.edge) },
+            length: &mut *self.length,
+            pool: &mut *self.pool,
+            _marker: PhantomData,
+        };
+        entry.reserve()?;
+        Ok(entry.insert_reserved(value).0)
+    }
+
+    /// Moves the cursor to the gap before the element of the map equal to
+    /// `key` if `before` is true, after it otherwise, or to the gap where
+    /// `key` would be if there is no such element.
+    fn seek(&mut self, key: &K, before: bool) {
+        // The tree may have been reorganized, but the root stays in place.
+        let root = unsafe { ptr::read(&self.edge) }.into_node().into_root_mut();
+        self.edge = match search::search_tree(root.as_mut(), key) {
+            Found(kv) if before => prev_leaf_edge(kv),
+            Found(kv) => next_leaf_edge(kv),
+            GoDown(edge) => edge,
+        };
+    }
+}
+
+/// An iterator produced by calling `extract_if` on BTreeMap.
+pub struct ExtractIf<'a, K: 'a, V: 'a, F, A: Allocator = Global>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    cursor: CursorMut<'a, K, V, A>,
+    pred: F,
+}
+
+impl<K: Debug, V: Debug, F, A: Allocator> Debug for ExtractIf<'_, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ExtractIf")
+            .field(&self.cursor.as_cursor().peek_next())
+            .finish()
+    }
+}
+
+impl<K: Ord, V, F, A: Allocator> Iterator for ExtractIf<'_, K, V, F, A>
+where
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let (k, v) = self.cursor.peek_next()?;
+            if (self.pred)(k, v) {
+                return self.cursor.remove_next();
+            }
+            self.cursor.next();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(*self.cursor.length))
+    }
+}
+
+impl<K: Ord, V, F, A: Allocator> FusedIterator for ExtractIf<'_, K, V, F, A> where
+    F: FnMut(&K, &mut V) -> bool
+{
+}
+
 enum UnderflowResult<'a, K, V> {
     AtRoot,
-    EmptyParent(NodeRef<marker::Mut<'a>, K, V, marker::Internal>),
+    EmptyParent,
     Merged(NodeRef<marker::Mut<'a>, K, V, marker::Internal>),
-    Stole(NodeRef<marker::Mut<'a>, K, V, marker::Internal>),
+    Stole,
 }
 
-fn handle_underfull_node<'a, K, V>(
+fn handle_underfull_node<'a, K, V, A: Allocator>(
     node: NodeRef<marker::Mut<'a>, K, V, marker::LeafOrInternal>,
+    pool: &mut node::NodePool<K, V, A>,
 ) -> UnderflowResult<'a, K, V> {
     let parent = if let Ok(parent) = node.ascend() {
         parent
@@ -2641,21 +3678,46 @@ fn handle_underfull_node<'a, K, V>(
         Ok(left) => (true, left),
         Err(parent) => match parent.right_kv() {
             Ok(right) => (false, right),
-            Err(parent) => {
-                return EmptyParent(parent.into_node());
-            }
+            Err(_) => return EmptyParent,
         },
     };
 
     if handle.can_merge() {
-        Merged(handle.merge().into_node())
+        Merged(handle.merge(pool).into_node())
     } else {
         if is_left {
             handle.steal_left();
         } else {
             handle.steal_right();
         }
-        Stole(handle.into_node())
+        Stole
+    }
+}
+
+/// Iterator over the pairs of a sorted iterator, keeping only the last pair
+/// among the ones with equal keys.
+struct DedupSortedIter<K, V, I: Iterator<Item = (K, V)>> {
+    iter: Peekable<I>,
+}
+
+impl<K: Ord, V, I: Iterator<Item = (K, V)>> Iterator for DedupSortedIter<K, V, I> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let next = self.iter.next()?;
+            let peeked = match self.iter.peek() {
+                Some(peeked) => peeked,
+                None => return Some(next),
+            };
+            match next.0.cmp(&peeked.0) {
+                Ordering::Less => return Some(next),
+                Ordering::Equal => {}
+                Ordering::Greater => {
+                    panic!("keys are not sorted in BTreeMap::try_from_sorted_iter")
+                }
+            }
+        }
     }
 }
 
@@ -2664,7 +3726,7 @@ impl<K: Ord, V, I: Iterator<Item = (K, V)>> Iterator for MergeIter<K, V, I> {
 
     fn next(&mut self) -> Option<(K, V)> {
         let res = match (self.left.peek(), self.right.peek()) {
-            (Some(&(ref left_key, _)), Some(&(ref right_key, _))) => left_key.cmp(right_key),
+            (Some((left_key, _)), Some((right_key, _))) => left_key.cmp(right_key),
             (Some(_), None) => Ordering::Less,
             (None, Some(_)) => Ordering::Greater,
             (None, None) => return None,
@@ -2682,3 +3744,418 @@ impl<K: Ord, V, I: Iterator<Item = (K, V)>> Iterator for MergeIter<K, V, I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_insert_splits() {
+        let mut map = BTreeMap::new();
+        // enough keys for the root to be split several times
+        for i in (0..2000).rev() {
+            assert_eq!(map.try_insert(i, i * 2).unwrap(), None);
+        }
+        assert_eq!(map.len(), 2000);
+        assert!(map
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..2000).map(|i| (i, i * 2))));
+        map.check();
+        assert_eq!(map.try_insert(1000, 0).unwrap(), Some(2000));
+    }
+
+    #[test]
+    fn try_insert_give_back() {
+        let mut map = BTreeMap::new();
+        for i in 0..500 {
+            assert_eq!(map.try_insert_give_back(i, i.to_string()).unwrap(), None);
+        }
+        assert_eq!(map.len(), 500);
+        assert_eq!(
+            map.try_insert_give_back(7, String::from("seven")).unwrap(),
+            Some(String::from("7"))
+        );
+        assert_eq!(map[&7], "seven");
+        if let Vacant(v) = map.try_entry(1000).unwrap() {
+            v.try_insert_give_back(String::from("1000")).unwrap();
+        }
+        assert_eq!(map.len(), 501);
+    }
+
+    #[test]
+    fn try_append() {
+        for &(a, b) in &[(1, 1), (11, 1), (100, 100), (1000, 3000), (3000, 1000)] {
+            let mut left = BTreeMap::new();
+            for i in 0..a {
+                left.try_insert(i * 2, i).unwrap();
+            }
+            let mut right = BTreeMap::new();
+            for i in 0..b {
+                right.try_insert(i * 3, i).unwrap();
+            }
+            let mut expected = std::collections::BTreeMap::new();
+            expected.extend(left.iter().map(|(k, v)| (*k, *v)));
+            expected.extend(right.iter().map(|(k, v)| (*k, *v)));
+
+            left.try_append(&mut right).unwrap();
+            left.check();
+            assert!(right.is_empty());
+            assert_eq!(left.len(), expected.len());
+            assert!(left.iter().eq(expected.iter()));
+            left.try_insert(-1, 0).unwrap();
+            assert_eq!(left.remove(&-1), Some(0));
+        }
+    }
+
+    #[test]
+    fn try_collect_and_try_clone() {
+        use crate::TryCollect;
+
+        let mut map: BTreeMap<u32, u32> = (0..300).rev().map(|i| (i, i * 2)).try_collect().unwrap();
+        map.try_extend((300..600).map(|i| (i, i * 2))).unwrap();
+        let clone = map.try_clone().unwrap();
+        assert_eq!(clone, map);
+        assert!(clone
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..600).map(|i| (i, i * 2))));
+    }
+
+    #[test]
+    fn pop_first_and_last() {
+        let mut map = BTreeMap::new();
+        for i in 0..1000 {
+            map.try_insert(i, i * 2).unwrap();
+        }
+        for i in 0..500 {
+            assert_eq!(map.first_key_value(), Some((&i, &(i * 2))));
+            assert_eq!(map.pop_first(), Some((i, i * 2)));
+            assert_eq!(map.last_key_value(), Some((&(999 - i), &((999 - i) * 2))));
+            assert_eq!(map.pop_last(), Some((999 - i, (999 - i) * 2)));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.pop_first(), None);
+        assert_eq!(map.pop_last(), None);
+        assert_eq!(map.first_key_value(), None);
+    }
+
+    #[test]
+    fn retain_and_extract_if() {
+        let mut map = BTreeMap::new();
+        for i in 0..1000 {
+            map.try_insert(i, i).unwrap();
+        }
+        map.retain(|k, v| {
+            *v += 1;
+            k % 3 != 0
+        });
+        assert_eq!(map.len(), 666);
+        assert!(map
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..1000).filter(|k| k % 3 != 0).map(|k| (k, k + 1))));
+
+        let extracted: Vec<_> = map.extract_if(|k, _| k % 2 == 0).collect();
+        assert!(extracted.into_iter().eq((0..1000)
+            .filter(|k| k % 3 != 0 && k % 2 == 0)
+            .map(|k| (k, k + 1))));
+        assert!(map
+            .keys()
+            .copied()
+            .eq((0..1000).filter(|k| k % 3 != 0 && k % 2 != 0)));
+
+        // the elements not visited are kept
+        assert_eq!(map.extract_if(|_, _| true).next(), Some((1, 2)));
+        assert_eq!(map.len(), 332);
+        map.retain(|_, _| false);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn cursor_mut_matches_std() {
+        let mut map = BTreeMap::new();
+        let mut expected = std::collections::BTreeMap::new();
+        let mut cursor = map.lower_bound_mut(Unbounded);
+        // insert increasing keys at the front and back of the gap
+        for i in 0..600 {
+            cursor.try_insert_before(i * 4, i).unwrap();
+            expected.insert(i * 4, i);
+        }
+        assert_eq!(cursor.peek_next(), None);
+        assert_eq!(cursor.peek_prev(), Some((&2396, &mut 599)));
+        assert!(map.iter().map(|(k, v)| (*k, *v)).eq(expected.clone()));
+
+        let mut state = 0x2545_f491u32;
+        let mut cursor = map.lower_bound_mut(Included(&1200));
+        let mut model = expected.range(1200..).next().map(|(k, _)| *k);
+        for _ in 0..5000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            match state % 6 {
+                0 => {
+                    let removed = cursor.remove_next();
+                    assert_eq!(removed, model.map(|k| (k, expected.remove(&k).unwrap())));
+                    model = removed.and_then(|(k, _)| expected.range(k..).next().map(|(k, _)| *k));
+                }
+                1 => {
+                    let removed = cursor.remove_prev();
+                    let prev = match model {
+                        Some(k) => expected.range(..k).next_back(),
+                        None => expected.iter().next_back(),
+                    }
+                    .map(|(k, _)| *k);
+                    assert_eq!(removed, prev.map(|k| (k, expected.remove(&k).unwrap())));
+                }
+                2 | 3 => {
+                    let moved = cursor.next().map(|(k, v)| (*k, *v));
+                    assert_eq!(moved, model.map(|k| (k, expected[&k])));
+                    if let Some(k) = model {
+                        model = expected.range(k + 1..).next().map(|(k, _)| *k);
+                    }
+                }
+                4 => {
+                    let moved = cursor.prev().map(|(k, v)| (*k, *v));
+                    let prev = match model {
+                        Some(k) => expected.range(..k).next_back(),
+                        None => expected.iter().next_back(),
+                    }
+                    .map(|(k, _)| *k);
+                    assert_eq!(moved, prev.map(|k| (k, expected[&k])));
+                    if let Some(k) = prev {
+                        model = Some(k);
+                    }
+                }
+                _ => {
+                    // insert in the gap when there is room for a new key
+                    let prev = cursor.peek_prev().map(|(k, _)| *k);
+                    let next = cursor.peek_next().map(|(k, _)| *k);
+                    let key = prev.map_or(-1, |k| k + 1);
+                    if next.map_or(true, |n| key < n) && key >= 0 {
+                        if state % 2 == 0 {
+                            cursor.try_insert_after(key, 0).unwrap();
+                            model = Some(key);
+                        } else {
+                            cursor.try_insert_before(key, 0).unwrap();
+                        }
+                        expected.insert(key, 0);
+                    }
+                }
+            }
+            assert_eq!(*cursor.length, expected.len());
+        }
+        assert!(map.iter().map(|(k, v)| (*k, *v)).eq(expected));
+    }
+
+    #[test]
+    fn cursor_bounds() {
+        let mut map = BTreeMap::new();
+        assert_eq!(map.lower_bound(Included(&3)).peek_next(), None);
+        map.lower_bound_mut(Unbounded)
+            .try_insert_after(500, "last")
+            .unwrap();
+        for i in (0..200).filter(|i| i % 2 == 0) {
+            map.try_insert(i, "even").unwrap();
+        }
+        for i in 0..199 {
+            let lower = map.lower_bound(Included(&i));
+            let key = i + i % 2;
+            assert_eq!(lower.peek_next().map(|(k, _)| *k), Some(key));
+            let upper = map.upper_bound(Excluded(&i));
+            assert_eq!(upper.peek_next().map(|(k, _)| *k), Some(key));
+            let mut upper = map.upper_bound(Included(&i));
+            assert_eq!(upper.prev().map(|(k, _)| *k), Some(i - i % 2));
+        }
+        assert_eq!(
+            map.upper_bound(Unbounded).peek_prev(),
+            Some((&500, &"last"))
+        );
+    }
+
+    #[test]
+    fn custom_allocator() {
+        use crate::allocator::tests::Bounded;
+
+        let alloc = Bounded::new(40);
+        let mut map = BTreeMap::new_in(&alloc);
+        let mut len = 0;
+        while map.try_insert(len, len).is_ok() {
+            len += 1;
+        }
+        map.check();
+        assert_eq!(map.len(), len);
+        assert_eq!(alloc.live(), 40);
+        assert!(map.try_clone().is_err());
+        assert!(map.split_off(&(len / 2)).is_err());
+        assert_eq!(map.len(), len);
+        map.clear();
+        assert_eq!(alloc.live(), 0);
+
+        map.try_extend((0..100).map(|i| (i, i))).unwrap();
+        let mut right = map.split_off(&50).unwrap();
+        let mut clone = right.try_clone().unwrap();
+        right.try_append(&mut clone).unwrap();
+        map.try_append(&mut right).unwrap();
+        map.check();
+        assert!(map.keys().copied().eq(0..100));
+        drop((map, right, clone));
+        assert_eq!(alloc.live(), 0);
+
+        let map = BTreeMap::try_from_sorted_iter_in((0..1000).map(|i| (i, i)), &alloc);
+        assert!(map.is_err());
+        assert_eq!(alloc.live(), 0);
+        let map = BTreeMap::try_from_sorted_iter_in((0..100).map(|i| (i, i)), &alloc).unwrap();
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next(), Some((0, 0)));
+        drop(iter);
+        assert_eq!(alloc.live(), 0);
+    }
+
+    #[test]
+    fn try_from_sorted_iter() {
+        for len in [0, 1, 11, 12, 100, 144, 1000, 5000] {
+            let map = BTreeMap::try_from_sorted_iter((0..len).map(|i| (i, i * 2))).unwrap();
+            assert_eq!(map.len(), len);
+            assert!(map
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .eq((0..len).map(|i| (i, i * 2))));
+            // the right edge was rebalanced, removing from it keeps the tree valid
+            let mut map = map;
+            for i in (0..len).rev() {
+                assert_eq!(map.pop_last(), Some((i, i * 2)));
+                assert_eq!(map.first_key_value().map(|(k, _)| *k), (i > 0).then_some(0));
+            }
+        }
+        let map = BTreeMap::try_from_sorted_iter((0..300).map(|i| (i / 3, i))).unwrap();
+        assert!(map
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..100).map(|i| (i, i * 3 + 2))));
+    }
+
+    #[test]
+    #[should_panic(expected = "keys are not sorted")]
+    fn try_from_sorted_iter_unsorted() {
+        let _ = BTreeMap::try_from_sorted_iter(vec![(1, ()), (3, ()), (2, ())]);
+    }
+
+    #[test]
+    fn try_reserve_detailed() {
+        let mut map = BTreeMap::new();
+        map.try_insert(1, ()).unwrap();
+        let e = map.try_reserve_detailed(usize::MAX).unwrap_err();
+        assert_eq!(e.kind(), crate::ReserveErrorKind::CapacityOverflow);
+        assert_eq!(e.collection(), Some(CollectionKind::BTreeMap));
+        assert_eq!(e.len(), Some(usize::MAX));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn try_reserve_does_not_allocate() {
+        // the nodes of the tree and of the pool, which only change on allocation
+        fn total_nodes(map: &BTreeMap<u32, ()>) -> (usize, usize) {
+            let (leaves, internals) = map.node_counts();
+            let (free_leaves, free_internals) = map.pool.free_nodes();
+            (leaves + free_leaves, internals + free_internals)
+        }
+
+        let mut seed = 0x2545_f491u32;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for &(initial, additional) in &[(0, 1), (0, 11), (0, 500), (300, 0), (300, 1000)] {
+            let mut map = BTreeMap::new();
+            for _ in 0..initial {
+                map.try_insert(rand(), ()).unwrap();
+            }
+            map.try_reserve(additional).unwrap();
+            let reserved = total_nodes(&map);
+
+            // ascending keys split nodes as unevenly as possible
+            let start = map.len() as u32;
+            for i in 0..additional as u32 {
+                map.try_insert(u32::MAX - start - i, ()).unwrap();
+            }
+            assert_eq!(total_nodes(&map), reserved);
+
+            // removals give their nodes back to the pool, or free them once it is full
+            map.try_reserve(additional).unwrap();
+            let mut reserved = total_nodes(&map);
+            for _ in 0..additional {
+                map.pop_first();
+                let after_pop = total_nodes(&map);
+                assert!(after_pop.0 <= reserved.0 && after_pop.1 <= reserved.1);
+                map.try_insert(rand(), ()).unwrap();
+                assert_eq!(total_nodes(&map), after_pop);
+                reserved = after_pop;
+            }
+        }
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut map = BTreeMap::new();
+        map.try_reserve(1000).unwrap();
+        for i in 0..200 {
+            map.try_insert(i, ()).unwrap();
+        }
+        assert_ne!(map.pool.free_nodes(), (0, 0));
+        map.shrink_to_fit();
+        assert_eq!(map.pool.free_nodes(), (0, 0));
+        // the nodes freed after shrink_to_fit are deallocated
+        map.retain(|&k, _| k % 10 == 0);
+        assert_eq!(map.pool.free_nodes(), (0, 0));
+        assert!(map.keys().copied().eq((0..200).step_by(10)));
+        map.check();
+    }
+
+    #[test]
+    fn removals_refill_the_pool_up_to_the_reservation() {
+        // without try_reserve, the freed nodes are deallocated
+        let mut map = BTreeMap::new();
+        for i in 0..1000 {
+            map.try_insert(i, ()).unwrap();
+        }
+        map.retain(|&k, _| k % 100 == 0);
+        assert_eq!(map.pool.free_nodes(), (0, 0));
+        while map.pop_first().is_some() {}
+        assert_eq!(map.pool.free_nodes(), (0, 0));
+
+        let mut map = BTreeMap::new();
+        map.try_reserve(100).unwrap();
+        let reserved = map.pool.free_nodes();
+        for i in 0..1000 {
+            map.try_insert(i, ()).unwrap();
+        }
+        assert_eq!(map.pool.free_nodes(), (0, 0));
+        map.retain(|&k, _| k % 100 == 0);
+        map.check();
+        // the pool is full again, and won't grow past the reservation
+        assert_eq!(map.pool.free_nodes(), reserved);
+        while map.pop_first().is_some() {}
+        assert_eq!(map.pool.free_nodes(), reserved);
+        for i in 0..100 {
+            map.try_insert(i, ()).unwrap();
+        }
+        assert_eq!(map.len(), 100);
+    }
+
+    #[test]
+    fn bulk_push_nodes_needed_is_enough() {
+        for len in 0..2000 {
+            let mut map = BTreeMap::new();
+            let (leaves, internals) = bulk_push_nodes_needed(len);
+            map.pool.try_reserve(leaves + 1, internals + 1).unwrap();
+            map.bulk_push((0..len).map(|i| (i, ()))).unwrap();
+            map.fix_right_edge();
+            // the extra nodes were not needed
+            assert_eq!(map.pool.free_nodes(), (1, 1));
+            assert!(map.keys().copied().eq(0..len));
+        }
+    }
+}
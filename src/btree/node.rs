@@ -31,14 +31,16 @@
 // - A node of length `n` has `n` keys, `n` values, and (in an internal node) `n + 1` edges.
 //   This implies that even an empty internal node has at least one edge.
 
+#![allow(clippy::type_complexity)]
+
 use core::marker::PhantomData;
 use core::mem::{self, MaybeUninit};
-use core::ptr::{self, NonNull, Unique};
+use core::ptr::{self, NonNull};
 use core::slice;
 
-use crate::boxed::FallibleBox;
+use crate::allocator::{try_allocate, Allocator, Global};
 use crate::TryReserveError;
-use alloc::alloc::{Allocator, Global, Layout};
+use alloc::alloc::Layout;
 use alloc::boxed::Box;
 
 const B: usize = 6;
@@ -108,8 +110,8 @@ impl<K, V> LeafNode<K, V> {
         LeafNode {
             // As a general policy, we leave fields uninitialized if they can be, as this should
             // be both slightly faster and easier to track in Valgrind.
-            keys: MaybeUninit::uninit_array::<CAPACITY>(),
-            vals: MaybeUninit::uninit_array::<CAPACITY>(),
+            keys: MaybeUninit::uninit().assume_init(),
+            vals: MaybeUninit::uninit().assume_init(),
             parent: ptr::null(),
             parent_idx: MaybeUninit::uninit(),
             len: 0,
@@ -161,7 +163,7 @@ impl<K, V> InternalNode<K, V> {
     unsafe fn new() -> Self {
         InternalNode {
             data: LeafNode::new(),
-            edges: MaybeUninit::uninit_array::<{ 2 * B }>(),
+            edges: MaybeUninit::uninit().assume_init(),
         }
     }
 }
@@ -171,31 +173,29 @@ impl<K, V> InternalNode<K, V> {
 /// of nodes is actually behind the box, and, partially due to this lack of information, has no
 /// destructor.
 struct BoxedNode<K, V> {
-    ptr: Unique<LeafNode<K, V>>,
+    ptr: NonNull<LeafNode<K, V>>,
+    // the node is owned
+    _marker: PhantomData<Box<LeafNode<K, V>>>,
 }
 
 impl<K, V> BoxedNode<K, V> {
-    fn from_leaf(node: Box<LeafNode<K, V>>) -> Self {
-        let (ptr, _g) = Box::into_unique(node);
-        BoxedNode { ptr: ptr }
+    fn from_leaf(node: NonNull<LeafNode<K, V>>) -> Self {
+        unsafe { BoxedNode::from_ptr(node) }
     }
 
-    fn from_internal(node: Box<InternalNode<K, V>>) -> Self {
-        unsafe {
-            BoxedNode {
-                ptr: Unique::new_unchecked(Box::into_raw(node) as *mut LeafNode<K, V>),
-            }
-        }
+    fn from_internal(node: NonNull<InternalNode<K, V>>) -> Self {
+        unsafe { BoxedNode::from_ptr(node.cast()) }
     }
 
     unsafe fn from_ptr(ptr: NonNull<LeafNode<K, V>>) -> Self {
         BoxedNode {
-            ptr: Unique::new_unchecked(ptr.as_ptr()),
+            ptr,
+            _marker: PhantomData,
         }
     }
 
     fn as_ptr(&self) -> NonNull<LeafNode<K, V>> {
-        NonNull::from(self.ptr)
+        self.ptr
     }
 }
 
@@ -225,13 +225,13 @@ impl<K, V> Root<K, V> {
         }
     }
 
-    pub fn new_leaf() -> Result<Self, TryReserveError> {
-        Ok(Root {
-            node: BoxedNode::from_leaf(<Box<_> as FallibleBox<_>>::try_new(unsafe {
-                LeafNode::new()
-            })?),
+    /// Makes a tree of a single empty leaf, taken from `pool`, which must hold a free
+    /// leaf node.
+    pub fn new_leaf_from<A: Allocator>(pool: &mut NodePool<K, V, A>) -> Self {
+        Root {
+            node: BoxedNode::from_leaf(pool.pop_leaf()),
             height: 0,
-        })
+        }
     }
 
     pub fn as_ref(&self) -> NodeRef<marker::Immut<'_>, K, V, marker::LeafOrInternal> {
@@ -263,12 +263,22 @@ impl<K, V> Root<K, V> {
 
     /// Adds a new internal node with a single edge, pointing to the previous root, and make that
     /// new node the root. This increases the height by 1 and is the opposite of `pop_level`.
-    pub fn push_level(
+    /// The new root is taken from `pool`, which must hold a free internal node.
+    pub fn push_level_from<A: Allocator>(
         &mut self,
-    ) -> Result<NodeRef<marker::Mut<'_>, K, V, marker::Internal>, TryReserveError> {
+        pool: &mut NodePool<K, V, A>,
+    ) -> NodeRef<marker::Mut<'_>, K, V, marker::Internal> {
         debug_assert!(!self.is_shared_root());
-        let mut new_node = <Box<_> as FallibleBox<_>>::try_new(unsafe { InternalNode::new() })?;
-        new_node.edges[0].write(unsafe { BoxedNode::from_ptr(self.node.as_ptr()) });
+        self.push_internal_level(pool.pop_internal())
+    }
+
+    fn push_internal_level(
+        &mut self,
+        new_node: NonNull<InternalNode<K, V>>,
+    ) -> NodeRef<marker::Mut<'_>, K, V, marker::Internal> {
+        unsafe {
+            (*new_node.as_ptr()).edges[0].write(BoxedNode::from_ptr(self.node.as_ptr()));
+        }
 
         self.node = BoxedNode::from_internal(new_node);
         self.height += 1;
@@ -284,17 +294,18 @@ impl<K, V> Root<K, V> {
             ret.reborrow_mut().first_edge().correct_parent_link();
         }
 
-        Ok(ret)
+        ret
     }
 
     /// Removes the root node, using its first child as the new root. This cannot be called when
     /// the tree consists only of a leaf node. As it is intended only to be called when the root
     /// has only one edge, no cleanup is done on any of the other children are elements of the root.
-    /// This decreases the height by 1 and is the opposite of `push_level`.
-    pub fn pop_level(&mut self) {
+    /// This decreases the height by 1 and is the opposite of `push_level`. The removed node
+    /// is recycled by `pool`.
+    pub fn pop_level<A: Allocator>(&mut self, pool: &mut NodePool<K, V, A>) {
         debug_assert!(self.height > 0);
 
-        let top = self.node.ptr;
+        let top = self.node.as_ptr();
 
         self.node = unsafe {
             BoxedNode::from_ptr(
@@ -311,14 +322,183 @@ impl<K, V> Root<K, V> {
         }
 
         unsafe {
-            Global.deallocate(
-                NonNull::from(top).cast(),
-                Layout::new::<InternalNode<K, V>>(),
-            );
+            pool.recycle_internal(top.cast());
+        }
+    }
+}
+
+/// Nodes allocated ahead of a modification of the tree, so that no allocation can fail
+/// once the tree has started being changed. The nodes freed by a removal are put back
+/// in the pool for later insertions, as long as it holds less free nodes than `keep`
+/// allows, and are deallocated otherwise. The free nodes are chained through their
+/// `parent` field, and the ones which were not used are deallocated with the pool.
+///
+/// The pool owns the allocator of the tree, all its nodes are allocated and
+/// deallocated through the pool.
+pub struct NodePool<K, V, A: Allocator = Global> {
+    leaves: *mut LeafNode<K, V>,
+    internals: *mut InternalNode<K, V>,
+    free_leaves: usize,
+    free_internals: usize,
+    keep_leaves: usize,
+    keep_internals: usize,
+    alloc: A,
+}
+
+impl<K, V, A: Allocator> NodePool<K, V, A> {
+    pub fn new_in(alloc: A) -> Self {
+        NodePool {
+            leaves: ptr::null_mut(),
+            internals: ptr::null_mut(),
+            free_leaves: 0,
+            free_internals: 0,
+            keep_leaves: 0,
+            keep_internals: 0,
+            alloc,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Deallocates the free nodes, and stops keeping the nodes freed by removals.
+    pub fn release(&mut self) {
+        while self.free_leaves > 0 {
+            let node = self.pop_leaf();
+            unsafe { self.deallocate_leaf(node) };
+        }
+        while self.free_internals > 0 {
+            let node = self.pop_internal();
+            unsafe { self.deallocate_internal(node) };
+        }
+        self.keep_leaves = 0;
+        self.keep_internals = 0;
+    }
+
+    /// Releases the pool and returns its allocator.
+    pub fn into_allocator(mut self) -> A {
+        self.release();
+        let alloc = unsafe { ptr::read(&self.alloc) };
+        mem::forget(self);
+        alloc
+    }
+
+    /// Lets the pool keep up to `leaves` free leaf nodes and `internals` free internal
+    /// nodes freed by removals, if it didn't allow more already.
+    pub fn keep(&mut self, leaves: usize, internals: usize) {
+        self.keep_leaves = self.keep_leaves.max(leaves);
+        self.keep_internals = self.keep_internals.max(internals);
+    }
+
+    /// Allocates nodes until the pool holds at least `leaves` free leaf nodes and
+    /// `internals` free internal nodes. On failure, the nodes already allocated are
+    /// kept in the pool.
+    pub fn try_reserve(&mut self, leaves: usize, internals: usize) -> Result<(), TryReserveError> {
+        while self.free_leaves < leaves {
+            let node = try_allocate(&self.alloc, Layout::new::<LeafNode<K, V>>())?.cast();
+            unsafe {
+                ptr::write(node.as_ptr(), LeafNode::new());
+                self.push_leaf(node);
+            }
+        }
+        while self.free_internals < internals {
+            let node = try_allocate(&self.alloc, Layout::new::<InternalNode<K, V>>())?.cast();
+            unsafe {
+                ptr::write(node.as_ptr(), InternalNode::new());
+                self.push_internal(node);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of free leaf and internal nodes in the pool.
+    #[cfg(test)]
+    pub fn free_nodes(&self) -> (usize, usize) {
+        (self.free_leaves, self.free_internals)
+    }
+
+    /// Keeps a leaf node freed by a removal, or deallocates it if the pool is full.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be an unused leaf node of the tree of this pool.
+    unsafe fn recycle_leaf(&mut self, node: NonNull<LeafNode<K, V>>) {
+        if self.free_leaves < self.keep_leaves {
+            self.push_leaf(node);
+        } else {
+            self.deallocate_leaf(node);
+        }
+    }
+
+    /// Keeps an internal node freed by a removal, or deallocates it if the pool is full.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be an unused internal node of the tree of this pool.
+    unsafe fn recycle_internal(&mut self, node: NonNull<InternalNode<K, V>>) {
+        if self.free_internals < self.keep_internals {
+            self.push_internal(node);
+        } else {
+            self.deallocate_internal(node);
+        }
+    }
+
+    unsafe fn deallocate_leaf(&self, node: NonNull<LeafNode<K, V>>) {
+        self.alloc
+            .deallocate(node.cast(), Layout::new::<LeafNode<K, V>>());
+    }
+
+    unsafe fn deallocate_internal(&self, node: NonNull<InternalNode<K, V>>) {
+        self.alloc
+            .deallocate(node.cast(), Layout::new::<InternalNode<K, V>>());
+    }
+
+    unsafe fn push_leaf(&mut self, node: NonNull<LeafNode<K, V>>) {
+        let leaf = &mut *node.as_ptr();
+        leaf.parent = self.leaves as *const InternalNode<K, V>;
+        leaf.len = 0;
+        self.leaves = node.as_ptr();
+        self.free_leaves += 1;
+    }
+
+    unsafe fn push_internal(&mut self, node: NonNull<InternalNode<K, V>>) {
+        let internal = &mut *node.as_ptr();
+        internal.data.parent = self.internals;
+        internal.data.len = 0;
+        self.internals = node.as_ptr();
+        self.free_internals += 1;
+    }
+
+    fn pop_leaf(&mut self) -> NonNull<LeafNode<K, V>> {
+        assert!(self.free_leaves > 0, "no leaf node reserved");
+        unsafe {
+            let node = NonNull::new_unchecked(self.leaves);
+            self.leaves = (*self.leaves).parent as *mut LeafNode<K, V>;
+            (*node.as_ptr()).parent = ptr::null();
+            self.free_leaves -= 1;
+            node
+        }
+    }
+
+    fn pop_internal(&mut self) -> NonNull<InternalNode<K, V>> {
+        assert!(self.free_internals > 0, "no internal node reserved");
+        unsafe {
+            let node = NonNull::new_unchecked(self.internals);
+            self.internals = (*self.internals).data.parent as *mut InternalNode<K, V>;
+            (*node.as_ptr()).data.parent = ptr::null();
+            self.free_internals -= 1;
+            node
         }
     }
 }
 
+impl<K, V, A: Allocator> Drop for NodePool<K, V, A> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
 // N.B. `NodeRef` is always covariant in `K` and `V`, even when the `BorrowType`
 // is `Mut`. This is technically wrong, but cannot result in any unsafety due to
 // internal use of `NodeRef` because we stay completely generic over `K` and `V`.
@@ -328,9 +508,9 @@ impl<K, V> Root<K, V> {
 ///
 /// This type has a number of parameters that controls how it acts:
 /// - `BorrowType`: This can be `Immut<'a>` or `Mut<'a>` for some `'a` or `Owned`.
-///    When this is `Immut<'a>`, the `NodeRef` acts roughly like `&'a Node`,
-///    when this is `Mut<'a>`, the `NodeRef` acts roughly like `&'a mut Node`,
-///    and when this is `Owned`, the `NodeRef` acts roughly like `Box<Node>`.
+///   When this is `Immut<'a>`, the `NodeRef` acts roughly like `&'a Node`,
+///   when this is `Mut<'a>`, the `NodeRef` acts roughly like `&'a mut Node`,
+///   and when this is `Owned`, the `NodeRef` acts roughly like `Box<Node>`.
 /// - `K` and `V`: These control what types of things are stored in the nodes.
 /// - `Type`: This can be `Leaf`, `Internal`, or `LeafOrInternal`. When this is
 ///   `Leaf`, the `NodeRef` points to a leaf node, when this is `Internal` the
@@ -479,17 +659,44 @@ impl<BorrowType, K, V, Type> NodeRef<BorrowType, K, V, Type> {
     }
 }
 
+impl<BorrowType, K, V> NodeRef<BorrowType, K, V, marker::Leaf> {
+    /// Returns the number of leaf and internal nodes which must be allocated to insert a
+    /// key/value pair into this node, as every full node on the way up to the root gets
+    /// split, and a full root gets a new root pushed on top of it.
+    pub fn nodes_needed_for_insert(self) -> (usize, usize) {
+        if self.len() < CAPACITY {
+            return (0, 0);
+        }
+        let mut internals = 0;
+        let mut node = self.forget_type();
+        loop {
+            match node.ascend() {
+                Ok(parent) => {
+                    let parent = parent.into_node();
+                    if parent.len() < CAPACITY {
+                        return (1, internals);
+                    }
+                    internals += 1;
+                    node = parent.forget_type();
+                }
+                Err(_) => return (1, internals + 1),
+            }
+        }
+    }
+}
+
 impl<K, V> NodeRef<marker::Owned, K, V, marker::Leaf> {
     /// Similar to `ascend`, gets a reference to a node's parent node, but also
     /// deallocate the current node in the process. This is unsafe because the
     /// current node will still be accessible despite being deallocated.
-    pub unsafe fn deallocate_and_ascend(
+    pub unsafe fn deallocate_and_ascend<A: Allocator>(
         self,
+        alloc: &A,
     ) -> Option<Handle<NodeRef<marker::Owned, K, V, marker::Internal>, marker::Edge>> {
         debug_assert!(!self.is_shared_root());
         let node = self.node;
         let ret = self.ascend().ok();
-        Global.deallocate(node.cast(), Layout::new::<LeafNode<K, V>>());
+        alloc.deallocate(node.cast(), Layout::new::<LeafNode<K, V>>());
         ret
     }
 }
@@ -498,12 +705,13 @@ impl<K, V> NodeRef<marker::Owned, K, V, marker::Internal> {
     /// Similar to `ascend`, gets a reference to a node's parent node, but also
     /// deallocate the current node in the process. This is unsafe because the
     /// current node will still be accessible despite being deallocated.
-    pub unsafe fn deallocate_and_ascend(
+    pub unsafe fn deallocate_and_ascend<A: Allocator>(
         self,
+        alloc: &A,
     ) -> Option<Handle<NodeRef<marker::Owned, K, V, marker::Internal>, marker::Edge>> {
         let node = self.node;
         let ret = self.ascend().ok();
-        Global.deallocate(node.cast(), Layout::new::<InternalNode<K, V>>());
+        alloc.deallocate(node.cast(), Layout::new::<InternalNode<K, V>>());
         ret
     }
 }
@@ -552,6 +760,18 @@ impl<'a, K, V, Type> NodeRef<marker::Mut<'a>, K, V, Type> {
     fn vals_mut(&mut self) -> &mut [V] {
         unsafe { self.reborrow_mut().into_val_slice_mut() }
     }
+
+    /// Returns a pointer to the first key which, unlike `keys_mut`, is allowed
+    /// to write past the initialized keys. Must not be called on the shared root.
+    fn keys_mut_ptr(&mut self) -> *mut K {
+        unsafe { ptr::addr_of_mut!((*self.as_leaf_mut()).keys) as *mut K }
+    }
+
+    /// Returns a pointer to the first value which, unlike `vals_mut`, is allowed
+    /// to write past the initialized values. Must not be called on the shared root.
+    fn vals_mut_ptr(&mut self) -> *mut V {
+        unsafe { ptr::addr_of_mut!((*self.as_leaf_mut()).vals) as *mut V }
+    }
 }
 
 impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Immut<'a>, K, V, Type> {
@@ -589,8 +809,10 @@ impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Immut<'a>, K, V, Type> {
 
             // This is a non-debug-assert because it can be completely compile-time evaluated.
             assert!(mem::size_of::<NodeHeader<K, V>>() == mem::size_of::<NodeHeader<K, V, K>>());
-            let header = self.as_header() as *const _ as *const NodeHeader<K, V, K>;
-            let keys = unsafe { &(*header).keys_start as *const _ as *const K };
+            // The pointer is derived from the node itself rather than from `as_header`,
+            // so that it keeps the provenance of the whole node.
+            let header = self.node.as_ptr() as *const NodeHeader<K, V, K>;
+            let keys = unsafe { ptr::addr_of!((*header).keys_start) as *const K };
             unsafe { slice::from_raw_parts(keys, self.len()) }
         }
     }
@@ -598,9 +820,7 @@ impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Immut<'a>, K, V, Type> {
     fn into_val_slice(self) -> &'a [V] {
         debug_assert!(!self.is_shared_root());
         // We cannot be the root, so `as_leaf` is okay
-        unsafe {
-            slice::from_raw_parts(MaybeUninit::slice_as_ptr(&self.as_leaf().vals), self.len())
-        }
+        unsafe { slice::from_raw_parts(slice_as_ptr(&self.as_leaf().vals), self.len()) }
     }
 
     fn into_slices(self) -> (&'a [K], &'a [V]) {
@@ -624,7 +844,7 @@ impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Mut<'a>, K, V, Type> {
         } else {
             unsafe {
                 slice::from_raw_parts_mut(
-                    MaybeUninit::slice_as_mut_ptr(&mut (*self.as_leaf_mut()).keys),
+                    slice_as_mut_ptr(&mut (*self.as_leaf_mut()).keys),
                     self.len(),
                 )
             }
@@ -635,7 +855,7 @@ impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Mut<'a>, K, V, Type> {
         debug_assert!(!self.is_shared_root());
         unsafe {
             slice::from_raw_parts_mut(
-                MaybeUninit::slice_as_mut_ptr(&mut (*self.as_leaf_mut()).vals),
+                slice_as_mut_ptr(&mut (*self.as_leaf_mut()).vals),
                 self.len(),
             )
         }
@@ -651,10 +871,8 @@ impl<'a, K: 'a, V: 'a, Type> NodeRef<marker::Mut<'a>, K, V, Type> {
         unsafe {
             let len = self.len();
             let leaf = self.as_leaf_mut();
-            let keys =
-                slice::from_raw_parts_mut(MaybeUninit::slice_as_mut_ptr(&mut (*leaf).keys), len);
-            let vals =
-                slice::from_raw_parts_mut(MaybeUninit::slice_as_mut_ptr(&mut (*leaf).vals), len);
+            let keys = slice::from_raw_parts_mut(slice_as_mut_ptr(&mut (*leaf).keys), len);
+            let vals = slice::from_raw_parts_mut(slice_as_mut_ptr(&mut (*leaf).vals), len);
             (keys, vals)
         }
     }
@@ -670,8 +888,8 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::Leaf> {
         let idx = self.len();
 
         unsafe {
-            ptr::write(self.keys_mut().as_mut_ptr().add(idx), key);
-            ptr::write(self.vals_mut().as_mut_ptr().add(idx), val);
+            ptr::write(self.keys_mut_ptr().add(idx), key);
+            ptr::write(self.vals_mut_ptr().add(idx), val);
 
             (*self.as_leaf_mut()).len += 1;
         }
@@ -683,9 +901,11 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::Leaf> {
         debug_assert!(self.len() < CAPACITY);
         debug_assert!(!self.is_shared_root());
 
+        let len = self.len();
+
         unsafe {
-            slice_insert(self.keys_mut(), 0, key);
-            slice_insert(self.vals_mut(), 0, val);
+            slice_insert(self.keys_mut_ptr(), len, 0, key);
+            slice_insert(self.vals_mut_ptr(), len, 0, val);
 
             (*self.as_leaf_mut()).len += 1;
         }
@@ -703,8 +923,8 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::Internal> {
         let idx = self.len();
 
         unsafe {
-            ptr::write(self.keys_mut().as_mut_ptr().add(idx), key);
-            ptr::write(self.vals_mut().as_mut_ptr().add(idx), val);
+            ptr::write(self.keys_mut_ptr().add(idx), key);
+            ptr::write(self.vals_mut_ptr().add(idx), val);
             self.as_internal_mut()
                 .edges
                 .as_mut_ptr()
@@ -737,14 +957,14 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::Internal> {
         debug_assert!(edge.height == self.height - 1);
         debug_assert!(self.len() < CAPACITY);
 
+        let len = self.len();
+
         unsafe {
-            slice_insert(self.keys_mut(), 0, key);
-            slice_insert(self.vals_mut(), 0, val);
+            slice_insert(self.keys_mut_ptr(), len, 0, key);
+            slice_insert(self.vals_mut_ptr(), len, 0, val);
             slice_insert(
-                slice::from_raw_parts_mut(
-                    MaybeUninit::slice_as_mut_ptr(&mut self.as_internal_mut().edges),
-                    self.len() + 1,
-                ),
+                slice_as_mut_ptr(&mut self.as_internal_mut().edges),
+                len + 1,
                 0,
                 edge.node,
             );
@@ -803,7 +1023,7 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::LeafOrInternal> {
                 ForceResult::Internal(mut internal) => {
                     let edge = slice_remove(
                         slice::from_raw_parts_mut(
-                            MaybeUninit::slice_as_mut_ptr(&mut internal.as_internal_mut().edges),
+                            slice_as_mut_ptr(&mut internal.as_internal_mut().edges),
                             old_len + 1,
                         ),
                         0,
@@ -830,7 +1050,7 @@ impl<'a, K, V> NodeRef<marker::Mut<'a>, K, V, marker::LeafOrInternal> {
     }
 
     fn into_kv_pointers_mut(mut self) -> (*mut K, *mut V) {
-        (self.keys_mut().as_mut_ptr(), self.vals_mut().as_mut_ptr())
+        (self.keys_mut_ptr(), self.vals_mut_ptr())
     }
 }
 
@@ -957,6 +1177,22 @@ impl<'a, K, V, NodeType, HandleType> Handle<NodeRef<marker::Mut<'a>, K, V, NodeT
     }
 }
 
+impl<BorrowType, K, V, NodeType, HandleType>
+    Handle<NodeRef<BorrowType, K, V, NodeType>, HandleType>
+{
+    /// Removes any static information about whether the node of this handle is a
+    /// `Leaf` or an `Internal` node.
+    pub fn forget_node_type(
+        self,
+    ) -> Handle<NodeRef<BorrowType, K, V, marker::LeafOrInternal>, HandleType> {
+        Handle {
+            node: self.node.forget_type(),
+            idx: self.idx,
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<BorrowType, K, V, NodeType> Handle<NodeRef<BorrowType, K, V, NodeType>, marker::Edge> {
     /// Creates a new handle to an edge in `node`. `idx` must be less than or equal to
     /// `node.len()`.
@@ -993,37 +1229,45 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge
     /// this edge. This method assumes that there is enough space in the node for the new
     /// pair to fit.
     ///
-    /// The returned pointer points to the inserted value.
-    fn insert_fit(&mut self, key: K, val: V) -> *mut V {
+    /// The returned pointers point to the inserted key and value.
+    fn insert_fit(&mut self, key: K, val: V) -> (*mut K, *mut V) {
         // Necessary for correctness, but in a private module
         debug_assert!(self.node.len() < CAPACITY);
         debug_assert!(!self.node.is_shared_root());
 
+        let len = self.node.len();
+
         unsafe {
-            slice_insert(self.node.keys_mut(), self.idx, key);
-            slice_insert(self.node.vals_mut(), self.idx, val);
+            slice_insert(self.node.keys_mut_ptr(), len, self.idx, key);
+            slice_insert(self.node.vals_mut_ptr(), len, self.idx, val);
 
             (*self.node.as_leaf_mut()).len += 1;
 
-            self.node.vals_mut().as_mut_ptr().add(self.idx)
+            (
+                self.node.keys_mut_ptr().add(self.idx),
+                self.node.vals_mut_ptr().add(self.idx),
+            )
         }
     }
 
     /// Inserts a new key/value pair between the key/value pairs to the right and left of
-    /// this edge. This method splits the node if there isn't enough room.
+    /// this edge. This method splits the node if there isn't enough room, taking the new
+    /// node from `pool`.
     ///
-    /// The returned pointer points to the inserted value.
-    pub fn insert(
+    /// The returned pointers point to the inserted key and value, which always stay
+    /// in a leaf node, even when the parents get split in turn.
+    pub fn insert<A: Allocator>(
         mut self,
         key: K,
         val: V,
-    ) -> Result<(InsertResult<'a, K, V, marker::Leaf>, *mut V), TryReserveError> {
+        pool: &mut NodePool<K, V, A>,
+    ) -> (InsertResult<'a, K, V, marker::Leaf>, (*mut K, *mut V)) {
         if self.node.len() < CAPACITY {
             let ptr = self.insert_fit(key, val);
-            Ok((InsertResult::Fit(Handle::new_kv(self.node, self.idx)), ptr))
+            (InsertResult::Fit(Handle::new_kv(self.node, self.idx)), ptr)
         } else {
             let middle = Handle::new_kv(self.node, B);
-            let (mut left, k, v, mut right) = middle.split()?;
+            let (mut left, k, v, mut right) = middle.split(pool);
             let ptr = if self.idx <= B {
                 unsafe { Handle::new_edge(left.reborrow_mut(), self.idx).insert_fit(key, val) }
             } else {
@@ -1035,7 +1279,7 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge
                     .insert_fit(key, val)
                 }
             };
-            Ok((InsertResult::Split(left, k, v, right), ptr))
+            (InsertResult::Split(left, k, v, right), ptr)
         }
     }
 }
@@ -1043,9 +1287,9 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::Edge
 impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::Edge> {
     /// Fixes the parent pointer and index in the child node below this edge. This is useful
     /// when the ordering of edges has been changed, such as in the various `insert` methods.
-    fn correct_parent_link(mut self) {
+    fn correct_parent_link(self) {
         let idx = self.idx as u16;
-        let ptr = self.node.as_internal_mut() as *mut _;
+        let ptr = self.node.node.as_ptr() as *mut InternalNode<K, V>;
         let mut child = self.descend();
         unsafe {
             (*child.as_leaf_mut()).parent = ptr;
@@ -1074,10 +1318,8 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
             self.cast_unchecked::<marker::Leaf>().insert_fit(key, val);
 
             slice_insert(
-                slice::from_raw_parts_mut(
-                    MaybeUninit::slice_as_mut_ptr(&mut self.node.as_internal_mut().edges),
-                    self.node.len(),
-                ),
+                slice_as_mut_ptr(&mut self.node.as_internal_mut().edges),
+                self.node.len(),
                 self.idx + 1,
                 edge.node,
             );
@@ -1090,22 +1332,23 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
 
     /// Inserts a new key/value pair and an edge that will go to the right of that new pair
     /// between this edge and the key/value pair to the right of this edge. This method splits
-    /// the node if there isn't enough room.
-    pub fn insert(
+    /// the node if there isn't enough room, taking the new node from `pool`.
+    pub fn insert<A: Allocator>(
         mut self,
         key: K,
         val: V,
         edge: Root<K, V>,
-    ) -> Result<InsertResult<'a, K, V, marker::Internal>, TryReserveError> {
+        pool: &mut NodePool<K, V, A>,
+    ) -> InsertResult<'a, K, V, marker::Internal> {
         // Necessary for correctness, but this is an internal module
         debug_assert!(edge.height == self.node.height - 1);
 
         if self.node.len() < CAPACITY {
             self.insert_fit(key, val, edge);
-            Ok(InsertResult::Fit(Handle::new_kv(self.node, self.idx)))
+            InsertResult::Fit(Handle::new_kv(self.node, self.idx))
         } else {
             let middle = Handle::new_kv(self.node, B);
-            let (mut left, k, v, mut right) = middle.split()?;
+            let (mut left, k, v, mut right) = middle.split(pool);
             if self.idx <= B {
                 unsafe {
                     Handle::new_edge(left.reborrow_mut(), self.idx).insert_fit(key, val, edge);
@@ -1119,7 +1362,7 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
                     .insert_fit(key, val, edge);
                 }
             }
-            Ok(InsertResult::Split(left, k, v, right))
+            InsertResult::Split(left, k, v, right)
         }
     }
 }
@@ -1184,22 +1427,20 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::KV>
     /// - The node is truncated to only contain the key/value pairs to the right of
     ///   this handle.
     /// - The key and value pointed to by this handle and extracted.
-    /// - All the key/value pairs to the right of this handle are put into a newly
-    ///   allocated node.
-    pub fn split(
+    /// - All the key/value pairs to the right of this handle are put into a new node
+    ///   taken from `pool`.
+    pub fn split<A: Allocator>(
         mut self,
-    ) -> Result<
-        (
-            NodeRef<marker::Mut<'a>, K, V, marker::Leaf>,
-            K,
-            V,
-            Root<K, V>,
-        ),
-        TryReserveError,
-    > {
+        pool: &mut NodePool<K, V, A>,
+    ) -> (
+        NodeRef<marker::Mut<'a>, K, V, marker::Leaf>,
+        K,
+        V,
+        Root<K, V>,
+    ) {
         debug_assert!(!self.node.is_shared_root());
         unsafe {
-            let mut new_node = <Box<_> as FallibleBox<_>>::try_new(LeafNode::new())?;
+            let new_node = &mut *pool.pop_leaf().as_ptr();
 
             let k = ptr::read(self.node.keys().get_unchecked(self.idx));
             let v = ptr::read(self.node.vals().get_unchecked(self.idx));
@@ -1220,15 +1461,15 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Leaf>, marker::KV>
             (*self.node.as_leaf_mut()).len = self.idx as u16;
             new_node.len = new_len as u16;
 
-            Ok((
+            (
                 self.node,
                 k,
                 v,
                 Root {
-                    node: BoxedNode::from_leaf(new_node),
+                    node: BoxedNode::from_leaf(NonNull::from(new_node)),
                     height: 0,
                 },
-            ))
+            )
         }
     }
 
@@ -1258,20 +1499,18 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
     ///   right of this handle.
     /// - The key and value pointed to by this handle and extracted.
     /// - All the edges and key/value pairs to the right of this handle are put into
-    ///   a newly allocated node.
-    pub fn split(
+    ///   a new node taken from `pool`.
+    pub fn split<A: Allocator>(
         mut self,
-    ) -> Result<
-        (
-            NodeRef<marker::Mut<'a>, K, V, marker::Internal>,
-            K,
-            V,
-            Root<K, V>,
-        ),
-        TryReserveError,
-    > {
+        pool: &mut NodePool<K, V, A>,
+    ) -> (
+        NodeRef<marker::Mut<'a>, K, V, marker::Internal>,
+        K,
+        V,
+        Root<K, V>,
+    ) {
         unsafe {
-            let mut new_node = <Box<_> as FallibleBox<_>>::try_new(InternalNode::new())?;
+            let new_node = &mut *pool.pop_internal().as_ptr();
 
             let k = ptr::read(self.node.keys().get_unchecked(self.idx));
             let v = ptr::read(self.node.vals().get_unchecked(self.idx));
@@ -1299,7 +1538,7 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
             new_node.data.len = new_len as u16;
 
             let mut new_root = Root {
-                node: BoxedNode::from_internal(new_node),
+                node: BoxedNode::from_internal(NonNull::from(new_node)),
                 height,
             };
 
@@ -1307,7 +1546,7 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
                 Handle::new_edge(new_root.as_mut().cast_unchecked(), i).correct_parent_link();
             }
 
-            Ok((self.node, k, v, new_root))
+            (self.node, k, v, new_root)
         }
     }
 
@@ -1325,9 +1564,11 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
     /// to by this handle, and the node immediately to the right of this handle into one new
     /// child of the underlying node, returning an edge referencing that new child.
     ///
-    /// Assumes that this edge `.can_merge()`.
-    pub fn merge(
+    /// Assumes that this edge `.can_merge()`. The right child, which is no longer used, is
+    /// recycled by `pool`.
+    pub fn merge<A: Allocator>(
         mut self,
+        pool: &mut NodePool<K, V, A>,
     ) -> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::Edge> {
         let self1 = unsafe { ptr::read(&self) };
         let self2 = unsafe { ptr::read(&self) };
@@ -1337,25 +1578,25 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
         let right_len = right_node.len();
 
         // necessary for correctness, but in a private module
-        debug_assert!(left_len + right_len + 1 <= CAPACITY);
+        debug_assert!(left_len + right_len < CAPACITY);
 
         unsafe {
             ptr::write(
-                left_node.keys_mut().as_mut_ptr().add(left_len),
+                left_node.keys_mut_ptr().add(left_len),
                 slice_remove(self.node.keys_mut(), self.idx),
             );
             ptr::copy_nonoverlapping(
                 right_node.keys().as_ptr(),
-                left_node.keys_mut().as_mut_ptr().add(left_len + 1),
+                left_node.keys_mut_ptr().add(left_len + 1),
                 right_len,
             );
             ptr::write(
-                left_node.vals_mut().as_mut_ptr().add(left_len),
+                left_node.vals_mut_ptr().add(left_len),
                 slice_remove(self.node.vals_mut(), self.idx),
             );
             ptr::copy_nonoverlapping(
                 right_node.vals().as_ptr(),
-                left_node.vals_mut().as_mut_ptr().add(left_len + 1),
+                left_node.vals_mut_ptr().add(left_len + 1),
                 right_len,
             );
 
@@ -1384,9 +1625,9 @@ impl<'a, K, V> Handle<NodeRef<marker::Mut<'a>, K, V, marker::Internal>, marker::
                         .correct_parent_link();
                 }
 
-                Global.deallocate(right_node.node.cast(), Layout::new::<InternalNode<K, V>>());
+                pool.recycle_internal(right_node.node.cast());
             } else {
-                Global.deallocate(right_node.node.cast(), Layout::new::<LeafNode<K, V>>());
+                pool.recycle_leaf(right_node.node);
             }
 
             Handle::new_edge(self.node, self.idx)
@@ -1659,21 +1900,25 @@ pub mod marker {
     pub enum Edge {}
 }
 
-unsafe fn slice_insert<T>(slice: &mut [T], idx: usize, val: T) {
-    ptr::copy(
-        slice.as_ptr().add(idx),
-        slice.as_mut_ptr().add(idx + 1),
-        slice.len() - idx,
-    );
-    ptr::write(slice.as_mut_ptr().add(idx), val);
+fn slice_as_ptr<T>(slice: &[MaybeUninit<T>]) -> *const T {
+    slice.as_ptr() as *const T
+}
+
+fn slice_as_mut_ptr<T>(slice: &mut [MaybeUninit<T>]) -> *mut T {
+    slice.as_mut_ptr() as *mut T
+}
+
+/// Inserts `val` at `idx` in the `len` initialized elements starting at `ptr`,
+/// which must have room for one more element.
+unsafe fn slice_insert<T>(ptr: *mut T, len: usize, idx: usize, val: T) {
+    ptr::copy(ptr.add(idx), ptr.add(idx + 1), len - idx);
+    ptr::write(ptr.add(idx), val);
 }
 
 unsafe fn slice_remove<T>(slice: &mut [T], idx: usize) -> T {
-    let ret = ptr::read(slice.get_unchecked(idx));
-    ptr::copy(
-        slice.as_ptr().add(idx + 1),
-        slice.as_mut_ptr().add(idx),
-        slice.len() - idx - 1,
-    );
+    let len = slice.len();
+    let ptr = slice.as_mut_ptr();
+    let ret = ptr::read(ptr.add(idx));
+    ptr::copy(ptr.add(idx + 1), ptr.add(idx), len - idx - 1);
     ret
 }
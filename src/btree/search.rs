@@ -11,12 +11,12 @@ pub enum SearchResult<BorrowType, K, V, FoundType, GoDownType> {
     GoDown(Handle<NodeRef<BorrowType, K, V, GoDownType>, marker::Edge>),
 }
 
-pub fn search_tree<BorrowType, K, V, Q: ?Sized>(
+pub fn search_tree<BorrowType, K, V, Q>(
     mut node: NodeRef<BorrowType, K, V, marker::LeafOrInternal>,
     key: &Q,
 ) -> SearchResult<BorrowType, K, V, marker::LeafOrInternal, marker::Leaf>
 where
-    Q: Ord,
+    Q: Ord + ?Sized,
     K: Borrow<Q>,
 {
     loop {
@@ -33,12 +33,12 @@ where
     }
 }
 
-pub fn search_node<BorrowType, K, V, Type, Q: ?Sized>(
+pub fn search_node<BorrowType, K, V, Type, Q>(
     node: NodeRef<BorrowType, K, V, Type>,
     key: &Q,
 ) -> SearchResult<BorrowType, K, V, Type, Type>
 where
-    Q: Ord,
+    Q: Ord + ?Sized,
     K: Borrow<Q>,
 {
     match search_linear(&node, key) {
@@ -47,12 +47,12 @@ where
     }
 }
 
-pub fn search_linear<BorrowType, K, V, Type, Q: ?Sized>(
+pub fn search_linear<BorrowType, K, V, Type, Q>(
     node: &NodeRef<BorrowType, K, V, Type>,
     key: &Q,
 ) -> (usize, bool)
 where
-    Q: Ord,
+    Q: Ord + ?Sized,
     K: Borrow<Q>,
 {
     for (i, k) in node.keys().iter().enumerate() {
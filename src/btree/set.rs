@@ -1,13 +1,15 @@
 // This is pretty much entirely stolen from TreeSet, since BTreeMap has an identical interface
 // to TreeMap
 
-use crate::TryReserveError;
+use crate::allocator::{Allocator, Global};
+use crate::{AllocError, TryClone, TryFromIterator, TryReserveError};
 use core::borrow::Borrow;
 use core::cmp::max;
 use core::cmp::Ordering::{self, Equal, Greater, Less};
 use core::fmt::{self, Debug};
-use core::iter::{FromIterator, FusedIterator, Peekable};
-use core::ops::{BitAnd, BitOr, BitXor, RangeBounds, Sub};
+use core::hash::{Hash, Hasher};
+use core::iter::{FusedIterator, Peekable};
+use core::ops::RangeBounds;
 
 use super::map::{self, BTreeMap, Keys};
 use super::Recover;
@@ -57,10 +59,37 @@ use super::Recover;
 ///     println!("{}", book);
 /// }
 /// ```
-#[derive(Clone, Hash, PartialEq, Eq, Ord, PartialOrd)]
+///
+/// The values are stored in nodes allocated in the allocator `A`, see
+/// [`new_in`](#method.new_in).
+pub struct BTreeSet<T, A: Allocator = Global> {
+    map: BTreeMap<T, (), A>,
+}
+
+impl<T: Hash, A: Allocator> Hash for BTreeSet<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.map.hash(state)
+    }
+}
+
+impl<T: PartialEq, A: Allocator> PartialEq for BTreeSet<T, A> {
+    fn eq(&self, other: &BTreeSet<T, A>) -> bool {
+        self.map.eq(&other.map)
+    }
+}
+
+impl<T: Eq, A: Allocator> Eq for BTreeSet<T, A> {}
 
-pub struct BTreeSet<T> {
-    map: BTreeMap<T, ()>,
+impl<T: PartialOrd, A: Allocator> PartialOrd for BTreeSet<T, A> {
+    fn partial_cmp(&self, other: &BTreeSet<T, A>) -> Option<Ordering> {
+        self.map.partial_cmp(&other.map)
+    }
+}
+
+impl<T: Ord, A: Allocator> Ord for BTreeSet<T, A> {
+    fn cmp(&self, other: &BTreeSet<T, A>) -> Ordering {
+        self.map.cmp(&other.map)
+    }
 }
 
 /// An iterator over the items of a `BTreeSet`.
@@ -70,7 +99,6 @@ pub struct BTreeSet<T> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`iter`]: struct.BTreeSet.html#method.iter
-
 pub struct Iter<'a, T: 'a> {
     iter: Keys<'a, T, ()>,
 }
@@ -88,10 +116,16 @@ impl<T: fmt::Debug> fmt::Debug for Iter<'_, T> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`into_iter`]: struct.BTreeSet.html#method.into_iter
+pub struct IntoIter<T, A: Allocator = Global> {
+    iter: map::IntoIter<T, (), A>,
+}
 
-#[derive(Debug)]
-pub struct IntoIter<T> {
-    iter: map::IntoIter<T, ()>,
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for IntoIter<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoIter")
+            .field("iter", &self.iter)
+            .finish()
+    }
 }
 
 /// An iterator over a sub-range of items in a `BTreeSet`.
@@ -114,22 +148,21 @@ pub struct Range<'a, T: 'a> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`difference`]: struct.BTreeSet.html#method.difference
-
-pub struct Difference<'a, T: 'a> {
-    inner: DifferenceInner<'a, T>,
+pub struct Difference<'a, T: 'a, A: Allocator = Global> {
+    inner: DifferenceInner<'a, T, A>,
 }
-enum DifferenceInner<'a, T: 'a> {
+enum DifferenceInner<'a, T: 'a, A: Allocator> {
     Stitch {
         self_iter: Iter<'a, T>,
         other_iter: Peekable<Iter<'a, T>>,
     },
     Search {
         self_iter: Iter<'a, T>,
-        other_set: &'a BTreeSet<T>,
+        other_set: &'a BTreeSet<T, A>,
     },
 }
 
-impl<T: fmt::Debug> fmt::Debug for Difference<'_, T> {
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for Difference<'_, T, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.inner {
             DifferenceInner::Stitch {
@@ -155,7 +188,6 @@ impl<T: fmt::Debug> fmt::Debug for Difference<'_, T> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`symmetric_difference`]: struct.BTreeSet.html#method.symmetric_difference
-
 pub struct SymmetricDifference<'a, T: 'a> {
     a: Peekable<Iter<'a, T>>,
     b: Peekable<Iter<'a, T>>,
@@ -177,22 +209,21 @@ impl<T: fmt::Debug> fmt::Debug for SymmetricDifference<'_, T> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`intersection`]: struct.BTreeSet.html#method.intersection
-
-pub struct Intersection<'a, T: 'a> {
-    inner: IntersectionInner<'a, T>,
+pub struct Intersection<'a, T: 'a, A: Allocator = Global> {
+    inner: IntersectionInner<'a, T, A>,
 }
-enum IntersectionInner<'a, T: 'a> {
+enum IntersectionInner<'a, T: 'a, A: Allocator> {
     Stitch {
         small_iter: Iter<'a, T>, // for size_hint, should be the smaller of the sets
         other_iter: Iter<'a, T>,
     },
     Search {
         small_iter: Iter<'a, T>,
-        large_set: &'a BTreeSet<T>,
+        large_set: &'a BTreeSet<T, A>,
     },
 }
 
-impl<T: fmt::Debug> fmt::Debug for Intersection<'_, T> {
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for Intersection<'_, T, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.inner {
             IntersectionInner::Stitch {
@@ -218,7 +249,6 @@ impl<T: fmt::Debug> fmt::Debug for Intersection<'_, T> {
 ///
 /// [`BTreeSet`]: struct.BTreeSet.html
 /// [`union`]: struct.BTreeSet.html#method.union
-
 pub struct Union<'a, T: 'a> {
     a: Peekable<Iter<'a, T>>,
     b: Peekable<Iter<'a, T>>,
@@ -252,11 +282,54 @@ impl<T: Ord> BTreeSet<T> {
     ///
     /// let mut set: BTreeSet<i32> = BTreeSet::new();
     /// ```
-
     #[inline]
     pub fn new() -> BTreeSet<T> {
+        BTreeSet::new_in(Global)
+    }
+
+    /// Builds a set from an iterator of sorted values, in linear time.
+    ///
+    /// If several values are equal, the last one is kept. If an allocation
+    /// fails, the values already taken from `iter` are freed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the values of `iter` are not in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let set = BTreeSet::try_from_sorted_iter(vec![1, 2, 2, 3]).unwrap();
+    /// assert!(set.iter().eq([1, 2, 3].iter()));
+    /// ```
+    pub fn try_from_sorted_iter<I>(iter: I) -> Result<Self, TryReserveError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        Self::try_from_sorted_iter_in(iter, Global)
+    }
+}
+
+impl<T: Ord, A: Allocator + Clone> BTreeSet<T, A> {
+    /// Makes a new empty `BTreeSet` whose nodes are allocated in `alloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "unstable", feature(allocator_api))]
+    /// use fallible_collections::allocator::Global;
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new_in(Global);
+    /// set.try_insert(1).unwrap();
+    /// assert!(set.contains(&1));
+    /// ```
+    #[inline]
+    pub fn new_in(alloc: A) -> BTreeSet<T, A> {
         BTreeSet {
-            map: BTreeMap::new(),
+            map: BTreeMap::new_in(alloc),
         }
     }
 
@@ -282,11 +355,10 @@ impl<T: Ord> BTreeSet<T> {
     /// }
     /// assert_eq!(Some(&5), set.range(4..).next());
     /// ```
-
     #[inline]
-    pub fn range<K: ?Sized, R>(&self, range: R) -> Range<'_, T>
+    pub fn range<K, R>(&self, range: R) -> Range<'_, T>
     where
-        K: Ord,
+        K: Ord + ?Sized,
         T: Borrow<K>,
         R: RangeBounds<K>,
     {
@@ -315,8 +387,7 @@ impl<T: Ord> BTreeSet<T> {
     /// let diff: Vec<_> = a.difference(&b).cloned().collect();
     /// assert_eq!(diff, [1]);
     /// ```
-
-    pub fn difference<'a>(&'a self, other: &'a BTreeSet<T>) -> Difference<'a, T> {
+    pub fn difference<'a>(&'a self, other: &'a BTreeSet<T, A>) -> Difference<'a, T, A> {
         if self.len() > other.len() / ITER_PERFORMANCE_TIPPING_SIZE_DIFF {
             // Self is bigger than or not much smaller than other set.
             // Iterate both sets jointly, spotting matches along the way.
@@ -358,11 +429,10 @@ impl<T: Ord> BTreeSet<T> {
     /// let sym_diff: Vec<_> = a.symmetric_difference(&b).cloned().collect();
     /// assert_eq!(sym_diff, [1, 3]);
     /// ```
-
     #[inline]
     pub fn symmetric_difference<'a>(
         &'a self,
-        other: &'a BTreeSet<T>,
+        other: &'a BTreeSet<T, A>,
     ) -> SymmetricDifference<'a, T> {
         SymmetricDifference {
             a: self.iter().peekable(),
@@ -390,8 +460,7 @@ impl<T: Ord> BTreeSet<T> {
     /// let intersection: Vec<_> = a.intersection(&b).cloned().collect();
     /// assert_eq!(intersection, [2]);
     /// ```
-
-    pub fn intersection<'a>(&'a self, other: &'a BTreeSet<T>) -> Intersection<'a, T> {
+    pub fn intersection<'a>(&'a self, other: &'a BTreeSet<T, A>) -> Intersection<'a, T, A> {
         let (small, other) = if self.len() <= other.len() {
             (self, other)
         } else {
@@ -436,9 +505,8 @@ impl<T: Ord> BTreeSet<T> {
     /// let union: Vec<_> = a.union(&b).cloned().collect();
     /// assert_eq!(union, [1, 2]);
     /// ```
-
     #[inline]
-    pub fn union<'a>(&'a self, other: &'a BTreeSet<T>) -> Union<'a, T> {
+    pub fn union<'a>(&'a self, other: &'a BTreeSet<T, A>) -> Union<'a, T> {
         Union {
             a: self.iter().peekable(),
             b: other.iter().peekable(),
@@ -457,7 +525,6 @@ impl<T: Ord> BTreeSet<T> {
     /// v.clear();
     /// assert!(v.is_empty());
     /// ```
-
     #[inline(always)]
     pub fn clear(&mut self) {
         self.map.clear()
@@ -478,12 +545,11 @@ impl<T: Ord> BTreeSet<T> {
     /// assert_eq!(set.contains(&1), true);
     /// assert_eq!(set.contains(&4), false);
     /// ```
-
     #[inline(always)]
-    pub fn contains<Q: ?Sized>(&self, value: &Q) -> bool
+    pub fn contains<Q>(&self, value: &Q) -> bool
     where
         T: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         self.map.contains_key(value)
     }
@@ -503,12 +569,11 @@ impl<T: Ord> BTreeSet<T> {
     /// assert_eq!(set.get(&2), Some(&2));
     /// assert_eq!(set.get(&4), None);
     /// ```
-
     #[inline(always)]
-    pub fn get<Q: ?Sized>(&self, value: &Q) -> Option<&T>
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
     where
         T: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         Recover::get(&self.map, value)
     }
@@ -530,9 +595,8 @@ impl<T: Ord> BTreeSet<T> {
     /// b.insert(1);
     /// assert_eq!(a.is_disjoint(&b), false);
     /// ```
-
     #[inline]
-    pub fn is_disjoint(&self, other: &BTreeSet<T>) -> bool {
+    pub fn is_disjoint(&self, other: &BTreeSet<T, A>) -> bool {
         self.intersection(other).next().is_none()
     }
 
@@ -553,8 +617,7 @@ impl<T: Ord> BTreeSet<T> {
     /// set.insert(4);
     /// assert_eq!(set.is_subset(&sup), false);
     /// ```
-
-    pub fn is_subset(&self, other: &BTreeSet<T>) -> bool {
+    pub fn is_subset(&self, other: &BTreeSet<T, A>) -> bool {
         // Same result as self.difference(other).next().is_none()
         // but the 3 paths below are faster (in order: hugely, 20%, 5%).
         if self.len() > other.len() {
@@ -615,13 +678,117 @@ impl<T: Ord> BTreeSet<T> {
     /// set.insert(2);
     /// assert_eq!(set.is_superset(&sub), true);
     /// ```
-
     #[inline(always)]
-    pub fn is_superset(&self, other: &BTreeSet<T>) -> bool {
+    pub fn is_superset(&self, other: &BTreeSet<T, A>) -> bool {
         other.is_subset(self)
     }
 
-    /// Adds a value to the set.
+    /// Returns a reference to the first value in the set, if any.
+    /// This value is always the minimum of all values in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    /// assert_eq!(set.first(), None);
+    /// set.try_insert(1).unwrap();
+    /// assert_eq!(set.first(), Some(&1));
+    /// set.try_insert(2).unwrap();
+    /// assert_eq!(set.first(), Some(&1));
+    /// ```
+    #[inline]
+    pub fn first(&self) -> Option<&T> {
+        self.map.first_key_value().map(|(k, _)| k)
+    }
+
+    /// Returns a reference to the last value in the set, if any.
+    /// This value is always the maximum of all values in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    /// assert_eq!(set.last(), None);
+    /// set.try_insert(1).unwrap();
+    /// assert_eq!(set.last(), Some(&1));
+    /// set.try_insert(2).unwrap();
+    /// assert_eq!(set.last(), Some(&2));
+    /// ```
+    #[inline]
+    pub fn last(&self) -> Option<&T> {
+        self.map.last_key_value().map(|(k, _)| k)
+    }
+
+    /// Removes the first value from the set and returns it, if any.
+    /// The first value is always the minimum value in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    /// set.try_insert(1).unwrap();
+    /// set.try_insert(2).unwrap();
+    /// assert_eq!(set.pop_first(), Some(1));
+    /// assert_eq!(set.pop_first(), Some(2));
+    /// assert_eq!(set.pop_first(), None);
+    /// ```
+    #[inline]
+    pub fn pop_first(&mut self) -> Option<T> {
+        self.map.pop_first().map(|(k, _)| k)
+    }
+
+    /// Removes the last value from the set and returns it, if any.
+    /// The last value is always the maximum value in the set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    /// set.try_insert(1).unwrap();
+    /// set.try_insert(2).unwrap();
+    /// assert_eq!(set.pop_last(), Some(2));
+    /// assert_eq!(set.pop_last(), Some(1));
+    /// assert_eq!(set.pop_last(), None);
+    /// ```
+    #[inline]
+    pub fn pop_last(&mut self) -> Option<T> {
+        self.map.pop_last().map(|(k, _)| k)
+    }
+
+    /// Retains only the elements specified by the predicate.
+    ///
+    /// In other words, remove all elements `e` for which `f(&e)` returns `false`.
+    /// The elements are visited in ascending order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let mut set: BTreeSet<i32> = vec![1, 2, 3, 4, 5, 6].try_collect().unwrap();
+    /// // Keep only the even numbers.
+    /// set.retain(|&k| k % 2 == 0);
+    /// assert!(set.iter().eq([2, 4, 6].iter()));
+    /// ```
+    #[inline]
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|k, _| f(k));
+    }
+
+    /// Adds a value to the set. The set is left unchanged on error (strong
+    /// guarantee).
     ///
     /// If the set did not have this value present, `true` is returned.
     ///
@@ -641,14 +808,35 @@ impl<T: Ord> BTreeSet<T> {
     /// assert_eq!(set.insert(2), false);
     /// assert_eq!(set.len(), 1);
     /// ```
-
     #[inline]
     pub fn try_insert(&mut self, value: T) -> Result<bool, TryReserveError> {
         Ok(self.map.try_insert(value, ())?.is_none())
     }
 
+    /// Adds a value to the set, giving back the value if an allocation
+    /// failed. The set is left unchanged in that case (strong guarantee).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    ///
+    /// assert_eq!(set.try_insert_give_back(2).unwrap(), true);
+    /// assert_eq!(set.try_insert_give_back(2).unwrap(), false);
+    /// assert_eq!(set.len(), 1);
+    /// ```
+    #[inline]
+    pub fn try_insert_give_back(&mut self, value: T) -> Result<bool, AllocError<T>> {
+        self.map
+            .try_insert_give_back(value, ())
+            .map(|old| old.is_none())
+            .map_err(|e| e.map(|(value, _)| value))
+    }
+
     /// Adds a value to the set, replacing the existing value, if any, that is equal to the given
-    /// one. Returns the replaced value.
+    /// one. Returns the replaced value. The set is left unchanged on error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -662,10 +850,9 @@ impl<T: Ord> BTreeSet<T> {
     /// set.replace(Vec::with_capacity(10));
     /// assert_eq!(set.get(&[][..]).unwrap().capacity(), 10);
     /// ```
-
     #[inline]
     pub fn replace(&mut self, value: T) -> Result<Option<T>, TryReserveError> {
-        Ok(Recover::replace(&mut self.map, value)?)
+        Recover::replace(&mut self.map, value)
     }
 
     /// Removes a value from the set. Returns whether the value was
@@ -686,12 +873,11 @@ impl<T: Ord> BTreeSet<T> {
     /// assert_eq!(set.remove(&2), true);
     /// assert_eq!(set.remove(&2), false);
     /// ```
-
     #[inline(always)]
-    pub fn remove<Q: ?Sized>(&mut self, value: &Q) -> bool
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
     where
         T: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         self.map.remove(value).is_some()
     }
@@ -711,52 +897,45 @@ impl<T: Ord> BTreeSet<T> {
     /// assert_eq!(set.take(&2), Some(2));
     /// assert_eq!(set.take(&2), None);
     /// ```
-
     #[inline(always)]
-    pub fn take<Q: ?Sized>(&mut self, value: &Q) -> Option<T>
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
     where
         T: Borrow<Q>,
-        Q: Ord,
+        Q: Ord + ?Sized,
     {
         Recover::take(&mut self.map, value)
     }
 
     /// Moves all elements from `other` into `Self`, leaving `other` empty.
     ///
+    /// On allocation failure, both `self` and `other` are left unchanged
+    /// (strong guarantee).
+    ///
     /// # Examples
     ///
     /// ```
-    /// use std::collections::BTreeSet;
+    /// use fallible_collections::btree::BTreeSet;
     ///
     /// let mut a = BTreeSet::new();
-    /// a.insert(1);
-    /// a.insert(2);
-    /// a.insert(3);
+    /// a.try_insert(1).unwrap();
+    /// a.try_insert(2).unwrap();
     ///
     /// let mut b = BTreeSet::new();
-    /// b.insert(3);
-    /// b.insert(4);
-    /// b.insert(5);
-    ///
-    /// a.append(&mut b);
+    /// b.try_insert(2).unwrap();
+    /// b.try_insert(3).unwrap();
     ///
-    /// assert_eq!(a.len(), 5);
-    /// assert_eq!(b.len(), 0);
+    /// a.try_append(&mut b).unwrap();
     ///
-    /// assert!(a.contains(&1));
-    /// assert!(a.contains(&2));
-    /// assert!(a.contains(&3));
-    /// assert!(a.contains(&4));
-    /// assert!(a.contains(&5));
+    /// assert_eq!(a.len(), 3);
+    /// assert!(b.is_empty());
     /// ```
-
-    #[inline(always)]
-    pub fn append(&mut self, other: &mut Self) {
-        self.map.append(&mut other.map);
+    #[inline]
+    pub fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
+        self.map.try_append(&mut other.map)
     }
 
     /// Splits the collection into two at the given key. Returns everything after the given key,
-    /// including the key.
+    /// including the key. The set is left unchanged on error (strong guarantee).
     ///
     /// # Examples
     ///
@@ -784,19 +963,155 @@ impl<T: Ord> BTreeSet<T> {
     /// assert!(b.contains(&17));
     /// assert!(b.contains(&41));
     /// ```
-
     #[inline]
-    pub fn try_split_off<Q: ?Sized + Ord>(&mut self, key: &Q) -> Result<Self, TryReserveError>
+    pub fn try_split_off<Q>(&mut self, key: &Q) -> Result<Self, TryReserveError>
     where
+        Q: Ord + ?Sized,
         T: Borrow<Q>,
     {
         Ok(BTreeSet {
             map: self.map.split_off(key)?,
         })
     }
+
+    /// Inserts all the values of `iter` into the set, stopping at the first
+    /// allocation failure. The values inserted before the failure are kept
+    /// (basic guarantee).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    ///
+    /// let mut set = BTreeSet::new();
+    /// set.try_extend(vec![3, 1, 2, 1]).unwrap();
+    /// assert_eq!(set.len(), 3);
+    /// ```
+    pub fn try_extend<I: IntoIterator<Item = T>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), TryReserveError> {
+        for value in iter {
+            self.try_insert(value)?;
+        }
+        Ok(())
+    }
+
+    /// Same as `try_from_sorted_iter`, the nodes being allocated in `alloc`.
+    pub fn try_from_sorted_iter_in<I>(iter: I, alloc: A) -> Result<Self, TryReserveError>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let iter = iter.into_iter().map(|value| (value, ()));
+        Ok(BTreeSet {
+            map: BTreeMap::try_from_sorted_iter_in(iter, alloc)?,
+        })
+    }
 }
 
-impl<T> BTreeSet<T> {
+impl<T: Ord + TryClone, A: Allocator + Clone> BTreeSet<T, A> {
+    /// Returns the difference of `self` and `other` as a new `BTreeSet<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let a: BTreeSet<u32> = vec![1, 2, 3].try_collect().unwrap();
+    /// let b: BTreeSet<u32> = vec![3, 4, 5].try_collect().unwrap();
+    ///
+    /// let result = a.try_difference(&b).unwrap();
+    /// assert!(result.iter().eq(&[1, 2]));
+    /// ```
+    pub fn try_difference(
+        &self,
+        other: &BTreeSet<T, A>,
+    ) -> Result<BTreeSet<T, A>, TryReserveError> {
+        self.try_from_cloned(self.difference(other))
+    }
+
+    /// Returns the symmetric difference of `self` and `other` as a new `BTreeSet<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let a: BTreeSet<u32> = vec![1, 2, 3].try_collect().unwrap();
+    /// let b: BTreeSet<u32> = vec![2, 3, 4].try_collect().unwrap();
+    ///
+    /// let result = a.try_symmetric_difference(&b).unwrap();
+    /// assert!(result.iter().eq(&[1, 4]));
+    /// ```
+    pub fn try_symmetric_difference(
+        &self,
+        other: &BTreeSet<T, A>,
+    ) -> Result<BTreeSet<T, A>, TryReserveError> {
+        self.try_from_cloned(self.symmetric_difference(other))
+    }
+
+    /// Returns the intersection of `self` and `other` as a new `BTreeSet<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let a: BTreeSet<u32> = vec![1, 2, 3].try_collect().unwrap();
+    /// let b: BTreeSet<u32> = vec![2, 3, 4].try_collect().unwrap();
+    ///
+    /// let result = a.try_intersection(&b).unwrap();
+    /// assert!(result.iter().eq(&[2, 3]));
+    /// ```
+    pub fn try_intersection(
+        &self,
+        other: &BTreeSet<T, A>,
+    ) -> Result<BTreeSet<T, A>, TryReserveError> {
+        self.try_from_cloned(self.intersection(other))
+    }
+
+    /// Returns the union of `self` and `other` as a new `BTreeSet<T>`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::btree::BTreeSet;
+    /// use fallible_collections::TryCollect;
+    ///
+    /// let a: BTreeSet<u32> = vec![1, 2, 3].try_collect().unwrap();
+    /// let b: BTreeSet<u32> = vec![3, 4, 5].try_collect().unwrap();
+    ///
+    /// let result = a.try_union(&b).unwrap();
+    /// assert!(result.iter().eq(&[1, 2, 3, 4, 5]));
+    /// ```
+    pub fn try_union(&self, other: &BTreeSet<T, A>) -> Result<BTreeSet<T, A>, TryReserveError> {
+        self.try_from_cloned(self.union(other))
+    }
+
+    /// Builds a set from the clones of the sorted and deduplicated values of
+    /// `iter`, stopping at the first clone which failed. The set is allocated
+    /// in the allocator of `self`.
+    fn try_from_cloned<'a, I>(&self, iter: I) -> Result<BTreeSet<T, A>, TryReserveError>
+    where
+        I: Iterator<Item = &'a T>,
+        T: 'a,
+    {
+        let mut error = None;
+        let set = BTreeSet::try_from_sorted_iter_in(
+            iter.map_while(|value| value.try_clone().map_err(|e| error = Some(e)).ok()),
+            self.map.allocator().clone(),
+        )?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(set),
+        }
+    }
+}
+
+impl<T, A: Allocator> BTreeSet<T, A> {
     /// Gets an iterator that visits the values in the `BTreeSet` in ascending order.
     ///
     /// # Examples
@@ -824,7 +1139,6 @@ impl<T> BTreeSet<T> {
     /// assert_eq!(set_iter.next(), Some(&3));
     /// assert_eq!(set_iter.next(), None);
     /// ```
-
     #[inline(always)]
     pub fn iter(&self) -> Iter<'_, T> {
         Iter {
@@ -844,7 +1158,6 @@ impl<T> BTreeSet<T> {
     /// v.insert(1);
     /// assert_eq!(v.len(), 1);
     /// ```
-
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.map.len()
@@ -862,25 +1175,38 @@ impl<T> BTreeSet<T> {
     /// v.insert(1);
     /// assert!(!v.is_empty());
     /// ```
-
     #[inline(always)]
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
-}
 
-impl<T: Ord> FromIterator<T> for BTreeSet<T> {
+    /// Returns a reference to the allocator of the set.
     #[inline]
-    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> BTreeSet<T> {
+    pub fn allocator(&self) -> &A {
+        self.map.allocator()
+    }
+}
+
+impl<T: Ord> TryFromIterator<T> for BTreeSet<T> {
+    fn try_from_iterator<I: IntoIterator<Item = T>>(iterator: I) -> Result<Self, TryReserveError> {
         let mut set = BTreeSet::new();
-        set.extend(iter);
-        set
+        set.try_extend(iterator)?;
+        Ok(set)
     }
 }
 
-impl<T> IntoIterator for BTreeSet<T> {
+impl<T: TryClone, A: Allocator + Clone> TryClone for BTreeSet<T, A> {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Ok(BTreeSet {
+            map: self.map.try_clone()?,
+        })
+    }
+}
+
+impl<T, A: Allocator> IntoIterator for BTreeSet<T, A> {
     type Item = T;
-    type IntoIter = IntoIter<T>;
+    type IntoIter = IntoIter<T, A>;
 
     /// Gets an iterator for moving out the `BTreeSet`'s contents.
     ///
@@ -895,14 +1221,14 @@ impl<T> IntoIterator for BTreeSet<T> {
     /// assert_eq!(v, [1, 2, 3, 4]);
     /// ```
     #[inline(always)]
-    fn into_iter(self) -> IntoIter<T> {
+    fn into_iter(self) -> IntoIter<T, A> {
         IntoIter {
             iter: self.map.into_iter(),
         }
     }
 }
 
-impl<'a, T> IntoIterator for &'a BTreeSet<T> {
+impl<'a, T, A: Allocator> IntoIterator for &'a BTreeSet<T, A> {
     type Item = &'a T;
     type IntoIter = Iter<'a, T>;
 
@@ -912,22 +1238,6 @@ impl<'a, T> IntoIterator for &'a BTreeSet<T> {
     }
 }
 
-impl<T: Ord> Extend<T> for BTreeSet<T> {
-    #[inline]
-    fn extend<Iter: IntoIterator<Item = T>>(&mut self, iter: Iter) {
-        iter.into_iter().for_each(move |elem| {
-            self.try_insert(elem).expect("Out of Mem");
-        });
-    }
-}
-
-impl<'a, T: 'a + Ord + Copy> Extend<&'a T> for BTreeSet<T> {
-    #[inline]
-    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
-        self.extend(iter.into_iter().cloned());
-    }
-}
-
 impl<T: Ord> Default for BTreeSet<T> {
     /// Makes an empty `BTreeSet<T>` with a reasonable choice of B.
     #[inline(always)]
@@ -936,95 +1246,7 @@ impl<T: Ord> Default for BTreeSet<T> {
     }
 }
 
-impl<T: Ord + Clone> Sub<&BTreeSet<T>> for &BTreeSet<T> {
-    type Output = BTreeSet<T>;
-
-    /// Returns the difference of `self` and `rhs` as a new `BTreeSet<T>`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use std::collections::BTreeSet;
-    ///
-    /// let a: BTreeSet<_> = vec![1, 2, 3].into_iter().collect();
-    /// let b: BTreeSet<_> = vec![3, 4, 5].into_iter().collect();
-    ///
-    /// let result = &a - &b;
-    /// let result_vec: Vec<_> = result.into_iter().collect();
-    /// assert_eq!(result_vec, [1, 2]);
-    /// ```
-    fn sub(self, rhs: &BTreeSet<T>) -> BTreeSet<T> {
-        self.difference(rhs).cloned().collect()
-    }
-}
-
-impl<T: Ord + Clone> BitXor<&BTreeSet<T>> for &BTreeSet<T> {
-    type Output = BTreeSet<T>;
-
-    /// Returns the symmetric difference of `self` and `rhs` as a new `BTreeSet<T>`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use std::collections::BTreeSet;
-    ///
-    /// let a: BTreeSet<_> = vec![1, 2, 3].into_iter().collect();
-    /// let b: BTreeSet<_> = vec![2, 3, 4].into_iter().collect();
-    ///
-    /// let result = &a ^ &b;
-    /// let result_vec: Vec<_> = result.into_iter().collect();
-    /// assert_eq!(result_vec, [1, 4]);
-    /// ```
-    fn bitxor(self, rhs: &BTreeSet<T>) -> BTreeSet<T> {
-        self.symmetric_difference(rhs).cloned().collect()
-    }
-}
-
-impl<T: Ord + Clone> BitAnd<&BTreeSet<T>> for &BTreeSet<T> {
-    type Output = BTreeSet<T>;
-
-    /// Returns the intersection of `self` and `rhs` as a new `BTreeSet<T>`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use std::collections::BTreeSet;
-    ///
-    /// let a: BTreeSet<_> = vec![1, 2, 3].into_iter().collect();
-    /// let b: BTreeSet<_> = vec![2, 3, 4].into_iter().collect();
-    ///
-    /// let result = &a & &b;
-    /// let result_vec: Vec<_> = result.into_iter().collect();
-    /// assert_eq!(result_vec, [2, 3]);
-    /// ```
-    fn bitand(self, rhs: &BTreeSet<T>) -> BTreeSet<T> {
-        self.intersection(rhs).cloned().collect()
-    }
-}
-
-impl<T: Ord + Clone> BitOr<&BTreeSet<T>> for &BTreeSet<T> {
-    type Output = BTreeSet<T>;
-
-    /// Returns the union of `self` and `rhs` as a new `BTreeSet<T>`.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use std::collections::BTreeSet;
-    ///
-    /// let a: BTreeSet<_> = vec![1, 2, 3].into_iter().collect();
-    /// let b: BTreeSet<_> = vec![3, 4, 5].into_iter().collect();
-    ///
-    /// let result = &a | &b;
-    /// let result_vec: Vec<_> = result.into_iter().collect();
-    /// assert_eq!(result_vec, [1, 2, 3, 4, 5]);
-    /// ```
-    fn bitor(self, rhs: &BTreeSet<T>) -> BTreeSet<T> {
-        self.union(rhs).cloned().collect()
-    }
-}
-
-impl<T: Debug> Debug for BTreeSet<T> {
+impl<T: Debug, A: Allocator> Debug for BTreeSet<T, A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_set().entries(self.iter()).finish()
     }
@@ -1069,7 +1291,7 @@ impl<T> ExactSizeIterator for Iter<'_, T> {
 
 impl<T> FusedIterator for Iter<'_, T> {}
 
-impl<T> Iterator for IntoIter<T> {
+impl<T, A: Allocator> Iterator for IntoIter<T, A> {
     type Item = T;
 
     #[inline]
@@ -1083,21 +1305,21 @@ impl<T> Iterator for IntoIter<T> {
     }
 }
 
-impl<T> DoubleEndedIterator for IntoIter<T> {
+impl<T, A: Allocator> DoubleEndedIterator for IntoIter<T, A> {
     #[inline]
     fn next_back(&mut self) -> Option<T> {
         self.iter.next_back().map(|(k, _)| k)
     }
 }
 
-impl<T> ExactSizeIterator for IntoIter<T> {
+impl<T, A: Allocator> ExactSizeIterator for IntoIter<T, A> {
     #[inline(always)]
     fn len(&self) -> usize {
         self.iter.len()
     }
 }
 
-impl<T> FusedIterator for IntoIter<T> {}
+impl<T, A: Allocator> FusedIterator for IntoIter<T, A> {}
 
 impl<T> Clone for Range<'_, T> {
     #[inline(always)]
@@ -1135,7 +1357,7 @@ fn cmp_opt<T: Ord>(x: Option<&T>, y: Option<&T>, short: Ordering, long: Ordering
     }
 }
 
-impl<T> Clone for Difference<'_, T> {
+impl<T, A: Allocator> Clone for Difference<'_, T, A> {
     fn clone(&self) -> Self {
         Difference {
             inner: match &self.inner {
@@ -1158,7 +1380,7 @@ impl<T> Clone for Difference<'_, T> {
     }
 }
 
-impl<'a, T: Ord> Iterator for Difference<'a, T> {
+impl<'a, T: Ord, A: Allocator + Clone> Iterator for Difference<'a, T, A> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<&'a T> {
@@ -1189,7 +1411,7 @@ impl<'a, T: Ord> Iterator for Difference<'a, T> {
                 other_set,
             } => loop {
                 let self_next = self_iter.next()?;
-                if !other_set.contains(&self_next) {
+                if !other_set.contains(self_next) {
                     return Some(self_next);
                 }
             },
@@ -1211,7 +1433,7 @@ impl<'a, T: Ord> Iterator for Difference<'a, T> {
     }
 }
 
-impl<T: Ord> FusedIterator for Difference<'_, T> {}
+impl<T: Ord, A: Allocator + Clone> FusedIterator for Difference<'_, T, A> {}
 
 impl<T> Clone for SymmetricDifference<'_, T> {
     fn clone(&self) -> Self {
@@ -1246,7 +1468,7 @@ impl<'a, T: Ord> Iterator for SymmetricDifference<'a, T> {
 
 impl<T: Ord> FusedIterator for SymmetricDifference<'_, T> {}
 
-impl<T> Clone for Intersection<'_, T> {
+impl<T, A: Allocator> Clone for Intersection<'_, T, A> {
     fn clone(&self) -> Self {
         Intersection {
             inner: match &self.inner {
@@ -1269,7 +1491,7 @@ impl<T> Clone for Intersection<'_, T> {
     }
 }
 
-impl<'a, T: Ord> Iterator for Intersection<'a, T> {
+impl<'a, T: Ord, A: Allocator + Clone> Iterator for Intersection<'a, T, A> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<&'a T> {
@@ -1293,7 +1515,7 @@ impl<'a, T: Ord> Iterator for Intersection<'a, T> {
                 large_set,
             } => loop {
                 let small_next = small_iter.next()?;
-                if large_set.contains(&small_next) {
+                if large_set.contains(small_next) {
                     return Some(small_next);
                 }
             },
@@ -1310,7 +1532,7 @@ impl<'a, T: Ord> Iterator for Intersection<'a, T> {
     }
 }
 
-impl<T: Ord> FusedIterator for Intersection<'_, T> {}
+impl<T: Ord, A: Allocator + Clone> FusedIterator for Intersection<'_, T, A> {}
 
 impl<T> Clone for Union<'_, T> {
     #[inline]
@@ -1344,3 +1566,122 @@ impl<'a, T: Ord> Iterator for Union<'a, T> {
 }
 
 impl<T: Ord> FusedIterator for Union<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::try_clone::tests::Limited;
+    use crate::TryCollect;
+
+    fn set(values: impl Iterator<Item = u32>) -> BTreeSet<Limited> {
+        values.map(Limited::new).try_collect().unwrap()
+    }
+
+    fn values(set: &BTreeSet<Limited>) -> std::vec::Vec<u32> {
+        set.map.check();
+        set.iter().map(|v| v.0).collect()
+    }
+
+    #[test]
+    fn try_set_operations() {
+        use std::collections::BTreeSet as StdSet;
+
+        let cases = [
+            (0..0, 0..0),
+            (0..100, 0..0),
+            (0..100, 0..100),
+            (0..300, 100..200),
+            (0..150, 100..400),
+        ];
+        for (a, b) in cases {
+            // several nodes, with overlapping but not equal values
+            let a = set(a.map(|i| i * 2));
+            let b = set(b.map(|i| i * 3));
+            let std_a: StdSet<u32> = values(&a).into_iter().collect();
+            let std_b: StdSet<u32> = values(&b).into_iter().collect();
+
+            assert!(values(&a.try_union(&b).unwrap())
+                .iter()
+                .eq(std_a.union(&std_b)));
+            assert!(values(&a.try_intersection(&b).unwrap())
+                .iter()
+                .eq(std_a.intersection(&std_b)));
+            assert!(values(&a.try_difference(&b).unwrap())
+                .iter()
+                .eq(std_a.difference(&std_b)));
+            assert!(values(&a.try_symmetric_difference(&b).unwrap())
+                .iter()
+                .eq(std_a.symmetric_difference(&std_b)));
+            assert!(values(&a.try_clone().unwrap()).iter().eq(std_a.iter()));
+        }
+    }
+
+    #[test]
+    fn try_set_operations_clone_failure() {
+        let a = set(0..100);
+        let b = set(50..150);
+        let live = Limited::live();
+        for clones in [0, 1, 30, 99] {
+            Limited::set_clones(clones);
+            assert!(a.try_union(&b).is_err());
+            Limited::set_clones(clones);
+            assert!(a.try_difference(&b).is_err() || clones >= 50);
+            Limited::set_clones(clones);
+            assert!(a.try_clone().is_err());
+            // the values cloned before the failure were dropped
+            assert_eq!(Limited::live(), live);
+        }
+        Limited::set_clones(usize::MAX);
+        assert_eq!(values(&a.try_union(&b).unwrap()).len(), 150);
+        assert_eq!(values(&a), (0..100).collect::<std::vec::Vec<_>>());
+    }
+
+    #[test]
+    fn set_operations_in_allocator() {
+        use crate::allocator::tests::Bounded;
+
+        let alloc = Bounded::new(2);
+        let mut a = BTreeSet::new_in(&alloc);
+        a.try_extend(0..5u32).unwrap();
+        let other = Bounded::new(1);
+        let mut b = BTreeSet::new_in(&other);
+        b.try_extend(3..8u32).unwrap();
+
+        // the result is allocated in the allocator of `self`
+        let union = a.try_union(&b).unwrap();
+        assert!(union.iter().copied().eq(0..8));
+        assert_eq!((alloc.live(), other.live()), (2, 1));
+        drop(union);
+        assert!(b.try_difference(&a).is_err());
+        assert!(b.try_clone().is_err());
+        assert_eq!((alloc.live(), other.live()), (1, 1));
+    }
+
+    #[test]
+    fn pop_and_retain() {
+        let mut set: BTreeSet<u32> = (0..500).try_collect().unwrap();
+        assert_eq!((set.first(), set.last()), (Some(&0), Some(&499)));
+
+        // keep the multiples of 3, emptying some nodes
+        set.retain(|v| v % 3 == 0);
+        set.map.check();
+        assert_eq!(set.len(), 167);
+        assert!(set.iter().copied().eq((0..500).step_by(3)));
+
+        for i in 0..50 {
+            assert_eq!(set.pop_first(), Some(i * 3));
+            assert_eq!(set.pop_last(), Some(498 - i * 3));
+            set.map.check();
+        }
+        assert_eq!((set.first(), set.last()), (Some(&150), Some(&348)));
+        while set.pop_first().is_some() {}
+        assert!(set.is_empty());
+        assert_eq!((set.first(), set.last()), (None, None));
+        assert_eq!(set.pop_last(), None);
+
+        // the emptied set can grow again
+        set.try_insert(7).unwrap();
+        set.retain(|_| false);
+        assert_eq!(set.pop_first(), None);
+    }
+}
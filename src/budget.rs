@@ -0,0 +1,569 @@
+//! Memory budgets making the allocations fail past a limit
+//!
+//! A `Budget` counts the bytes allocated against it, and refuses the
+//! allocations which would exceed its limit, so that the fallible paths of
+//! the collections can be exercised deterministically, or used to cap the
+//! memory of a request.
+//!
+//! The budget is enforced by:
+//! - `LimitedAllocator`, an `Allocator` for the collections taking one:
+//!   `TryLinkedList`, `BTreeMap` and `BTreeSet` with the `btree` feature,
+//!   `TryHashMap` and `TryHashSet` with the `hashmap` feature and without
+//!   `std`, and `TryVec` and `TryBox` with the `unstable` feature,
+//! - `LimitedGlobalAlloc`, with the `std` feature, a wrapper of a
+//!   `GlobalAlloc` which charges the budget given to `with_budget` on the
+//!   current thread, so that every collection is limited.
+//!
+//! # Examples
+//!
+//! ```
+//! use fallible_collections::budget::{Budget, LimitedAllocator};
+//! use fallible_collections::TryLinkedList;
+//!
+//! let budget = Budget::new(1024);
+//! let mut list = TryLinkedList::new_in(LimitedAllocator::new(&budget));
+//! while list.try_push_back([0u8; 100]).is_ok() {}
+//! assert!(list.len() < 10);
+//! assert!(budget.used() <= 1024);
+//! drop(list);
+//! assert_eq!(budget.used(), 0);
+//! ```
+use crate::allocator::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A limit on the number of bytes allocated, shared by the allocators
+/// charging it.
+pub struct Budget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl Budget {
+    /// Creates a budget of `limit` bytes.
+    #[inline]
+    pub const fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the limit. The bytes already used are kept even if they
+    /// exceed the new limit, only the next allocations fail.
+    #[inline]
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes currently allocated against the budget.
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    /// Charges `size` bytes to the budget, returning false and leaving it
+    /// untouched if that would exceed the limit.
+    pub fn try_charge(&self, size: usize) -> bool {
+        let limit = self.limit();
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|&used| used <= limit)
+            })
+            .is_ok()
+    }
+
+    /// Gives back `size` bytes to the budget.
+    pub fn release(&self, size: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+}
+
+/// An allocator charging the size of its blocks to a `Budget`, and
+/// forwarding the allocations to `A`.
+#[derive(Copy, Clone, Debug)]
+pub struct LimitedAllocator<'b, A = Global> {
+    budget: &'b Budget,
+    inner: A,
+}
+
+impl<'b> LimitedAllocator<'b> {
+    /// Creates an allocator charging `budget` and allocating in the global
+    /// allocator.
+    #[inline]
+    pub fn new(budget: &'b Budget) -> Self {
+        Self::with_inner(budget, Global)
+    }
+}
+
+impl<'b, A> LimitedAllocator<'b, A> {
+    /// Creates an allocator charging `budget` and allocating in `inner`.
+    #[inline]
+    pub fn with_inner(budget: &'b Budget, inner: A) -> Self {
+        Self { budget, inner }
+    }
+
+    #[inline]
+    pub fn budget(&self) -> &'b Budget {
+        self.budget
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for LimitedAllocator<'_, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.budget.try_charge(layout.size()) {
+            return Err(AllocError);
+        }
+        self.inner.allocate(layout).map_err(|e| {
+            self.budget.release(layout.size());
+            e
+        })
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.budget.try_charge(layout.size()) {
+            return Err(AllocError);
+        }
+        self.inner.allocate_zeroed(layout).map_err(|e| {
+            self.budget.release(layout.size());
+            e
+        })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.deallocate(ptr, layout);
+        self.budget.release(layout.size());
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let extra = new_layout.size() - old_layout.size();
+        if !self.budget.try_charge(extra) {
+            return Err(AllocError);
+        }
+        self.inner.grow(ptr, old_layout, new_layout).map_err(|e| {
+            self.budget.release(extra);
+            e
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let extra = new_layout.size() - old_layout.size();
+        if !self.budget.try_charge(extra) {
+            return Err(AllocError);
+        }
+        self.inner
+            .grow_zeroed(ptr, old_layout, new_layout)
+            .map_err(|e| {
+                self.budget.release(extra);
+                e
+            })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.shrink(ptr, old_layout, new_layout)?;
+        self.budget.release(old_layout.size() - new_layout.size());
+        Ok(ptr)
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::global::{with_budget, LimitedGlobalAlloc};
+
+#[cfg(feature = "std")]
+mod global {
+    use super::Budget;
+    use core::cell::Cell;
+    use core::ptr;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::alloc::{GlobalAlloc, Layout, System};
+
+    /// A running `with_budget` call, linked to the enclosing one.
+    struct Scope {
+        // 0 marks the blocks allocated out of any scope
+        id: usize,
+        budget: *const Budget,
+        parent: *const Scope,
+    }
+
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+    std::thread_local! {
+        // the innermost `with_budget` of the thread
+        static CURRENT: Cell<*const Scope> = const { Cell::new(ptr::null()) };
+    }
+
+    impl Scope {
+        #[inline]
+        fn budget(&self) -> &Budget {
+            // the budget outlives the `with_budget` call owning the scope
+            unsafe { &*self.budget }
+        }
+
+        /// Returns the budget of the scope `id` if it is still running on the
+        /// current thread.
+        fn find(&self, id: usize) -> Option<&Budget> {
+            let mut scope = self;
+            loop {
+                if scope.id == id {
+                    return Some(scope.budget());
+                }
+                // the enclosing scopes outlive this one
+                scope = unsafe { scope.parent.as_ref()? };
+            }
+        }
+    }
+
+    /// Runs `f` with `budget` charged for the allocations of the current
+    /// thread made through `LimitedGlobalAlloc`. The scopes can be nested,
+    /// only the innermost budget is charged.
+    ///
+    /// Every block records the scope it was charged to, and only gives its
+    /// bytes back to that budget: the blocks allocated before the scope or
+    /// in another one are not credited to `budget` when freed in it, and a
+    /// block reallocated in the scope moves to it. The blocks freed after the
+    /// scope returned, or on another thread, stay charged to `budget`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fallible_collections::budget::{with_budget, Budget, LimitedGlobalAlloc};
+    /// use fallible_collections::TryVec;
+    ///
+    /// #[global_allocator]
+    /// static GLOBAL: LimitedGlobalAlloc = LimitedGlobalAlloc::new(std::alloc::System);
+    ///
+    /// fn main() {
+    ///     let budget = Budget::new(4096);
+    ///     with_budget(&budget, || {
+    ///         let mut vec = TryVec::<u8>::new();
+    ///         assert!(vec.reserve(8192).is_err());
+    ///         assert!(vec.reserve(1024).is_ok());
+    ///     });
+    /// }
+    /// ```
+    pub fn with_budget<R, F: FnOnce() -> R>(budget: &Budget, f: F) -> R {
+        struct Restore(*const Scope);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| current.set(self.0));
+            }
+        }
+
+        let scope = Scope {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            budget,
+            parent: CURRENT.with(|current| current.get()),
+        };
+        CURRENT.with(|current| current.set(&scope));
+        let _restore = Restore(scope.parent);
+        f()
+    }
+
+    /// Calls `f` with the innermost scope of the current thread, if any.
+    #[inline]
+    fn with_current<R, F: FnOnce(Option<&Scope>) -> R>(f: F) -> R {
+        let current = CURRENT
+            .try_with(|current| current.get())
+            .unwrap_or(ptr::null());
+        // the scope is unlinked before its `with_budget` call returns
+        f(unsafe { current.as_ref() })
+    }
+
+    /// Returns the layout of a block preceded by the id of the scope it is
+    /// charged to, and the offset of the block.
+    #[inline]
+    fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+        Layout::new::<usize>().extend(layout).ok()
+    }
+
+    /// A global allocator forwarding to `A`, and charging the budget given
+    /// to `with_budget` on the current thread, if any.
+    ///
+    /// Each block is preceded by a header of at least a `usize`, recording
+    /// the scope it is charged to.
+    #[derive(Debug, Default)]
+    pub struct LimitedGlobalAlloc<A = System> {
+        inner: A,
+    }
+
+    impl<A> LimitedGlobalAlloc<A> {
+        #[inline]
+        pub const fn new(inner: A) -> Self {
+            Self { inner }
+        }
+    }
+
+    impl<A: GlobalAlloc> LimitedGlobalAlloc<A> {
+        /// Allocates a block of `layout` and its header with `alloc`, charged
+        /// to the current scope.
+        unsafe fn alloc_with<F>(&self, layout: Layout, alloc: F) -> *mut u8
+        where
+            F: FnOnce(Layout) -> *mut u8,
+        {
+            let (full, offset) = match with_header(layout) {
+                Some(header) => header,
+                None => return ptr::null_mut(),
+            };
+            with_current(|scope| {
+                let budget = scope.map(Scope::budget);
+                if let Some(budget) = budget {
+                    if !budget.try_charge(layout.size()) {
+                        return ptr::null_mut();
+                    }
+                }
+                let base = alloc(full);
+                if base.is_null() {
+                    if let Some(budget) = budget {
+                        budget.release(layout.size());
+                    }
+                    return base;
+                }
+                base.cast::<usize>()
+                    .write(scope.map_or(0, |scope| scope.id));
+                base.add(offset)
+            })
+        }
+    }
+
+    unsafe impl<A: GlobalAlloc> GlobalAlloc for LimitedGlobalAlloc<A> {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.alloc_with(layout, |full| self.inner.alloc(full))
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            self.alloc_with(layout, |full| self.inner.alloc_zeroed(full))
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // the header fitted when the block was allocated
+            let (full, offset) = with_header(layout).unwrap_unchecked();
+            let base = ptr.sub(offset);
+            let id = base.cast::<usize>().read();
+            self.inner.dealloc(base, full);
+            if id != 0 {
+                with_current(|scope| {
+                    if let Some(budget) = scope.and_then(|scope| scope.find(id)) {
+                        budget.release(layout.size());
+                    }
+                })
+            }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let (full, offset) = with_header(layout).unwrap_unchecked();
+            let new_full = match offset.checked_add(new_size) {
+                Some(size) if Layout::from_size_align(size, full.align()).is_ok() => size,
+                _ => return ptr::null_mut(),
+            };
+            let base = ptr.sub(offset);
+            let id = base.cast::<usize>().read();
+            with_current(|scope| {
+                let current = scope.map_or(0, |scope| scope.id);
+                let budget = scope.map(Scope::budget);
+                // a block of another scope moves to the current one, as if it
+                // was allocated again
+                let (charged, owner, released) = if id == current {
+                    let charged = new_size.saturating_sub(layout.size());
+                    (charged, budget, layout.size().saturating_sub(new_size))
+                } else {
+                    let owner = scope.and_then(|scope| scope.find(id));
+                    (new_size, owner, layout.size())
+                };
+                if let Some(budget) = budget {
+                    if charged > 0 && !budget.try_charge(charged) {
+                        return ptr::null_mut();
+                    }
+                }
+                let new_base = self.inner.realloc(base, full, new_full);
+                if new_base.is_null() {
+                    if let Some(budget) = budget {
+                        budget.release(charged);
+                    }
+                    return new_base;
+                }
+                new_base.cast::<usize>().write(current);
+                if let Some(owner) = owner {
+                    owner.release(released);
+                }
+                new_base.add(offset)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TryLinkedList;
+
+    #[test]
+    fn budget() {
+        let budget = Budget::new(100);
+        assert!(budget.try_charge(60));
+        assert!(!budget.try_charge(60));
+        assert_eq!(budget.used(), 60);
+        assert_eq!(budget.remaining(), 40);
+        budget.release(20);
+        assert!(budget.try_charge(60));
+        budget.set_limit(50);
+        assert_eq!(budget.remaining(), 0);
+        assert!(!budget.try_charge(1));
+        assert!(!budget.try_charge(usize::MAX));
+    }
+
+    #[test]
+    fn limited_allocator() {
+        let node = Layout::new::<[u64; 3]>().size();
+        let budget = Budget::new(4 * node);
+        let mut list = TryLinkedList::new_in(LimitedAllocator::new(&budget));
+        for i in 0..4 {
+            list.try_push_back(i).unwrap();
+        }
+        assert_eq!(list.try_push_back(4).unwrap_err().into_inner(), 4);
+        assert_eq!(budget.remaining(), 0);
+        assert_eq!(list.pop_front(), Some(0));
+        assert!(list.try_push_front(5).is_ok());
+        drop(list);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn append_clone_between_budgets() {
+        let node = Layout::new::<[u64; 3]>().size();
+        let first = Budget::new(6 * node);
+        let second = Budget::new(3 * node);
+        let mut a = TryLinkedList::new_in(LimitedAllocator::new(&first));
+        let mut b = TryLinkedList::new_in(LimitedAllocator::new(&second));
+        for i in 0..3 {
+            a.try_push_back(i).unwrap();
+            b.try_push_back(i + 3).unwrap();
+        }
+        // the clones are charged to the budget of `a`
+        a.try_append_clone(&b).unwrap();
+        assert_eq!(first.used(), 6 * node);
+        assert_eq!(second.used(), 3 * node);
+        assert!(a.try_append_clone(&b).is_err());
+        assert_eq!(a.len(), 6);
+        assert_eq!(first.used(), 6 * node);
+        drop(a);
+        drop(b);
+        assert_eq!(first.used(), 0);
+        assert_eq!(second.used(), 0);
+    }
+
+    // the testing module registers its allocator on top of this one
+    #[cfg(all(feature = "std", not(feature = "testing")))]
+    #[global_allocator]
+    static GLOBAL: LimitedGlobalAlloc = LimitedGlobalAlloc::new(std::alloc::System);
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn limited_global_alloc() {
+        use crate::TryVec;
+
+        let budget = Budget::new(1000);
+        with_budget(&budget, || {
+            let mut vec = TryVec::<u8>::new();
+            assert!(vec.reserve(1001).is_err());
+            assert!(vec.reserve(1000).is_ok());
+            assert_eq!(budget.used(), 1000);
+            let inner = Budget::new(0);
+            with_budget(&inner, || {
+                assert!(TryVec::<u8>::with_capacity(1).is_err());
+            });
+            assert!(TryVec::<u8>::with_capacity(1).is_err());
+            drop(vec);
+            assert_eq!(budget.used(), 0);
+            assert!(TryVec::<u8>::with_capacity(1).is_ok());
+        });
+        assert_eq!(budget.used(), 0);
+
+        #[cfg(feature = "hashmap")]
+        {
+            let budget = Budget::new(1 << 12);
+            with_budget(&budget, || {
+                let mut map: crate::TryHashMap<_, _> = crate::TryHashMap::default();
+                let mut i = 0u64;
+                while map.insert(i, [0u8; 64]).is_ok() {
+                    i += 1;
+                }
+                assert!(i > 0 && budget.used() <= 1 << 12);
+            });
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn limited_global_alloc_owner() {
+        use crate::TryVec;
+
+        let outer = Budget::new(1000);
+        let inner = Budget::new(1000);
+        let before = TryVec::<u8>::with_capacity(100).unwrap();
+        with_budget(&outer, || {
+            let vec = TryVec::<u8>::with_capacity(100).unwrap();
+            drop(before);
+            assert_eq!(outer.used(), 100);
+            let mut moved = TryVec::<u8>::with_capacity(200).unwrap();
+            with_budget(&inner, || {
+                let other = TryVec::<u8>::with_capacity(50).unwrap();
+                drop(vec);
+                assert_eq!((outer.used(), inner.used()), (200, 50));
+                moved.reserve(300).unwrap();
+                assert_eq!(outer.used(), 0);
+                assert_eq!(inner.used(), 50 + moved.capacity());
+                drop(other);
+            });
+            // freed after its scope returned
+            let capacity = moved.capacity();
+            drop(moved);
+            assert_eq!((outer.used(), inner.used()), (0, capacity));
+        });
+    }
+}
@@ -0,0 +1,363 @@
+//! Errors returned when an allocation failed
+use crate::{make_alloc_error, make_try_reserve_error, TryReserveError};
+use alloc::alloc::Layout;
+use core::fmt;
+
+/// Error returned by the `*_give_back` methods when an allocation
+/// failed. Contrary to a bare `TryReserveError`, the value which could
+/// not be stored is handed back to the caller, so it can retry after
+/// freeing some memory without losing data.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::{AllocError, TryVec};
+///
+/// let mut vec = TryVec::new();
+/// match vec.push_give_back(String::from("foo")) {
+///     Ok(()) => assert_eq!(vec[0], "foo"),
+///     Err(e) => {
+///         let value: String = e.into_inner();
+///         assert_eq!(value, "foo");
+///     }
+/// }
+/// ```
+#[derive(Debug, PartialEq, Eq)]
+pub struct AllocError<T> {
+    /// The value which could not be stored
+    pub value: T,
+    /// The allocation error
+    pub source: TryReserveError,
+}
+
+impl<T> AllocError<T> {
+    /// Creates a new `AllocError` giving back `value`
+    #[inline]
+    pub fn new(value: T, source: TryReserveError) -> Self {
+        Self { value, source }
+    }
+
+    /// Returns the value which could not be stored
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Returns the value which could not be stored and the allocation error
+    #[inline]
+    pub fn into_parts(self) -> (T, TryReserveError) {
+        (self.value, self.source)
+    }
+
+    /// Returns a reference on the allocation error
+    #[inline]
+    pub fn error(&self) -> &TryReserveError {
+        &self.source
+    }
+
+    /// Maps the value given back, keeping the allocation error
+    #[inline]
+    pub fn map<U, F: FnOnce(T) -> U>(self, f: F) -> AllocError<U> {
+        AllocError {
+            value: f(self.value),
+            source: self.source,
+        }
+    }
+}
+
+impl<T> From<(T, TryReserveError)> for AllocError<T> {
+    #[inline]
+    fn from((value, source): (T, TryReserveError)) -> Self {
+        Self { value, source }
+    }
+}
+
+impl<T> From<AllocError<T>> for TryReserveError {
+    /// Drops the value given back
+    #[inline]
+    fn from(e: AllocError<T>) -> Self {
+        e.source
+    }
+}
+
+impl<T> fmt::Display for AllocError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.source, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: fmt::Debug> std::error::Error for AllocError<T> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The reason why an allocation failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReserveErrorKind {
+    /// The computed capacity exceeded the collection's maximum (usually
+    /// `isize::MAX` bytes)
+    CapacityOverflow,
+    /// The memory allocator returned an error
+    AllocError,
+}
+
+/// The collection which failed to allocate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CollectionKind {
+    /// A `TryVec`
+    Vec,
+    /// A `TryVecDeque`
+    VecDeque,
+    /// A `TryString`
+    String,
+    /// A `TryHashMap`
+    HashMap,
+    /// A `TryHashSet`
+    HashSet,
+    /// A `btree::BTreeMap`
+    BTreeMap,
+}
+
+impl fmt::Display for CollectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Allocation error carrying what is known about the failed request: its
+/// kind, the layout requested from the allocator, the collection and the
+/// length it was grown to.
+///
+/// On stable, `TryReserveError` can't tell its kind nor carry a layout, so
+/// the `try_reserve_detailed` methods of `TryVec`, `TryString`,
+/// `TryVecDeque`, `btree::BTreeMap`, `TryHashMap` and `TryHashSet` return this
+/// error instead. Only the hash tables know the failed layout on stable.
+///
+/// It converts to and from `TryReserveError`: converting back gives the
+/// original error when there is one, and otherwise an error of the same
+/// kind, which only carries the layout on nightly.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::{CollectionKind, ReserveError, ReserveErrorKind, TryVec};
+///
+/// let mut vec = TryVec::<u64>::new();
+/// let e = vec.reserve(usize::MAX).unwrap_err();
+/// let e = ReserveError::from(e)
+///     .with_collection(CollectionKind::Vec)
+///     .with_len(usize::MAX);
+/// assert_eq!(e.kind(), ReserveErrorKind::CapacityOverflow);
+/// assert_eq!(e.len(), Some(usize::MAX));
+/// assert!(e.to_string().starts_with("capacity overflow while growing a Vec"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReserveError {
+    kind: ReserveErrorKind,
+    layout: Option<Layout>,
+    collection: Option<CollectionKind>,
+    len: Option<usize>,
+    source: Option<TryReserveError>,
+}
+
+impl ReserveError {
+    /// Creates an error of the given kind, without any context
+    #[inline]
+    pub fn new(kind: ReserveErrorKind) -> Self {
+        Self {
+            kind,
+            layout: None,
+            collection: None,
+            len: None,
+            source: None,
+        }
+    }
+
+    /// Sets the layout requested from the allocator
+    #[inline]
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Sets the collection which failed to allocate
+    #[inline]
+    pub fn with_collection(mut self, collection: CollectionKind) -> Self {
+        self.collection = Some(collection);
+        self
+    }
+
+    /// Sets the length the collection was grown to
+    #[inline]
+    pub fn with_len(mut self, len: usize) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Returns the reason why the allocation failed
+    #[inline]
+    pub fn kind(&self) -> ReserveErrorKind {
+        self.kind
+    }
+
+    /// Returns the layout requested from the allocator, if known
+    #[inline]
+    pub fn layout(&self) -> Option<Layout> {
+        self.layout
+    }
+
+    /// Returns the collection which failed to allocate, if known
+    #[inline]
+    pub fn collection(&self) -> Option<CollectionKind> {
+        self.collection
+    }
+
+    /// Returns the length the collection was grown to, if known
+    #[allow(clippy::len_without_is_empty)]
+    #[inline]
+    pub fn len(&self) -> Option<usize> {
+        self.len
+    }
+}
+
+impl From<TryReserveError> for ReserveError {
+    /// Keeps `e` as the source. Its layout is only known on nightly.
+    fn from(e: TryReserveError) -> Self {
+        #[cfg(feature = "unstable")]
+        let (kind, layout) = match e.kind() {
+            alloc::collections::TryReserveErrorKind::CapacityOverflow => {
+                (ReserveErrorKind::CapacityOverflow, None)
+            }
+            alloc::collections::TryReserveErrorKind::AllocError { layout, .. } => {
+                (ReserveErrorKind::AllocError, Some(layout))
+            }
+        };
+        // all capacity overflows compare equal, as they carry nothing
+        #[cfg(not(feature = "unstable"))]
+        let (kind, layout) = if e == make_try_reserve_error() {
+            (ReserveErrorKind::CapacityOverflow, None)
+        } else {
+            (ReserveErrorKind::AllocError, None)
+        };
+        Self {
+            kind,
+            layout,
+            collection: None,
+            len: None,
+            source: Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "hashmap")]
+impl From<hashbrown::TryReserveError> for ReserveError {
+    fn from(e: hashbrown::TryReserveError) -> Self {
+        match e {
+            hashbrown::TryReserveError::CapacityOverflow => {
+                Self::new(ReserveErrorKind::CapacityOverflow)
+            }
+            hashbrown::TryReserveError::AllocError { layout } => {
+                Self::new(ReserveErrorKind::AllocError).with_layout(layout)
+            }
+        }
+    }
+}
+
+impl From<ReserveError> for TryReserveError {
+    /// Gives back the source error if any, the layout being lost on stable
+    /// otherwise
+    fn from(e: ReserveError) -> Self {
+        match (e.source, e.kind, e.layout) {
+            (Some(source), _, _) => source,
+            (None, ReserveErrorKind::AllocError, Some(layout)) => make_alloc_error(layout),
+            (None, _, _) => make_try_reserve_error(),
+        }
+    }
+}
+
+impl fmt::Display for ReserveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, self.layout) {
+            (ReserveErrorKind::CapacityOverflow, _) => f.write_str("capacity overflow")?,
+            (ReserveErrorKind::AllocError, Some(layout)) => write!(
+                f,
+                "memory allocation of {} bytes aligned to {} failed",
+                layout.size(),
+                layout.align()
+            )?,
+            (ReserveErrorKind::AllocError, None) => f.write_str("memory allocation failed")?,
+        }
+        match (self.collection, self.len) {
+            (Some(collection), Some(len)) => {
+                write!(f, " while growing a {} to {} elements", collection, len)
+            }
+            (Some(collection), None) => write!(f, " in a {}", collection),
+            (None, Some(len)) => write!(f, " for {} elements", len),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReserveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_error() {
+        let e = AllocError::new(String::from("foo"), make_try_reserve_error());
+        assert_eq!(e.to_string(), make_try_reserve_error().to_string());
+        assert_eq!(e.error(), &make_try_reserve_error());
+        let e = e.map(|s| s.len());
+        let (len, source) = e.into_parts();
+        assert_eq!(len, 3);
+        let e: AllocError<_> = (len, source).into();
+        let source: TryReserveError = e.into();
+        assert_eq!(source, make_try_reserve_error());
+    }
+
+    #[test]
+    fn reserve_error() {
+        let e = ReserveError::from(make_try_reserve_error());
+        assert_eq!(e.kind(), ReserveErrorKind::CapacityOverflow);
+        assert_eq!(e.layout(), None);
+        assert_eq!(TryReserveError::from(e), make_try_reserve_error());
+
+        let layout = Layout::new::<[u64; 4]>();
+        let e = ReserveError::new(ReserveErrorKind::AllocError)
+            .with_layout(layout)
+            .with_collection(CollectionKind::HashMap)
+            .with_len(100);
+        assert_eq!(
+            e.to_string(),
+            "memory allocation of 32 bytes aligned to 8 failed while growing a HashMap to 100 elements"
+        );
+        assert_eq!((e.layout(), e.len()), (Some(layout), Some(100)));
+        let e = TryReserveError::from(e);
+        assert_eq!(e, make_alloc_error(layout));
+        // the kind makes the round trip, the layout on nightly only
+        let e = ReserveError::from(e);
+        assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+        #[cfg(feature = "unstable")]
+        assert_eq!(e.layout(), Some(layout));
+
+        // a real allocator failure is recognized on stable too
+        let mut v: alloc::vec::Vec<u8> = alloc::vec::Vec::new();
+        let e = ReserveError::from(v.try_reserve(isize::MAX as usize).unwrap_err());
+        assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+        #[cfg(not(feature = "unstable"))]
+        assert_eq!(e.to_string(), "memory allocation failed");
+    }
+}
@@ -1,40 +1,116 @@
 //! A try_format! macro replacing format!
-use super::FallibleVec;
-use crate::TryReserveError;
-use alloc::fmt::{Arguments, Write};
+use super::FallibleString;
+use crate::{make_try_reserve_error, TryReserveError};
+use alloc::fmt::{self, Arguments, Write};
 use alloc::string::String;
 
+/// Writer passing every byte through a fallible allocation, keeping the
+/// allocation error which `fmt::Error` can't carry.
+struct TryWriter<'a> {
+    buf: &'a mut String,
+    limit: Option<usize>,
+    error: Option<TryReserveError>,
+}
+
+impl Write for TryWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let res = match self.limit {
+            Some(limit) if limit - self.buf.len() < s.len() => Err(make_try_reserve_error()),
+            _ => self.buf.try_push_str(s),
+        };
+        res.map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+fn try_write(
+    buf: &mut String,
+    limit: Option<usize>,
+    args: Arguments<'_>,
+) -> Result<(), TryReserveError> {
+    let mut writer = TryWriter {
+        buf,
+        limit,
+        error: None,
+    };
+    let res = writer.write_fmt(args);
+    // checked first, as an implementation ignoring the error of a write
+    // makes write_fmt succeed with the refused bytes missing
+    if let Some(e) = writer.error.take() {
+        return Err(e);
+    }
+    match res {
+        Ok(()) => Ok(()),
+        Err(_) => panic!("a formatting trait implementation returned an error"),
+    }
+}
+
 /// Take a max capacity a try allocating a string with it.
 ///
-/// # Warning:
-///
-/// the max capacity must be > to the formating of the
-/// arguments. If writing the argument on the string exceed the
-/// capacity, no error is return and an allocation can occurs which
-/// can lead to a panic
+/// If writing the arguments on the string exceeds the capacity, the
+/// string grows fallibly and an error is returned if the allocation
+/// fails, see try_format_strict to fail instead of growing.
 pub fn try_format(max_capacity: usize, args: Arguments<'_>) -> Result<String, TryReserveError> {
-    let v = FallibleVec::try_with_capacity(max_capacity)?;
-    let mut s = String::from_utf8(v).expect("wtf an empty vec should be valid utf8");
-    s.write_fmt(args)
-        .expect("a formatting trait implementation returned an error");
+    let mut s = <String as FallibleString>::try_with_capacity(max_capacity)?;
+    try_write(&mut s, None, args)?;
     Ok(s)
 }
 
-#[macro_export]
 /// Take a max capacity a try allocating a string with it.
 ///
-/// # Warning:
+/// If writing the arguments on the string exceeds the capacity, a
+/// capacity overflow error is returned instead of growing the string.
+pub fn try_format_strict(
+    max_capacity: usize,
+    args: Arguments<'_>,
+) -> Result<String, TryReserveError> {
+    let mut s = <String as FallibleString>::try_with_capacity(max_capacity)?;
+    try_write(&mut s, Some(max_capacity), args)?;
+    Ok(s)
+}
+
+/// Try formatting the arguments into a string growing fallibly, without
+/// capacity hint.
+pub fn try_format_args(args: Arguments<'_>) -> Result<String, TryReserveError> {
+    let mut s = String::new();
+    try_write(&mut s, None, args)?;
+    Ok(s)
+}
+
+#[macro_export]
+/// Take a max capacity a try allocating a string with it.
 ///
-/// the max capacity must be > to the formating of the
-/// arguments. If writing the argument on the string exceed the
-/// capacity, no error is return and an allocation can occurs which
-/// can lead to a panic
+/// If writing the arguments on the string exceeds the capacity, the
+/// string grows fallibly and an error is returned if the allocation
+/// fails.
 macro_rules! tryformat {
     ($max_capacity:tt, $($arg:tt)*) => (
         $crate::format::try_format($max_capacity, format_args!($($arg)*))
     )
 }
 
+#[macro_export]
+/// Take a max capacity a try allocating a string with it.
+///
+/// If writing the arguments on the string exceeds the capacity, an
+/// error is returned instead of growing the string.
+macro_rules! tryformat_strict {
+    ($max_capacity:tt, $($arg:tt)*) => (
+        $crate::format::try_format_strict($max_capacity, format_args!($($arg)*))
+    )
+}
+
+#[macro_export]
+/// Same as format! but return an error instead of panic if an allocation
+/// failed
+macro_rules! try_format {
+    ($($arg:tt)*) => (
+        $crate::format::try_format_args(format_args!($($arg)*))
+    )
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -43,4 +119,59 @@ mod tests {
         assert_eq!(tryformat!(1, "{}", 1).unwrap(), format!("{}", 1));
         assert_eq!(tryformat!(3, "{}", 123).unwrap(), format!("{}", 123));
     }
+
+    #[test]
+    fn format_exceeding_capacity() {
+        assert_eq!(tryformat!(1, "{}", 123).unwrap(), format!("{}", 123));
+        assert_eq!(
+            tryformat!(0, "{:?}", "foo").unwrap(),
+            format!("{:?}", "foo")
+        );
+    }
+
+    #[test]
+    fn format_strict() {
+        assert_eq!(tryformat_strict!(3, "{}", 123).unwrap(), "123");
+        assert!(tryformat_strict!(2, "{}", 123).is_err());
+        assert!(tryformat_strict!(0, "1").is_err());
+        assert_eq!(tryformat_strict!(0, "").unwrap(), "");
+    }
+
+    #[test]
+    fn format_without_capacity() {
+        assert_eq!(try_format!("{}-{}", 1, "foo").unwrap(), "1-foo");
+        assert_eq!(try_format!("").unwrap(), "");
+    }
+
+    #[test]
+    fn format_oom() {
+        let max = usize::MAX;
+        assert!(tryformat!(max, "1").is_err(), "it should be OOM");
+    }
+
+    #[test]
+    fn format_swallowed_error() {
+        struct Swallowing;
+        impl core::fmt::Display for Swallowing {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                let _ = write!(f, "{}", 12345);
+                f.write_str("!")
+            }
+        }
+        assert_eq!(tryformat_strict!(6, "{}", Swallowing).unwrap(), "12345!");
+        assert!(tryformat_strict!(3, "{}", Swallowing).is_err());
+        assert!(try_format!("{}", Swallowing).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "a formatting trait implementation returned an error")]
+    fn format_trait_error() {
+        struct Failing;
+        impl core::fmt::Display for Failing {
+            fn fmt(&self, _: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                Err(core::fmt::Error)
+            }
+        }
+        let _ = try_format!("{}", Failing);
+    }
 }
@@ -1,37 +1,83 @@
 //! Implement Fallible HashMap
 use super::TryClone;
-use crate::TryReserveError;
+#[cfg(not(feature = "std"))]
+use crate::allocator::HashbrownAllocator;
+use crate::allocator::{Allocator, Global};
+use crate::{AllocError, CollectionKind, ReserveError, TryReserveError};
 use core::borrow::Borrow;
 use core::default::Default;
-use core::fmt::Debug;
-use core::hash::Hash;
+use core::fmt::{self, Debug};
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use core::ops::Index;
+
+#[cfg(not(feature = "std"))]
+type HashMap<K, V, S, A> = hashbrown::hash_map::HashMap<K, V, S, HashbrownAllocator<A>>;
+
+#[cfg(feature = "std")]
+type HashMap<K, V, S> = std::collections::HashMap<K, V, S>;
+
+#[cfg(not(feature = "std"))]
+type HashSet<T, S, A> = hashbrown::hash_set::HashSet<T, S, HashbrownAllocator<A>>;
+
+#[cfg(feature = "std")]
+type HashSet<T, S> = std::collections::HashSet<T, S>;
+
+/// The hasher used by default by `TryHashMap`, `RandomState` with std and
+/// the hashbrown default hasher without it.
+#[cfg(not(feature = "std"))]
+pub type DefaultHashBuilder = hashbrown::hash_map::DefaultHashBuilder;
+
+/// The hasher used by default by `TryHashMap`, `RandomState` with std and
+/// the hashbrown default hasher without it.
+#[cfg(feature = "std")]
+pub type DefaultHashBuilder = std::collections::hash_map::RandomState;
 
 #[cfg(not(feature = "std"))]
-type HashMap<K, V> = hashbrown::hash_map::HashMap<K, V>;
+use hashbrown::hash_map::{
+    Drain, Entry as InnerEntry, IntoIter, Iter, IterMut, Keys, Values, ValuesMut,
+};
 
 #[cfg(feature = "std")]
-type HashMap<K, V> = std::collections::HashMap<K, V>;
+use std::collections::hash_map::{
+    Drain, Entry as InnerEntry, IntoIter, Iter, IterMut, Keys, Values, ValuesMut,
+};
 
 #[cfg(not(feature = "std"))]
-use hashbrown::hash_map::{IntoIter, Iter};
+use hashbrown::hash_set::{
+    Difference, Drain as SetDrain, Intersection, IntoIter as SetIntoIter, Iter as SetIter,
+    SymmetricDifference, Union,
+};
 
 #[cfg(feature = "std")]
-use std::collections::hash_map::{IntoIter, Iter};
+use std::collections::hash_set::{
+    Difference, Drain as SetDrain, Intersection, IntoIter as SetIntoIter, Iter as SetIter,
+    SymmetricDifference, Union,
+};
 
-pub struct TryHashMap<K, V> {
-    inner: HashMap<K, V>,
+/// TryHashMap is a thin wrapper around std or hashbrown HashMap to provide
+/// support for fallible allocation.
+///
+/// The table is allocated in the allocator `A`. Another allocator than
+/// `Global` can only be used without the `std` feature, as the std HashMap
+/// takes no allocator parameter.
+pub struct TryHashMap<K, V, S = DefaultHashBuilder, A: Allocator = Global> {
+    #[cfg(not(feature = "std"))]
+    inner: HashMap<K, V, S, A>,
+    #[cfg(feature = "std")]
+    inner: HashMap<K, V, S>,
+    #[cfg(feature = "std")]
+    alloc: PhantomData<A>,
 }
 
-impl<K, V> Default for TryHashMap<K, V> {
+impl<K, V, S: Default> Default for TryHashMap<K, V, S> {
     #[inline(always)]
     fn default() -> Self {
-        Self {
-            inner: Default::default(),
-        }
+        Self::with_hasher(Default::default())
     }
 }
 
-impl<K: Debug, V: Debug> Debug for TryHashMap<K, V> {
+impl<K: Debug, V: Debug, S, A: Allocator> Debug for TryHashMap<K, V, S, A> {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.inner.fmt(f)
@@ -44,56 +90,342 @@ where
 {
     #[inline]
     pub fn with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
-        let mut map = Self {
-            inner: HashMap::new(),
-        };
-        map.reserve(capacity)?;
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+}
+
+impl<K, V, S> TryHashMap<K, V, S> {
+    /// Creates an empty map which will use the given hash builder to hash
+    /// keys. This doesn't allocate.
+    #[inline]
+    pub fn with_hasher(hash_builder: S) -> Self {
+        #[cfg(not(feature = "std"))]
+        {
+            Self::with_hasher_in(hash_builder, Global)
+        }
+        #[cfg(feature = "std")]
+        {
+            Self {
+                inner: HashMap::with_hasher(hash_builder),
+                alloc: PhantomData,
+            }
+        }
+    }
+
+    #[inline]
+    pub fn with_capacity_and_hasher(
+        capacity: usize,
+        hash_builder: S,
+    ) -> Result<Self, TryReserveError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut map = Self::with_hasher(hash_builder);
+        map.try_reserve(capacity)?;
+        Ok(map)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<K, V, A: Allocator> TryHashMap<K, V, DefaultHashBuilder, A> {
+    /// Creates an empty map allocating its table in `alloc`. This doesn't
+    /// allocate.
+    #[inline]
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_hasher_in(Default::default(), alloc)
+    }
+
+    /// Creates a map allocated in `alloc` with room for at least `capacity`
+    /// elements.
+    #[inline]
+    pub fn try_with_capacity_in(capacity: usize, alloc: A) -> Result<Self, TryReserveError>
+    where
+        K: Eq + Hash,
+    {
+        let mut map = Self::new_in(alloc);
+        map.try_reserve(capacity)?;
         Ok(map)
     }
+}
+
+#[cfg(not(feature = "std"))]
+impl<K, V, S, A: Allocator> TryHashMap<K, V, S, A> {
+    /// Creates an empty map which will use the given hash builder to hash
+    /// keys, and allocate its table in `alloc`. This doesn't allocate.
+    #[inline]
+    pub fn with_hasher_in(hash_builder: S, alloc: A) -> Self {
+        Self {
+            inner: HashMap::with_hasher_in(hash_builder, HashbrownAllocator(alloc)),
+        }
+    }
+
+    /// Returns a reference to the allocator of the map.
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        &self.inner.allocator().0
+    }
+}
+
+impl<K, V, S, A: Allocator> TryHashMap<K, V, S, A> {
+    /// Returns a reference to the map's `BuildHasher`.
+    #[inline(always)]
+    pub fn hasher(&self) -> &S {
+        self.inner.hasher()
+    }
+}
+
+impl<K, V, S, A: Allocator> TryHashMap<K, V, S, A>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline(always)]
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.contains_key(k)
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[inline(always)]
+    pub fn drain(&mut self) -> Drain<'_, K, V, HashbrownAllocator<A>> {
+        self.inner.drain()
+    }
 
+    #[cfg(feature = "std")]
     #[inline(always)]
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        self.inner.drain()
+    }
+
+    #[inline(always)]
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.inner.get(k)
     }
 
+    #[inline(always)]
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.get_key_value(k)
+    }
+
+    #[inline(always)]
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.get_mut(k)
+    }
+
+    /// Inserts a key-value pair into the map, which is left unchanged on error
+    /// (strong guarantee).
     #[inline]
     pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, TryReserveError> {
-        self.reserve(if self.inner.capacity() == 0 { 4 } else { 1 })?;
+        self.reserve_for_insert()?;
         Ok(self.inner.insert(k, v))
     }
 
+    /// Inserts a key-value pair into the map, giving back the pair if the
+    /// allocation failed. The map is left unchanged on error (strong
+    /// guarantee).
+    #[inline]
+    pub fn insert_give_back(&mut self, k: K, v: V) -> Result<Option<V>, AllocError<(K, V)>> {
+        match self.reserve_for_insert() {
+            Ok(()) => Ok(self.inner.insert(k, v)),
+            Err(e) => Err(AllocError::new((k, v), e)),
+        }
+    }
+
     #[inline(always)]
     pub fn iter(&self) -> Iter<'_, K, V> {
         self.inner.iter()
     }
 
+    #[inline(always)]
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        self.inner.iter_mut()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        self.inner.keys()
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.inner.len()
     }
 
     #[inline(always)]
-    pub fn remove<Q: ?Sized>(&mut self, k: &Q) -> Option<V>
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.inner.remove(k)
     }
 
     #[inline(always)]
-    fn reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
-        self.inner
-            .try_reserve(additional)
-            .map_err(|_| make_try_reserve_error())
+    pub fn remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.remove_entry(k)
+    }
+
+    #[inline(always)]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.inner.retain(f)
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place
+    /// manipulation. Room for one more element is reserved, so that
+    /// inserting in a vacant entry doesn't allocate. The map is left unchanged
+    /// on error (strong guarantee).
+    pub fn try_entry(&mut self, key: K) -> Result<Entry<'_, K, V, S, A>, TryReserveError> {
+        self.reserve_for_insert()?;
+        Ok(match self.inner.entry(key) {
+            InnerEntry::Occupied(inner) => Entry::Occupied(OccupiedEntry {
+                inner,
+                marker: PhantomData,
+            }),
+            InnerEntry::Vacant(inner) => Entry::Vacant(VacantEntry {
+                inner,
+                marker: PhantomData,
+            }),
+        })
+    }
+
+    /// Reserves capacity for at least `additional` more elements, the map is
+    /// left unchanged on error (strong guarantee).
+    #[inline]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        #[cfg(feature = "std")]
+        {
+            self.inner.try_reserve(additional)
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            self.inner
+                .try_reserve(additional)
+                .map_err(|e| ReserveError::from(e).into())
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more elements, as
+    /// `try_reserve`, the error telling the layout requested from the
+    /// allocator and the length the map was grown to. The map is left unchanged
+    /// on error (strong guarantee).
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.len().saturating_add(additional);
+        self.inner.try_reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::HashMap)
+                .with_len(len)
+        })
+    }
+
+    /// Shrinks the capacity of the map with a lower limit, as
+    /// std::collections::HashMap::shrink_to. The elements are moved to a
+    /// newly allocated table, the map is left unchanged if the allocation
+    /// failed (strong guarantee).
+    pub fn try_shrink_to(&mut self, min_capacity: usize) -> Result<(), TryReserveError>
+    where
+        S: Clone,
+        A: Clone,
+    {
+        let capacity = core::cmp::max(self.len(), min_capacity);
+        if capacity >= self.capacity() {
+            return Ok(());
+        }
+        let mut shrunk = self.try_with_capacity_of(capacity)?;
+        if shrunk.capacity() >= self.capacity() {
+            return Ok(());
+        }
+        for (k, v) in self.inner.drain() {
+            shrunk.inner.insert(k, v);
+        }
+        *self = shrunk;
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn values(&self) -> Values<'_, K, V> {
+        self.inner.values()
+    }
+
+    #[inline(always)]
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        self.inner.values_mut()
+    }
+
+    #[inline(always)]
+    fn reserve_for_insert(&mut self) -> Result<(), TryReserveError> {
+        self.try_reserve(if self.inner.capacity() == 0 { 4 } else { 1 })
+    }
+
+    /// Creates an empty map with the hasher and the allocator of `self`, and
+    /// room for `capacity` elements.
+    fn try_with_capacity_of(&self, capacity: usize) -> Result<Self, TryReserveError>
+    where
+        S: Clone,
+        A: Clone,
+    {
+        #[cfg(not(feature = "std"))]
+        let mut map = Self::with_hasher_in(self.hasher().clone(), self.allocator().clone());
+        #[cfg(feature = "std")]
+        let mut map = Self {
+            inner: HashMap::with_hasher(self.hasher().clone()),
+            alloc: PhantomData,
+        };
+        map.try_reserve(capacity)?;
+        Ok(map)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<K, V, S, A: Allocator> IntoIterator for TryHashMap<K, V, S, A> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, HashbrownAllocator<A>>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
     }
 }
 
-impl<K, V> IntoIterator for TryHashMap<K, V> {
+#[cfg(feature = "std")]
+impl<K, V, S, A: Allocator> IntoIterator for TryHashMap<K, V, S, A> {
     type Item = (K, V);
     type IntoIter = IntoIter<K, V>;
 
@@ -103,13 +435,54 @@ impl<K, V> IntoIterator for TryHashMap<K, V> {
     }
 }
 
-impl<K, V> TryClone for TryHashMap<K, V>
+impl<'a, K, V, S, A: Allocator> IntoIterator for &'a TryHashMap<K, V, S, A> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<'a, K, V, S, A: Allocator> IntoIterator for &'a mut TryHashMap<K, V, S, A> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter_mut()
+    }
+}
+
+impl<K, Q, V, S, A: Allocator> Index<&Q> for TryHashMap<K, V, S, A>
+where
+    K: Eq + Hash + Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    /// Returns a reference to the value corresponding to the supplied key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the `TryHashMap`.
+    #[inline]
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K, V, S, A> TryClone for TryHashMap<K, V, S, A>
 where
     K: Eq + Hash + TryClone,
     V: TryClone,
+    S: BuildHasher + Clone,
+    A: Allocator + Clone,
 {
     fn try_clone(&self) -> Result<Self, TryReserveError> {
-        let mut clone = Self::with_capacity(self.inner.len())?;
+        let mut clone = self.try_with_capacity_of(self.inner.len())?;
 
         for (key, value) in self.inner.iter() {
             clone.insert(key.try_clone()?, value.try_clone()?)?;
@@ -119,17 +492,953 @@ where
     }
 }
 
-#[test]
-fn tryhashmap_oom() {
-    match TryHashMap::<char, char>::default().reserve(core::usize::MAX) {
-        Ok(_) => panic!("it should be OOM"),
-        _ => (),
+/// A view into a single entry in a map, which may either be vacant or
+/// occupied. This enum is constructed from `TryHashMap::try_entry`.
+pub enum Entry<'a, K, V, S = DefaultHashBuilder, A: Allocator = Global> {
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V, S, A>),
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V, S, A>),
+}
+
+/// A view into a vacant entry in a `TryHashMap`. It is part of the `Entry`
+/// enum.
+pub struct VacantEntry<'a, K, V, S = DefaultHashBuilder, A: Allocator = Global> {
+    #[cfg(not(feature = "std"))]
+    inner: hashbrown::hash_map::VacantEntry<'a, K, V, S, HashbrownAllocator<A>>,
+    #[cfg(feature = "std")]
+    inner: std::collections::hash_map::VacantEntry<'a, K, V>,
+    marker: PhantomData<&'a (S, A)>,
+}
+
+/// A view into an occupied entry in a `TryHashMap`. It is part of the
+/// `Entry` enum.
+pub struct OccupiedEntry<'a, K, V, S = DefaultHashBuilder, A: Allocator = Global> {
+    #[cfg(not(feature = "std"))]
+    inner: hashbrown::hash_map::OccupiedEntry<'a, K, V, S, HashbrownAllocator<A>>,
+    #[cfg(feature = "std")]
+    inner: std::collections::hash_map::OccupiedEntry<'a, K, V>,
+    marker: PhantomData<&'a (S, A)>,
+}
+
+impl<K: Debug, V: Debug, S, A: Allocator> Debug for Entry<'_, K, V, S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Entry::Vacant(ref v) => f.debug_tuple("Entry").field(v).finish(),
+            Entry::Occupied(ref o) => f.debug_tuple("Entry").field(o).finish(),
+        }
+    }
+}
+
+impl<K: Debug, V, S, A: Allocator> Debug for VacantEntry<'_, K, V, S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+impl<K: Debug, V: Debug, S, A: Allocator> Debug for OccupiedEntry<'_, K, V, S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher, A: Allocator> Entry<'a, K, V, S, A> {
+    /// Ensures a value is in the entry by inserting the default if empty,
+    /// and returns a mutable reference to the value in the entry. The map is
+    /// left unchanged on error (strong guarantee).
+    #[inline]
+    pub fn or_try_insert(self, default: V) -> Result<&'a mut V, TryReserveError> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry.try_insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the
+    /// default function if empty, and returns a mutable reference to the
+    /// value in the entry. The map is left unchanged on error (strong
+    /// guarantee).
+    #[inline]
+    pub fn or_try_insert_with<F: FnOnce() -> V>(
+        self,
+        default: F,
+    ) -> Result<&'a mut V, TryReserveError> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry.try_insert(default()),
+        }
+    }
+
+    /// Returns a reference to this entry's key.
+    #[inline]
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any
+    /// potential inserts into the map.
+    #[inline]
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut V),
+    {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V, S, A: Allocator> VacantEntry<'a, K, V, S, A> {
+    /// Gets a reference to the key that would be used when inserting a
+    /// value through the `VacantEntry`.
+    #[inline(always)]
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    /// Take ownership of the key.
+    #[inline(always)]
+    pub fn into_key(self) -> K {
+        self.inner.into_key()
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher, A: Allocator> VacantEntry<'a, K, V, S, A> {
+    /// Sets the value of the entry with the `VacantEntry`'s key, and
+    /// returns a mutable reference to it. It does not allocate, as `try_entry`
+    /// reserved the room.
+    #[inline]
+    pub fn try_insert(self, value: V) -> Result<&'a mut V, TryReserveError> {
+        // the room was reserved by try_entry, so this doesn't allocate
+        Ok(self.inner.insert(value))
+    }
+}
+
+impl<'a, K, V, S, A: Allocator> OccupiedEntry<'a, K, V, S, A> {
+    /// Gets a reference to the key in the entry.
+    #[inline(always)]
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    /// Gets a reference to the value in the entry.
+    #[inline(always)]
+    pub fn get(&self) -> &V {
+        self.inner.get()
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut V {
+        self.inner.get_mut()
+    }
+
+    /// Converts the entry into a mutable reference to its value, with the
+    /// lifetime of the map.
+    #[inline(always)]
+    pub fn into_mut(self) -> &'a mut V {
+        self.inner.into_mut()
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    #[inline(always)]
+    pub fn insert(&mut self, value: V) -> V {
+        self.inner.insert(value)
+    }
+
+    /// Takes the value out of the entry, and returns it.
+    #[inline(always)]
+    pub fn remove(self) -> V {
+        self.inner.remove()
+    }
+
+    /// Takes the key and the value out of the entry, and returns them.
+    #[inline(always)]
+    pub fn remove_entry(self) -> (K, V) {
+        self.inner.remove_entry()
+    }
+}
+
+/// TryHashSet is a thin wrapper around std or hashbrown HashSet to provide
+/// support for fallible allocation.
+///
+/// See the crate documentation for more.
+///
+/// The table is allocated in the allocator `A`, as for `TryHashMap`.
+pub struct TryHashSet<T, S = DefaultHashBuilder, A: Allocator = Global> {
+    #[cfg(not(feature = "std"))]
+    inner: HashSet<T, S, A>,
+    #[cfg(feature = "std")]
+    inner: HashSet<T, S>,
+    #[cfg(feature = "std")]
+    alloc: PhantomData<A>,
+}
+
+impl<T, S: Default> Default for TryHashSet<T, S> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::with_hasher(Default::default())
+    }
+}
+
+impl<T: Debug, S, A: Allocator> Debug for TryHashSet<T, S, A> {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.inner.fmt(f)
     }
 }
 
-#[cold]
-fn make_try_reserve_error() -> TryReserveError {
-    let mut v: alloc::vec::Vec<[u8; 1024]> = alloc::vec::Vec::new();
-    // this will always overflow capacity
-    v.try_reserve(!0).unwrap_err()
+impl<T> TryHashSet<T>
+where
+    T: Eq + Hash,
+{
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Self::with_capacity_and_hasher(capacity, Default::default())
+    }
+}
+
+impl<T, S> TryHashSet<T, S> {
+    /// Creates an empty set which will use the given hash builder to hash
+    /// values. This doesn't allocate.
+    #[inline]
+    pub fn with_hasher(hash_builder: S) -> Self {
+        #[cfg(not(feature = "std"))]
+        {
+            Self::with_hasher_in(hash_builder, Global)
+        }
+        #[cfg(feature = "std")]
+        {
+            Self {
+                inner: HashSet::with_hasher(hash_builder),
+                alloc: PhantomData,
+            }
+        }
+    }
+
+    #[inline]
+    pub fn with_capacity_and_hasher(
+        capacity: usize,
+        hash_builder: S,
+    ) -> Result<Self, TryReserveError>
+    where
+        T: Eq + Hash,
+        S: BuildHasher,
+    {
+        let mut set = Self::with_hasher(hash_builder);
+        set.try_reserve(capacity)?;
+        Ok(set)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, A: Allocator> TryHashSet<T, DefaultHashBuilder, A> {
+    /// Creates an empty set allocating its table in `alloc`. This doesn't
+    /// allocate.
+    #[inline]
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_hasher_in(Default::default(), alloc)
+    }
+
+    /// Creates a set allocated in `alloc` with room for at least `capacity`
+    /// values.
+    #[inline]
+    pub fn try_with_capacity_in(capacity: usize, alloc: A) -> Result<Self, TryReserveError>
+    where
+        T: Eq + Hash,
+    {
+        let mut set = Self::new_in(alloc);
+        set.try_reserve(capacity)?;
+        Ok(set)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, S, A: Allocator> TryHashSet<T, S, A> {
+    /// Creates an empty set which will use the given hash builder to hash
+    /// values, and allocate its table in `alloc`. This doesn't allocate.
+    #[inline]
+    pub fn with_hasher_in(hash_builder: S, alloc: A) -> Self {
+        Self {
+            inner: HashSet::with_hasher_in(hash_builder, HashbrownAllocator(alloc)),
+        }
+    }
+
+    /// Returns a reference to the allocator of the set.
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        &self.inner.allocator().0
+    }
+}
+
+impl<T, S, A: Allocator> TryHashSet<T, S, A> {
+    /// Returns a reference to the set's `BuildHasher`.
+    #[inline(always)]
+    pub fn hasher(&self) -> &S {
+        self.inner.hasher()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, S, A: Allocator> TryHashSet<T, S, A>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Visits the values representing the difference, i.e., the values
+    /// that are in `self` but not in `other`.
+    #[inline(always)]
+    pub fn difference<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> Difference<'a, T, S, HashbrownAllocator<A>> {
+        self.inner.difference(&other.inner)
+    }
+
+    #[inline(always)]
+    pub fn drain(&mut self) -> SetDrain<'_, T, HashbrownAllocator<A>> {
+        self.inner.drain()
+    }
+
+    /// Visits the values representing the intersection, i.e., the values
+    /// that are both in `self` and `other`.
+    #[inline(always)]
+    pub fn intersection<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> Intersection<'a, T, S, HashbrownAllocator<A>> {
+        self.inner.intersection(&other.inner)
+    }
+
+    /// Visits the values representing the symmetric difference, i.e., the
+    /// values that are in `self` or in `other` but not in both.
+    #[inline(always)]
+    pub fn symmetric_difference<'a>(
+        &'a self,
+        other: &'a Self,
+    ) -> SymmetricDifference<'a, T, S, HashbrownAllocator<A>> {
+        self.inner.symmetric_difference(&other.inner)
+    }
+
+    /// Visits the values representing the union, i.e., all the values in
+    /// `self` or `other`, without duplicates.
+    #[inline(always)]
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, S, HashbrownAllocator<A>> {
+        self.inner.union(&other.inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, S, A: Allocator> TryHashSet<T, S, A>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    /// Visits the values representing the difference, i.e., the values
+    /// that are in `self` but not in `other`.
+    #[inline(always)]
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T, S> {
+        self.inner.difference(&other.inner)
+    }
+
+    #[inline(always)]
+    pub fn drain(&mut self) -> SetDrain<'_, T> {
+        self.inner.drain()
+    }
+
+    /// Visits the values representing the intersection, i.e., the values
+    /// that are both in `self` and `other`.
+    #[inline(always)]
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T, S> {
+        self.inner.intersection(&other.inner)
+    }
+
+    /// Visits the values representing the symmetric difference, i.e., the
+    /// values that are in `self` or in `other` but not in both.
+    #[inline(always)]
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T, S> {
+        self.inner.symmetric_difference(&other.inner)
+    }
+
+    /// Visits the values representing the union, i.e., all the values in
+    /// `self` or `other`, without duplicates.
+    #[inline(always)]
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, S> {
+        self.inner.union(&other.inner)
+    }
+}
+
+impl<T, S, A: Allocator> TryHashSet<T, S, A>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline(always)]
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.contains(value)
+    }
+
+    #[inline(always)]
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.get(value)
+    }
+
+    #[inline(always)]
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.inner.is_disjoint(&other.inner)
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.inner.is_subset(&other.inner)
+    }
+
+    #[inline(always)]
+    pub fn is_superset(&self, other: &Self) -> bool {
+        self.inner.is_superset(&other.inner)
+    }
+
+    #[inline(always)]
+    pub fn iter(&self) -> SetIter<'_, T> {
+        self.inner.iter()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    #[inline(always)]
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.remove(value)
+    }
+
+    /// Adds a value to the set, replacing the existing value, if any, that
+    /// is equal to the given one. Returns the replaced value. The set is left
+    /// unchanged on error (strong guarantee).
+    #[inline]
+    pub fn replace(&mut self, value: T) -> Result<Option<T>, TryReserveError> {
+        self.reserve_for_insert()?;
+        Ok(self.inner.replace(value))
+    }
+
+    #[inline(always)]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.inner.retain(f)
+    }
+
+    #[inline(always)]
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.take(value)
+    }
+
+    /// Adds a value to the set, returns whether the value was newly
+    /// inserted. The set is left unchanged on error (strong guarantee).
+    #[inline]
+    pub fn try_insert(&mut self, value: T) -> Result<bool, TryReserveError> {
+        self.reserve_for_insert()?;
+        Ok(self.inner.insert(value))
+    }
+
+    /// Adds a value to the set, giving back the value if the allocation
+    /// failed. The set is left unchanged on error (strong guarantee).
+    #[inline]
+    pub fn try_insert_give_back(&mut self, value: T) -> Result<bool, AllocError<T>> {
+        match self.reserve_for_insert() {
+            Ok(()) => Ok(self.inner.insert(value)),
+            Err(e) => Err(AllocError::new(value, e)),
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more elements, the set is
+    /// left unchanged on error (strong guarantee).
+    #[inline]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        #[cfg(feature = "std")]
+        {
+            self.inner.try_reserve(additional)
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            self.inner
+                .try_reserve(additional)
+                .map_err(|e| ReserveError::from(e).into())
+        }
+    }
+
+    /// Reserves capacity for at least `additional` more elements, as
+    /// `try_reserve`, the error telling the layout requested from the
+    /// allocator and the length the set was grown to. The set is left unchanged
+    /// on error (strong guarantee).
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.len().saturating_add(additional);
+        self.inner.try_reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::HashSet)
+                .with_len(len)
+        })
+    }
+
+    #[inline(always)]
+    fn reserve_for_insert(&mut self) -> Result<(), TryReserveError> {
+        self.try_reserve(if self.inner.capacity() == 0 { 4 } else { 1 })
+    }
+}
+
+impl<T, S, A> TryHashSet<T, S, A>
+where
+    T: Eq + Hash + TryClone,
+    S: BuildHasher,
+    A: Allocator,
+{
+    /// Clones the values of the difference of `self` and `other` into
+    /// `dest`. Room for all of them is reserved first, on failure `dest`
+    /// holds the values which could be cloned (basic guarantee).
+    pub fn try_difference_into(
+        &self,
+        other: &Self,
+        dest: &mut Self,
+    ) -> Result<(), TryReserveError> {
+        dest.try_extend_cloned(self.difference(other))
+    }
+
+    /// Clones the values of the intersection of `self` and `other` into
+    /// `dest`. Room for all of them is reserved first, on failure `dest`
+    /// holds the values which could be cloned (basic guarantee).
+    pub fn try_intersection_into(
+        &self,
+        other: &Self,
+        dest: &mut Self,
+    ) -> Result<(), TryReserveError> {
+        dest.try_extend_cloned(self.intersection(other))
+    }
+
+    /// Clones the values of the symmetric difference of `self` and `other`
+    /// into `dest`. Room for all of them is reserved first, on failure
+    /// `dest` holds the values which could be cloned (basic guarantee).
+    pub fn try_symmetric_difference_into(
+        &self,
+        other: &Self,
+        dest: &mut Self,
+    ) -> Result<(), TryReserveError> {
+        dest.try_extend_cloned(self.symmetric_difference(other))
+    }
+
+    /// Clones the values of the union of `self` and `other` into `dest`.
+    /// Room for all of them is reserved first, on failure `dest` holds the
+    /// values which could be cloned (basic guarantee).
+    pub fn try_union_into(&self, other: &Self, dest: &mut Self) -> Result<(), TryReserveError> {
+        dest.try_extend_cloned(self.union(other))
+    }
+
+    fn try_extend_cloned<'a, I>(&mut self, iter: I) -> Result<(), TryReserveError>
+    where
+        I: Iterator<Item = &'a T> + Clone,
+        T: 'a,
+    {
+        self.try_reserve(iter.clone().count())?;
+        for value in iter {
+            self.inner.insert(value.try_clone()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T, S, A: Allocator> IntoIterator for TryHashSet<T, S, A> {
+    type Item = T;
+    type IntoIter = SetIntoIter<T, HashbrownAllocator<A>>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T, S, A: Allocator> IntoIterator for TryHashSet<T, S, A> {
+    type Item = T;
+    type IntoIter = SetIntoIter<T>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a, T, S, A: Allocator> IntoIterator for &'a TryHashSet<T, S, A> {
+    type Item = &'a T;
+    type IntoIter = SetIter<'a, T>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<T, S, A: Allocator> PartialEq for TryHashSet<T, S, A>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<T, S, A: Allocator> Eq for TryHashSet<T, S, A>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+}
+
+impl<T, S, A> TryClone for TryHashSet<T, S, A>
+where
+    T: Eq + Hash + TryClone,
+    S: BuildHasher + Clone,
+    A: Allocator + Clone,
+{
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        #[cfg(not(feature = "std"))]
+        let mut clone = Self::with_hasher_in(self.hasher().clone(), self.allocator().clone());
+        #[cfg(feature = "std")]
+        let mut clone = Self {
+            inner: HashSet::with_hasher(self.hasher().clone()),
+            alloc: PhantomData,
+        };
+        clone.try_reserve(self.inner.len())?;
+
+        for value in self.inner.iter() {
+            clone.inner.insert(value.try_clone()?);
+        }
+
+        Ok(clone)
+    }
+}
+
+#[test]
+fn tryhashmap_oom() {
+    assert!(
+        TryHashMap::<char, char>::default()
+            .try_reserve(usize::MAX)
+            .is_err(),
+        "it should be OOM"
+    );
+}
+
+// the table sizes below don't fit in a 32-bit address space
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn tryhashmap_reserve_detailed() {
+    use crate::ReserveErrorKind;
+
+    let mut map = TryHashMap::<u64, u64>::default();
+    map.insert(1, 1).unwrap();
+    let e = map.try_reserve_detailed(usize::MAX).unwrap_err();
+    assert_eq!(e.kind(), ReserveErrorKind::CapacityOverflow);
+    assert_eq!(e.len(), Some(usize::MAX));
+
+    // the table fits in isize::MAX bytes but not in the address space
+    let e = map.try_reserve_detailed(1 << 50).unwrap_err();
+    assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+    assert_eq!(e.collection(), Some(CollectionKind::HashMap));
+    assert_eq!(e.len(), Some((1 << 50) + 1));
+    // std only hands the layout out on nightly
+    #[cfg(any(not(feature = "std"), feature = "unstable"))]
+    assert!(e.layout().unwrap().size() > 16 << 50);
+    #[cfg(feature = "unstable")]
+    assert!(matches!(
+        map.try_reserve(1 << 50).unwrap_err().kind(),
+        alloc::collections::TryReserveErrorKind::AllocError { .. }
+    ));
+    // the kind is kept through TryReserveError without std too
+    let e = ReserveError::from(map.try_reserve(1 << 50).unwrap_err());
+    assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+
+    let mut set = TryHashSet::<u64>::default();
+    let e = set.try_reserve_detailed(1 << 50).unwrap_err();
+    assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+    assert_eq!(e.collection(), Some(CollectionKind::HashSet));
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn tryhashmap_insert_give_back() {
+    let mut map: TryHashMap<_, _> = TryHashMap::default();
+    assert_eq!(map.insert_give_back(1, "a").unwrap(), None);
+    assert_eq!(map.insert_give_back(1, "b").unwrap(), Some("a"));
+    assert_eq!(map.get(&1), Some(&"b"));
+}
+
+#[test]
+fn tryhashmap_entry() {
+    let mut map: TryHashMap<_, _> = TryHashMap::default();
+    *map.try_entry("a").unwrap().or_try_insert(1).unwrap() += 10;
+    *map.try_entry("a").unwrap().or_try_insert(1).unwrap() += 10;
+    assert_eq!(map["a"], 21);
+    map.try_entry("b")
+        .unwrap()
+        .and_modify(|v| *v = 0)
+        .or_try_insert_with(|| 2)
+        .unwrap();
+    map.try_entry("b")
+        .unwrap()
+        .and_modify(|v| *v += 1)
+        .or_try_insert_with(|| 0)
+        .unwrap();
+    assert_eq!(map["b"], 3);
+    assert_eq!(map.try_entry("c").unwrap().key(), &"c");
+    match map.try_entry("a").unwrap() {
+        Entry::Occupied(mut o) => {
+            assert_eq!(o.insert(5), 21);
+            assert_eq!(o.remove_entry(), ("a", 5));
+        }
+        Entry::Vacant(_) => panic!("a should be occupied"),
+    }
+    match map.try_entry("c").unwrap() {
+        Entry::Vacant(v) => assert_eq!(v.into_key(), "c"),
+        Entry::Occupied(_) => panic!("c should be vacant"),
+    }
+    assert_eq!(map.len(), 1);
+}
+
+#[test]
+fn tryhashmap_api() {
+    let mut map = TryHashMap::with_capacity(16).unwrap();
+    assert!(map.capacity() >= 16);
+    for i in 0..10 {
+        map.insert(i, i * 10).unwrap();
+    }
+    assert!(map.contains_key(&3));
+    assert_eq!(map.get_key_value(&3), Some((&3, &30)));
+    *map.get_mut(&3).unwrap() += 1;
+    assert_eq!(map[&3], 31);
+    assert_eq!(map.remove_entry(&3), Some((3, 31)));
+    assert!(!map.contains_key(&3));
+    map.retain(|k, _| k % 2 == 0);
+    assert_eq!(map.len(), 5);
+    for v in map.values_mut() {
+        *v += 1;
+    }
+    for (_, v) in map.iter_mut() {
+        *v += 1;
+    }
+    let mut keys: Vec<_> = map.keys().copied().collect();
+    keys.sort_unstable();
+    assert_eq!(keys, [0, 2, 4, 6, 8]);
+    let mut values: Vec<_> = map.values().copied().collect();
+    values.sort_unstable();
+    assert_eq!(values, [2, 22, 42, 62, 82]);
+    let mut drained: Vec<_> = map.drain().collect();
+    drained.sort_unstable();
+    assert_eq!(drained[0], (0, 2));
+    assert!(map.is_empty());
+    map.insert(1, 1).unwrap();
+    map.clear();
+    assert!(map.is_empty());
+}
+
+#[test]
+fn tryhashmap_shrink_to() {
+    let mut map = TryHashMap::with_capacity(1000).unwrap();
+    for i in 0..10 {
+        map.insert(i, i).unwrap();
+    }
+    let capacity = map.capacity();
+    map.try_shrink_to(100).unwrap();
+    assert!(map.capacity() >= 100 && map.capacity() < capacity);
+    map.try_shrink_to(0).unwrap();
+    assert!(map.capacity() >= 10 && map.capacity() < 100);
+    assert_eq!(map.len(), 10);
+    assert!((0..10).all(|i| map[&i] == i));
+    map.try_shrink_to(1000).unwrap();
+    assert!(map.capacity() < 100);
+}
+
+#[test]
+fn tryhashmap_with_hasher() {
+    use core::hash::{BuildHasherDefault, Hasher};
+
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for b in bytes {
+                self.0 = self.0 << 8 | u64::from(*b);
+            }
+        }
+    }
+
+    type Identity = BuildHasherDefault<IdentityHasher>;
+
+    let mut map = TryHashMap::with_hasher(Identity::default());
+    assert!(map.is_empty());
+    assert_eq!(map.capacity(), 0);
+    map.insert(1u32, 10u32).unwrap();
+    *map.try_entry(2).unwrap().or_try_insert(20).unwrap() += 1;
+    assert_eq!(map[&2], 21);
+    let clone = map.try_clone().unwrap();
+    assert_eq!(clone.len(), 2);
+    assert_eq!(clone[&1], 10);
+
+    let mut map: TryHashMap<u32, u32, Identity> =
+        TryHashMap::with_capacity_and_hasher(100, Default::default()).unwrap();
+    assert!(map.capacity() >= 100);
+    map.insert(1, 1).unwrap();
+    map.try_shrink_to(0).unwrap();
+    assert!(map.capacity() < 100);
+    assert_eq!(map.hasher().build_hasher().finish(), 0);
+
+    let mut map = TryHashMap::<u32, u32, Identity>::default();
+    map.insert(3, 30).unwrap();
+    assert_eq!(map[&3], 30);
+    let mut set = TryHashSet::<u32, Identity>::default();
+    assert!(set.try_insert(3).unwrap());
+}
+
+#[test]
+fn tryhashset() {
+    let mut set: TryHashSet<_> = TryHashSet::default();
+    assert!(set.try_insert(1).unwrap());
+    assert!(!set.try_insert(1).unwrap());
+    assert!(set.try_insert_give_back(2).unwrap());
+    assert!(set.contains(&1));
+    assert_eq!(set.get(&2), Some(&2));
+    assert_eq!(set.replace(2).unwrap(), Some(2));
+    assert_eq!(set.replace(3).unwrap(), None);
+    assert_eq!(set.len(), 3);
+    assert!(set.remove(&3));
+    assert_eq!(set.take(&2), Some(2));
+    assert_eq!(set.take(&2), None);
+    for i in 0..10 {
+        set.try_insert(i).unwrap();
+    }
+    set.retain(|v| v % 2 == 0);
+    let mut values: Vec<_> = set.iter().copied().collect();
+    values.sort_unstable();
+    assert_eq!(values, [0, 2, 4, 6, 8]);
+    let clone = set.try_clone().unwrap();
+    assert_eq!(clone, set);
+    assert_eq!(set.drain().count(), 5);
+    assert!(set.is_empty());
+}
+
+#[test]
+fn tryhashset_algebra() {
+    let mut a = TryHashSet::with_capacity(4).unwrap();
+    let mut b = TryHashSet::default();
+    for i in 0..4 {
+        a.try_insert(i).unwrap();
+        b.try_insert(i + 2).unwrap();
+    }
+    let sorted = |set: &TryHashSet<u32>| {
+        let mut v: Vec<_> = set.iter().copied().collect();
+        v.sort_unstable();
+        v
+    };
+
+    let mut union = TryHashSet::default();
+    a.try_union_into(&b, &mut union).unwrap();
+    assert_eq!(sorted(&union), [0, 1, 2, 3, 4, 5]);
+    assert_eq!(a.union(&b).count(), 6);
+
+    let mut dest = TryHashSet::default();
+    a.try_intersection_into(&b, &mut dest).unwrap();
+    assert_eq!(sorted(&dest), [2, 3]);
+    assert_eq!(a.intersection(&b).count(), 2);
+
+    let mut dest = TryHashSet::default();
+    a.try_difference_into(&b, &mut dest).unwrap();
+    assert_eq!(sorted(&dest), [0, 1]);
+    assert_eq!(a.difference(&b).count(), 2);
+
+    let mut dest = TryHashSet::default();
+    a.try_symmetric_difference_into(&b, &mut dest).unwrap();
+    assert_eq!(sorted(&dest), [0, 1, 4, 5]);
+    assert_eq!(a.symmetric_difference(&b).count(), 4);
+
+    assert!(a.is_subset(&union) && b.is_subset(&union));
+    assert!(union.is_superset(&a) && !a.is_superset(&union));
+    assert!(!a.is_disjoint(&b));
+    let mut odd = TryHashSet::default();
+    odd.try_insert(7).unwrap();
+    assert!(odd.is_disjoint(&a));
+}
+
+#[cfg(not(feature = "std"))]
+#[test]
+fn tryhashmap_custom_allocator() {
+    use crate::allocator::tests::Bounded;
+
+    let alloc = Bounded::new(1);
+    let mut map = TryHashMap::try_with_capacity_in(8, &alloc).unwrap();
+    assert_eq!(alloc.live(), 1);
+    for i in 0..map.capacity() {
+        map.insert(i, i).unwrap();
+    }
+    // growing or cloning the table needs a second block
+    assert!(map.insert(map.capacity(), 0).is_err());
+    assert!(map.try_clone().is_err());
+    assert!(map.try_entry(usize::MAX).is_err());
+    assert_eq!(map.len(), map.capacity());
+    drop(map);
+    assert_eq!(alloc.live(), 0);
+
+    let mut set = TryHashSet::new_in(&alloc);
+    set.try_insert(1).unwrap();
+    let mut dest = TryHashSet::new_in(&alloc);
+    assert!(set.try_union_into(&set, &mut dest).is_err());
+    assert_eq!(set.into_iter().collect::<Vec<_>>(), [1]);
+    assert_eq!(alloc.live(), 0);
 }
@@ -20,44 +20,150 @@
 //! This list may not be exhaustive. Exercise caution when implementing
 //! any new traits to ensure they won't potentially allocate in a way that
 //! can't return a Result to indicate allocation failure.
+//!
+//! # Exception safety
+//!
+//! When a method returns an allocation error, the collection it was called
+//! on is left as it was before the call (strong guarantee): it holds the
+//! same elements in the same order, only its capacity may have grown. The
+//! elements cloned before the failure are dropped, and the `*_give_back`
+//! methods return the element which could not be inserted.
+//!
+//! The methods which only leave the collection valid and without leaks, but
+//! possibly modified (basic guarantee), say so in their documentation. These
+//! are `btree::BTreeMap::try_extend` and `btree::BTreeSet::try_extend`,
+//! which keep the elements inserted before the failure, and the
+//! `try_difference_into`, `try_intersection_into`,
+//! `try_symmetric_difference_into` and `try_union_into` methods of
+//! `TryHashSet`, which keep the values cloned into `dest` before the
+//! failure.
+//!
+//! With the `testing` feature, `testing::check_each_failure` can check these
+//! guarantees by failing each allocation of an operation in turn.
 
 #![cfg_attr(not(test), no_std)]
 #![cfg_attr(feature = "unstable", feature(try_reserve_kind))]
 #![cfg_attr(feature = "unstable", feature(min_specialization))]
 #![cfg_attr(feature = "unstable", feature(allocator_api))]
-#![cfg_attr(feature = "unstable", feature(dropck_eyepatch))]
-#![cfg_attr(feature = "unstable", feature(ptr_internals))]
-#![cfg_attr(feature = "unstable", feature(core_intrinsics))]
-#![cfg_attr(feature = "unstable", feature(maybe_uninit_slice))]
-#![cfg_attr(feature = "unstable", feature(maybe_uninit_uninit_array))]
 
 extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+pub mod error;
+pub use error::*;
+pub mod allocator;
 pub mod boxed;
 pub use boxed::*;
+#[cfg(target_has_atomic = "ptr")]
+pub mod budget;
 #[macro_use]
 pub mod vec;
 pub use vec::*;
+pub mod string;
+pub use string::*;
+pub mod vec_deque;
+pub use vec_deque::*;
+pub mod linked_list;
+pub use linked_list::TryLinkedList;
+pub mod small_vec;
+pub use small_vec::TrySmallVec;
 pub mod rc;
 pub use rc::*;
 #[cfg(target_has_atomic = "ptr")]
 pub mod arc;
 #[cfg(target_has_atomic = "ptr")]
 pub use arc::*;
-#[cfg(feature = "unstable")]
+pub mod binary_heap;
+pub use binary_heap::*;
+#[cfg(feature = "btree")]
 pub mod btree;
-#[cfg(all(feature = "hashmap", not(feature = "unstable")))]
+#[cfg(feature = "hashmap")]
 pub mod hashmap;
-#[cfg(all(feature = "hashmap", not(feature = "unstable")))]
+#[cfg(feature = "hashmap")]
 pub use hashmap::*;
 #[macro_use]
 pub mod format;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod try_clone;
 
 pub use alloc::collections::TryReserveError;
 
+#[cold]
+pub(crate) fn make_try_reserve_error() -> TryReserveError {
+    let mut v: alloc::vec::Vec<[u8; 1024]> = alloc::vec::Vec::new();
+    // this will always overflow capacity
+    v.try_reserve(!0).unwrap_err()
+}
+
+/// Build the error reported when the allocator returned null for `layout`.
+/// The layout can only be carried by the error on nightly, as
+/// `TryReserveErrorKind` is unstable. On stable, the error of a refused
+/// allocation of `isize::MAX` bytes is returned instead, which has the same
+/// kind but not the same layout. It is made once and cached where atomics
+/// are available, so only the first error asks the global allocator.
+#[cold]
+pub(crate) fn make_alloc_error(layout: alloc::alloc::Layout) -> TryReserveError {
+    #[cfg(feature = "unstable")]
+    {
+        alloc::collections::TryReserveErrorKind::AllocError {
+            layout,
+            non_exhaustive: (),
+        }
+        .into()
+    }
+    #[cfg(all(not(feature = "unstable"), target_has_atomic = "8"))]
+    {
+        use core::cell::UnsafeCell;
+        use core::mem::MaybeUninit;
+        use core::sync::atomic::{AtomicU8, Ordering};
+
+        const EMPTY: u8 = 0;
+        const WRITING: u8 = 1;
+        const READY: u8 = 2;
+
+        struct Cached(UnsafeCell<MaybeUninit<TryReserveError>>);
+        // written once, before `READY` is published, and only read after
+        unsafe impl Sync for Cached {}
+
+        static STATE: AtomicU8 = AtomicU8::new(EMPTY);
+        static ERROR: Cached = Cached(UnsafeCell::new(MaybeUninit::uninit()));
+
+        let _ = layout;
+        if STATE.load(Ordering::Acquire) == READY {
+            return unsafe { (*ERROR.0.get()).assume_init_ref().clone() };
+        }
+        let e = refused_alloc_error();
+        // a thread losing the race keeps the error it made
+        if STATE
+            .compare_exchange(EMPTY, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            unsafe { (*ERROR.0.get()).write(e.clone()) };
+            STATE.store(READY, Ordering::Release);
+        }
+        e
+    }
+    #[cfg(all(not(feature = "unstable"), not(target_has_atomic = "8")))]
+    {
+        let _ = layout;
+        refused_alloc_error()
+    }
+}
+
+/// Asks the global allocator for `isize::MAX` bytes, returning the error of
+/// its refusal. No allocator is expected to hand them out, but if one did,
+/// the block is freed here and a capacity overflow is returned instead.
+#[cfg(not(feature = "unstable"))]
+#[cold]
+fn refused_alloc_error() -> TryReserveError {
+    let mut v: alloc::vec::Vec<u8> = alloc::vec::Vec::new();
+    v.try_reserve_exact(isize::MAX as usize)
+        .err()
+        .unwrap_or_else(make_try_reserve_error)
+}
+
 #[cfg(feature = "std_io")]
 pub use vec::std_io::*;
 
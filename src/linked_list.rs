@@ -0,0 +1,897 @@
+//! Implement a Fallible doubly-linked list
+//!
+//! `alloc::collections::LinkedList` allocates a node for each element and
+//! aborts if it can't, so the list is reimplemented here with the nodes
+//! allocated fallibly, in the global allocator or the one given to
+//! `new_in`. The insertions give the element back when the node could not
+//! be allocated.
+use super::TryClone;
+use crate::allocator::{try_allocate, Allocator, Global};
+use crate::vec::TryFromIterator;
+use crate::{AllocError, TryReserveError};
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::fmt;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+
+/// TryLinkedList is a doubly-linked list whose nodes are allocated
+/// fallibly.
+///
+/// `append` and the cursor splices relink the nodes of another list, which
+/// are then freed by the allocator of this one: all the instances of `A`
+/// must be able to free the blocks of each other, as `Global` or references
+/// to the same allocator do. Two `LimitedAllocator`s charging different
+/// budgets, for instance, must not be mixed this way.
+///
+/// See the crate documentation for more.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::TryLinkedList;
+///
+/// let mut list = TryLinkedList::new();
+/// list.try_push_back(2).unwrap();
+/// list.try_push_front(1).unwrap();
+/// list.try_push_back(3).unwrap();
+/// assert!(list.iter().copied().eq(1..=3));
+/// assert_eq!(list.pop_front(), Some(1));
+/// ```
+pub struct TryLinkedList<T, A: Allocator = Global> {
+    head: Option<NonNull<Node<T>>>,
+    tail: Option<NonNull<Node<T>>>,
+    len: usize,
+    alloc: A,
+    marker: PhantomData<Box<Node<T>>>,
+}
+
+struct Node<T> {
+    next: Option<NonNull<Node<T>>>,
+    prev: Option<NonNull<Node<T>>>,
+    element: T,
+}
+
+impl<T> Node<T> {
+    /// Allocates an unlinked node in `alloc`, giving back `element` on
+    /// failure.
+    fn try_new_in<A: Allocator>(element: T, alloc: &A) -> Result<NonNull<Node<T>>, AllocError<T>> {
+        let ptr = match try_allocate(alloc, Layout::new::<Node<T>>()) {
+            Ok(ptr) => ptr.cast::<Node<T>>(),
+            Err(e) => return Err(AllocError::new(element, e)),
+        };
+        let node = Node {
+            next: None,
+            prev: None,
+            element,
+        };
+        unsafe { ptr.as_ptr().write(node) };
+        Ok(ptr)
+    }
+
+    /// Deallocates the unlinked `node` from `alloc`, returning its element.
+    unsafe fn free_in<A: Allocator>(node: NonNull<Node<T>>, alloc: &A) -> T {
+        let element = ptr::read(&(*node.as_ptr()).element);
+        alloc.deallocate(node.cast(), Layout::new::<Node<T>>());
+        element
+    }
+}
+
+unsafe impl<T: Send, A: Allocator + Send> Send for TryLinkedList<T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for TryLinkedList<T, A> {}
+
+impl<T> Default for TryLinkedList<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for TryLinkedList<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, A: Allocator> PartialEq for TryLinkedList<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, A: Allocator> Eq for TryLinkedList<T, A> {}
+
+impl<T, A: Allocator> Drop for TryLinkedList<T, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T> TryLinkedList<T> {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+}
+
+impl<T, A: Allocator> TryLinkedList<T, A> {
+    /// Creates an empty list whose nodes will be allocated in `alloc`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #![cfg_attr(feature = "unstable", feature(allocator_api))]
+    /// use fallible_collections::allocator::Global;
+    /// use fallible_collections::TryLinkedList;
+    ///
+    /// let mut list = TryLinkedList::new_in(&Global);
+    /// list.try_push_back(1).unwrap();
+    /// assert_eq!(list.len(), 1);
+    /// ```
+    #[inline(always)]
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+            alloc,
+            marker: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Moves all the elements of `other` to the back of the list, leaving
+    /// `other` empty. The nodes are relinked, so this never allocates: see
+    /// the type documentation for the allocators this requires.
+    pub fn append(&mut self, other: &mut Self) {
+        match self.tail {
+            None => core::mem::swap(self, other),
+            Some(tail) => {
+                if let Some(other_head) = other.head.take() {
+                    unsafe {
+                        (*tail.as_ptr()).next = Some(other_head);
+                        (*other_head.as_ptr()).prev = Some(tail);
+                    }
+                    self.tail = other.tail.take();
+                    self.len += core::mem::replace(&mut other.len, 0);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn back(&self) -> Option<&T> {
+        self.tail.map(|node| unsafe { &(*node.as_ptr()).element })
+    }
+
+    #[inline]
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        self.tail
+            .map(|node| unsafe { &mut (*node.as_ptr()).element })
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    pub fn contains(&self, x: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.iter().any(|e| e == x)
+    }
+
+    /// Returns a cursor pointing at the back element, or at the "ghost"
+    /// non-element if the list is empty.
+    #[inline]
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T, A> {
+        CursorMut {
+            index: self.len.saturating_sub(1),
+            current: self.tail,
+            list: self,
+        }
+    }
+
+    /// Returns a cursor pointing at the front element, or at the "ghost"
+    /// non-element if the list is empty.
+    #[inline]
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T, A> {
+        CursorMut {
+            index: 0,
+            current: self.head,
+            list: self,
+        }
+    }
+
+    #[inline]
+    pub fn front(&self) -> Option<&T> {
+        self.head.map(|node| unsafe { &(*node.as_ptr()).element })
+    }
+
+    #[inline]
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        self.head
+            .map(|node| unsafe { &mut (*node.as_ptr()).element })
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            head: self.head,
+            tail: self.tail,
+            len: self.len,
+            marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            head: self.head,
+            tail: self.tail,
+            len: self.len,
+            marker: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn pop_back(&mut self) -> Option<T> {
+        let node = self.pop_back_node()?;
+        Some(unsafe { Node::free_in(node, &self.alloc) })
+    }
+
+    #[inline]
+    pub fn pop_front(&mut self) -> Option<T> {
+        let node = self.pop_front_node()?;
+        Some(unsafe { Node::free_in(node, &self.alloc) })
+    }
+
+    /// Appends an element to the back of the list, giving back the element
+    /// if the node could not be allocated. The list is left unchanged on error
+    /// (strong guarantee).
+    pub fn try_push_back(&mut self, elt: T) -> Result<(), AllocError<T>> {
+        let node = Node::try_new_in(elt, &self.alloc)?;
+        unsafe { self.link_between(node, self.tail, None) };
+        Ok(())
+    }
+
+    /// Prepends an element to the front of the list, giving back the element
+    /// if the node could not be allocated. The list is left unchanged on error
+    /// (strong guarantee).
+    pub fn try_push_front(&mut self, elt: T) -> Result<(), AllocError<T>> {
+        let node = Node::try_new_in(elt, &self.alloc)?;
+        unsafe { self.link_between(node, None, self.head) };
+        Ok(())
+    }
+
+    /// Links the unlinked `node` between the adjacent nodes `prev` and
+    /// `next`, `None` standing for the ends of the list.
+    unsafe fn link_between(
+        &mut self,
+        node: NonNull<Node<T>>,
+        prev: Option<NonNull<Node<T>>>,
+        next: Option<NonNull<Node<T>>>,
+    ) {
+        (*node.as_ptr()).prev = prev;
+        (*node.as_ptr()).next = next;
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = Some(node),
+            None => self.head = Some(node),
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    /// Unlinks `node` from the list, the caller taking back the ownership
+    /// of it.
+    unsafe fn unlink_node(&mut self, node: NonNull<Node<T>>) -> NonNull<Node<T>> {
+        let (prev, next) = ((*node.as_ptr()).prev, (*node.as_ptr()).next);
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = prev,
+            None => self.tail = prev,
+        }
+        self.len -= 1;
+        node
+    }
+
+    #[inline]
+    fn pop_back_node(&mut self) -> Option<NonNull<Node<T>>> {
+        self.tail.map(|node| unsafe { self.unlink_node(node) })
+    }
+
+    #[inline]
+    fn pop_front_node(&mut self) -> Option<NonNull<Node<T>>> {
+        self.head.map(|node| unsafe { self.unlink_node(node) })
+    }
+}
+
+impl<T: TryClone, A: Allocator + Clone> TryLinkedList<T, A> {
+    /// Clones the elements of `other` and appends them to the back of the
+    /// list, the list is left untouched on error (strong guarantee). The
+    /// clones are allocated by the allocator of `self`.
+    pub fn try_append_clone(&mut self, other: &Self) -> Result<(), TryReserveError> {
+        let mut cloned = Self::new_in(self.alloc.clone());
+        for elt in other.iter() {
+            cloned.try_push_back(elt.try_clone()?)?;
+        }
+        self.append(&mut cloned);
+        Ok(())
+    }
+}
+
+impl<T: TryClone, A: Allocator + Clone> TryClone for TryLinkedList<T, A> {
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        let mut list = Self::new_in(self.alloc.clone());
+        for elt in self.iter() {
+            list.try_push_back(elt.try_clone()?)?;
+        }
+        Ok(list)
+    }
+}
+
+impl<T> TryFromIterator<T> for TryLinkedList<T> {
+    fn try_from_iterator<I: IntoIterator<Item = T>>(iterator: I) -> Result<Self, TryReserveError> {
+        let mut list = Self::new();
+        for elt in iterator {
+            list.try_push_back(elt)?;
+        }
+        Ok(list)
+    }
+}
+
+impl<T, A: Allocator> IntoIterator for TryLinkedList<T, A> {
+    type Item = T;
+    type IntoIter = IntoIter<T, A>;
+
+    #[inline]
+    fn into_iter(self) -> IntoIter<T, A> {
+        IntoIter { list: self }
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a TryLinkedList<T, A> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a mut TryLinkedList<T, A> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    #[inline]
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+/// An iterator over the elements of a `TryLinkedList`.
+pub struct Iter<'a, T: 'a> {
+    head: Option<NonNull<Node<T>>>,
+    tail: Option<NonNull<Node<T>>>,
+    len: usize,
+    marker: PhantomData<&'a Node<T>>,
+}
+
+unsafe impl<T: Sync> Send for Iter<'_, T> {}
+unsafe impl<T: Sync> Sync for Iter<'_, T> {}
+
+impl<T> Clone for Iter<'_, T> {
+    fn clone(&self) -> Self {
+        Iter { ..*self }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Iter").field(&self.len).finish()
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.head.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.head = node.next;
+            &node.element
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.tail.map(|node| unsafe {
+            let node = &*node.as_ptr();
+            self.len -= 1;
+            self.tail = node.prev;
+            &node.element
+        })
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<T> FusedIterator for Iter<'_, T> {}
+
+/// A mutable iterator over the elements of a `TryLinkedList`.
+pub struct IterMut<'a, T: 'a> {
+    head: Option<NonNull<Node<T>>>,
+    tail: Option<NonNull<Node<T>>>,
+    len: usize,
+    marker: PhantomData<&'a mut Node<T>>,
+}
+
+unsafe impl<T: Send> Send for IterMut<'_, T> {}
+unsafe impl<T: Sync> Sync for IterMut<'_, T> {}
+
+impl<T: fmt::Debug> fmt::Debug for IterMut<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IterMut").field(&self.len).finish()
+    }
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a mut T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.head.map(|node| unsafe {
+            let node = &mut *node.as_ptr();
+            self.len -= 1;
+            self.head = node.next;
+            &mut node.element
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.tail.map(|node| unsafe {
+            let node = &mut *node.as_ptr();
+            self.len -= 1;
+            self.tail = node.prev;
+            &mut node.element
+        })
+    }
+}
+
+impl<T> ExactSizeIterator for IterMut<'_, T> {}
+
+impl<T> FusedIterator for IterMut<'_, T> {}
+
+/// An owning iterator over the elements of a `TryLinkedList`.
+pub struct IntoIter<T, A: Allocator = Global> {
+    list: TryLinkedList<T, A>,
+}
+
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for IntoIter<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IntoIter").field(&self.list).finish()
+    }
+}
+
+impl<T, A: Allocator> Iterator for IntoIter<T, A> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        self.list.pop_front()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.len, Some(self.list.len))
+    }
+}
+
+impl<T, A: Allocator> DoubleEndedIterator for IntoIter<T, A> {
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        self.list.pop_back()
+    }
+}
+
+impl<T, A: Allocator> ExactSizeIterator for IntoIter<T, A> {}
+
+impl<T, A: Allocator> FusedIterator for IntoIter<T, A> {}
+
+/// A cursor over a `TryLinkedList` with editing operations.
+///
+/// A cursor points at an element of the list, or at a "ghost" non-element
+/// which sits between the back and the front of the list, so that it can
+/// wrap around. Inserting an element allocates its node, and gives back the
+/// element if that failed.
+///
+/// # Examples
+///
+/// Moving the most recently used element of an LRU list to the front,
+/// without allocating.
+///
+/// ```
+/// use fallible_collections::TryLinkedList;
+///
+/// let mut lru = TryLinkedList::new();
+/// for i in 0..4 {
+///     lru.try_push_back(i).unwrap();
+/// }
+/// let mut cursor = lru.cursor_front_mut();
+/// while cursor.current() != Some(&mut 2) {
+///     cursor.move_next();
+/// }
+/// let used = cursor.remove_current_as_list().unwrap();
+/// lru.cursor_front_mut().splice_before(used);
+/// assert!(lru.into_iter().eq(vec![2, 0, 1, 3]));
+/// ```
+pub struct CursorMut<'a, T: 'a, A: Allocator = Global> {
+    index: usize,
+    current: Option<NonNull<Node<T>>>,
+    list: &'a mut TryLinkedList<T, A>,
+}
+
+unsafe impl<T: Send, A: Allocator + Send> Send for CursorMut<'_, T, A> {}
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for CursorMut<'_, T, A> {}
+
+impl<T: fmt::Debug, A: Allocator> fmt::Debug for CursorMut<'_, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CursorMut")
+            .field(&self.list)
+            .field(&self.index())
+            .finish()
+    }
+}
+
+impl<'a, T, A: Allocator> CursorMut<'a, T, A> {
+    /// Returns the index of the current element, or `None` if the cursor
+    /// points at the "ghost" non-element.
+    #[inline]
+    pub fn index(&self) -> Option<usize> {
+        self.current.map(|_| self.index)
+    }
+
+    /// Moves the cursor to the next element, the "ghost" non-element being
+    /// after the back, and before the front of the list.
+    pub fn move_next(&mut self) {
+        match self.current.take() {
+            None => {
+                self.current = self.list.head;
+                self.index = 0;
+            }
+            Some(current) => unsafe {
+                self.current = (*current.as_ptr()).next;
+                self.index += 1;
+            },
+        }
+    }
+
+    /// Moves the cursor to the previous element, the "ghost" non-element
+    /// being after the back, and before the front of the list.
+    pub fn move_prev(&mut self) {
+        match self.current.take() {
+            None => {
+                self.current = self.list.tail;
+                self.index = self.list.len.saturating_sub(1);
+            }
+            Some(current) => unsafe {
+                self.current = (*current.as_ptr()).prev;
+                self.index = self.index.checked_sub(1).unwrap_or(self.list.len);
+            },
+        }
+    }
+
+    /// Returns a reference to the current element, or `None` if the cursor
+    /// points at the "ghost" non-element.
+    #[inline]
+    pub fn current(&mut self) -> Option<&mut T> {
+        self.current
+            .map(|current| unsafe { &mut (*current.as_ptr()).element })
+    }
+
+    /// Returns a reference to the next element, which is the front of the
+    /// list if the cursor points at the "ghost" non-element.
+    #[inline]
+    pub fn peek_next(&mut self) -> Option<&mut T> {
+        self.next_node()
+            .map(|next| unsafe { &mut (*next.as_ptr()).element })
+    }
+
+    /// Returns a reference to the previous element, which is the back of the
+    /// list if the cursor points at the "ghost" non-element.
+    #[inline]
+    pub fn peek_prev(&mut self) -> Option<&mut T> {
+        self.prev_node()
+            .map(|prev| unsafe { &mut (*prev.as_ptr()).element })
+    }
+
+    /// Inserts an element after the current one, or at the front of the list
+    /// if the cursor points at the "ghost" non-element. The element is given
+    /// back if its node could not be allocated. The list is left unchanged on
+    /// error (strong guarantee).
+    pub fn try_insert_after(&mut self, item: T) -> Result<(), AllocError<T>> {
+        let node = Node::try_new_in(item, &self.list.alloc)?;
+        let next = self.next_node();
+        unsafe { self.list.link_between(node, self.current, next) };
+        if self.current.is_none() {
+            // the "ghost" non-element's index has changed
+            self.index = self.list.len;
+        }
+        Ok(())
+    }
+
+    /// Inserts an element before the current one, or at the back of the list
+    /// if the cursor points at the "ghost" non-element. The element is given
+    /// back if its node could not be allocated. The list is left unchanged on
+    /// error (strong guarantee).
+    pub fn try_insert_before(&mut self, item: T) -> Result<(), AllocError<T>> {
+        let node = Node::try_new_in(item, &self.list.alloc)?;
+        let prev = self.prev_node();
+        unsafe { self.list.link_between(node, prev, self.current) };
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Removes the current element and returns it, moving the cursor to the
+    /// next element. Does nothing if the cursor points at the "ghost"
+    /// non-element.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node = self.remove_current_node()?;
+        Some(unsafe { Node::free_in(node, &self.list.alloc) })
+    }
+
+    fn next_node(&self) -> Option<NonNull<Node<T>>> {
+        match self.current {
+            None => self.list.head,
+            Some(current) => unsafe { (*current.as_ptr()).next },
+        }
+    }
+
+    fn prev_node(&self) -> Option<NonNull<Node<T>>> {
+        match self.current {
+            None => self.list.tail,
+            Some(current) => unsafe { (*current.as_ptr()).prev },
+        }
+    }
+
+    fn remove_current_node(&mut self) -> Option<NonNull<Node<T>>> {
+        let current = self.current?;
+        self.current = unsafe { (*current.as_ptr()).next };
+        Some(unsafe { self.list.unlink_node(current) })
+    }
+}
+
+impl<'a, T, A: Allocator + Clone> CursorMut<'a, T, A> {
+    /// Removes the current element and returns it as a list of one element,
+    /// reusing its node, so this never allocates. The cursor moves to the
+    /// next element.
+    pub fn remove_current_as_list(&mut self) -> Option<TryLinkedList<T, A>> {
+        self.remove_current_node().map(|node| {
+            let mut list = TryLinkedList::new_in(self.list.alloc.clone());
+            unsafe { list.link_between(node, None, None) };
+            list
+        })
+    }
+
+    /// Moves all the elements of `list` after the current one, or at the
+    /// front if the cursor points at the "ghost" non-element. The nodes are
+    /// relinked, so this never allocates: see the type documentation of
+    /// `TryLinkedList` for the allocators this requires.
+    pub fn splice_after(&mut self, mut list: TryLinkedList<T, A>) {
+        let next_index = match self.current {
+            None => 0,
+            Some(_) => self.index + 1,
+        };
+        let mut tail = self.list.split_at(self.next_node(), next_index);
+        self.list.append(&mut list);
+        self.list.append(&mut tail);
+        if self.current.is_none() {
+            self.index = self.list.len;
+        }
+    }
+
+    /// Moves all the elements of `list` before the current one, or at the
+    /// back if the cursor points at the "ghost" non-element. The nodes are
+    /// relinked, so this never allocates: see the type documentation of
+    /// `TryLinkedList` for the allocators this requires.
+    pub fn splice_before(&mut self, mut list: TryLinkedList<T, A>) {
+        let len = list.len;
+        let mut tail = self.list.split_at(self.current, self.index);
+        self.list.append(&mut list);
+        self.list.append(&mut tail);
+        self.index += len;
+    }
+}
+
+impl<T, A: Allocator + Clone> TryLinkedList<T, A> {
+    /// Splits the list before `at`, the node at `index` in the list, returning
+    /// the nodes from `at` to the back. `None` stands for the end of the list.
+    fn split_at(&mut self, at: Option<NonNull<Node<T>>>, index: usize) -> Self {
+        let mut tail = Self::new_in(self.alloc.clone());
+        let at = match at {
+            Some(at) => at,
+            None => return tail,
+        };
+        let len = self.len - index;
+        unsafe {
+            let prev = (*at.as_ptr()).prev.take();
+            match prev {
+                Some(prev) => (*prev.as_ptr()).next = None,
+                None => self.head = None,
+            }
+            tail.tail = self.tail;
+            self.tail = prev;
+        }
+        tail.head = Some(at);
+        tail.len = len;
+        self.len -= len;
+        tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::tests::Bounded;
+    use crate::TryCollect;
+
+    #[test]
+    fn push_pop() {
+        let mut list = TryLinkedList::new();
+        for i in 0..5 {
+            list.try_push_back(i).unwrap();
+            list.try_push_front(-i).unwrap();
+        }
+        assert_eq!(list.len(), 10);
+        assert_eq!(list.front(), Some(&-4));
+        assert_eq!(list.back(), Some(&4));
+        assert!(list
+            .iter()
+            .rev()
+            .copied()
+            .eq(vec![4, 3, 2, 1, 0, 0, -1, -2, -3, -4]));
+        for x in list.iter_mut() {
+            *x *= 10;
+        }
+        assert_eq!(list.pop_front(), Some(-40));
+        assert_eq!(list.pop_back(), Some(40));
+        assert!(list.contains(&30));
+        list.clear();
+        assert!(list.is_empty());
+        assert_eq!(list.pop_back(), None);
+    }
+
+    #[test]
+    fn cursor() {
+        let mut list: TryLinkedList<u32> = (0..5).try_collect().unwrap();
+        let mut cursor = list.cursor_front_mut();
+        assert_eq!(cursor.index(), Some(0));
+        cursor.move_next();
+        cursor.try_insert_after(10).unwrap();
+        cursor.try_insert_before(20).unwrap();
+        assert_eq!(cursor.index(), Some(2));
+        assert_eq!(cursor.current(), Some(&mut 1));
+        assert_eq!(cursor.peek_next(), Some(&mut 10));
+        assert_eq!(cursor.peek_prev(), Some(&mut 20));
+        assert_eq!(cursor.remove_current(), Some(1));
+        assert_eq!(cursor.current(), Some(&mut 10));
+
+        // wrap around through the "ghost" non-element
+        let mut cursor = list.cursor_back_mut();
+        cursor.move_next();
+        assert_eq!(cursor.index(), None);
+        cursor.try_insert_after(30).unwrap();
+        cursor.try_insert_before(40).unwrap();
+        cursor.move_prev();
+        assert_eq!(cursor.index(), Some(7));
+        assert_eq!(cursor.current(), Some(&mut 40));
+        assert!(list.iter().copied().eq(vec![30, 0, 20, 10, 2, 3, 4, 40]));
+    }
+
+    #[test]
+    fn splice() {
+        let mut list: TryLinkedList<u32> = (0..4).try_collect().unwrap();
+        let mut cursor = list.cursor_front_mut();
+        cursor.move_next();
+        let one = cursor.remove_current_as_list().unwrap();
+        assert_eq!(cursor.index(), Some(1));
+        cursor.splice_after(one);
+        let other: TryLinkedList<u32> = (7..9).try_collect().unwrap();
+        cursor.splice_before(other);
+        assert_eq!(cursor.index(), Some(3));
+        assert_eq!(cursor.current(), Some(&mut 2));
+        assert!(list.iter().copied().eq(vec![0, 7, 8, 2, 1, 3]));
+        assert_eq!(list.len(), 6);
+    }
+
+    #[test]
+    fn clone_and_append() {
+        let mut a: TryLinkedList<u32> = (0..3).try_collect().unwrap();
+        let b: TryLinkedList<u32> = (3..6).try_collect().unwrap();
+        a.try_append_clone(&b).unwrap();
+        assert!(a.iter().copied().eq(0..6));
+        assert_eq!(b.len(), 3);
+        let mut c = a.try_clone().unwrap();
+        assert_eq!(a, c);
+        a.append(&mut c);
+        assert!(c.is_empty());
+        assert_eq!(a.len(), 12);
+        assert!(a.into_iter().rev().take(3).eq(vec![5, 4, 3]));
+    }
+
+    #[test]
+    fn custom_allocator() {
+        let alloc = Bounded::new(3);
+        let mut list = TryLinkedList::new_in(&alloc);
+        for i in 0..3 {
+            list.try_push_back(i).unwrap();
+        }
+        let err = list.try_push_front(3).unwrap_err();
+        assert_eq!(err.into_inner(), 3);
+        assert_eq!(alloc.live(), 3);
+
+        let mut cursor = list.cursor_front_mut();
+        let one = cursor.remove_current_as_list().unwrap();
+        cursor.move_next();
+        cursor.splice_after(one);
+        assert!(list.iter().copied().eq(vec![1, 2, 0]));
+        let cloned = list.try_clone();
+        assert!(cloned.is_err());
+        assert_eq!(list.pop_back(), Some(0));
+        assert_eq!(alloc.live(), 2);
+        drop(list);
+        assert_eq!(alloc.live(), 0);
+    }
+}
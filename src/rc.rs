@@ -1,15 +1,23 @@
 //! Implement a Fallible Rc
 use super::FallibleBox;
-use crate::TryReserveError;
+use super::TryClone;
+use crate::{make_alloc_error, TryReserveError};
+use alloc::alloc::{alloc, dealloc, Layout};
 use alloc::boxed::Box;
 use alloc::rc::Rc;
+use core::cell::Cell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
 
 /// trait to implement Fallible Rc
 #[cfg_attr(
     any(not(feature = "unstable"), feature = "rust_1_57"),
     deprecated(
         since = "0.4.9",
-        note = "⚠️️️this function is not completely fallible, it can panic!, see [issue](https://github.com/vcombey/fallible_collections/issues/13). help wanted"
+        note = "⚠️️️this function is not completely fallible, it can panic!, see [issue](https://github.com/vcombey/fallible_collections/issues/13). use TryRc instead"
     )
 )]
 pub trait FallibleRc<T> {
@@ -28,8 +36,259 @@ impl<T> FallibleRc<T> for Rc<T> {
     }
 }
 
+// the weak count includes one implicit weak reference hold by all the
+// strong references together, as in std.
+#[repr(C)]
+struct RcBox<T> {
+    strong: Cell<usize>,
+    weak: Cell<usize>,
+    value: T,
+}
+
+impl<T> RcBox<T> {
+    #[inline]
+    fn inc(count: &Cell<usize>) {
+        let n = count.get();
+        if n == usize::MAX {
+            panic!("reference count overflow");
+        }
+        count.set(n + 1);
+    }
+}
+
+/// TryRc is a single-threaded reference-counting pointer whose header and
+/// value are allocated in one fallible allocation, contrary to
+/// `Rc::from(Box<T>)` which reallocates.
+///
+/// As other types of this crate, it does not implement `Clone`, use
+/// `try_clone` which only bumps the reference count.
+pub struct TryRc<T> {
+    ptr: NonNull<RcBox<T>>,
+    phantom: PhantomData<RcBox<T>>,
+}
+
+/// TryRcWeak is a version of `TryRc` that holds a non-owning reference to
+/// the managed allocation, see std::rc::Weak
+pub struct TryRcWeak<T> {
+    ptr: NonNull<RcBox<T>>,
+}
+
+impl<T> TryRc<T> {
+    /// try creating a new TryRc, returning an error if allocation failed
+    #[inline]
+    pub fn try_new(value: T) -> Result<Self, TryReserveError> {
+        let ptr = Self::try_allocate()?;
+        unsafe {
+            ptr::write(
+                ptr.as_ptr(),
+                RcBox {
+                    strong: Cell::new(1),
+                    weak: Cell::new(1),
+                    value,
+                },
+            );
+        }
+        Ok(Self::from_inner(ptr))
+    }
+
+    /// try creating a new TryRc with uninitialized contents
+    #[inline]
+    pub fn try_new_uninit() -> Result<TryRc<MaybeUninit<T>>, TryReserveError> {
+        TryRc::try_new(MaybeUninit::uninit())
+    }
+
+    fn try_allocate() -> Result<NonNull<RcBox<T>>, TryReserveError> {
+        let layout = Layout::new::<RcBox<T>>();
+        // the layout is never zero sized because of the counters
+        let ptr = unsafe { alloc(layout) } as *mut RcBox<T>;
+        NonNull::new(ptr).ok_or_else(|| make_alloc_error(layout))
+    }
+
+    #[inline(always)]
+    fn from_inner(ptr: NonNull<RcBox<T>>) -> Self {
+        Self {
+            ptr,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn inner(&self) -> &RcBox<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns a mutable reference into the given `TryRc`, if there are
+    /// no other `TryRc` or `TryRcWeak` pointers to the same allocation.
+    #[inline]
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.inner().strong.get() == 1 && this.inner().weak.get() == 1 {
+            unsafe { Some(&mut (*this.ptr.as_ptr()).value) }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the inner value, if the `TryRc` has exactly one strong
+    /// reference, otherwise the same `TryRc` is given back.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this.inner().strong.get() != 1 {
+            return Err(this);
+        }
+        unsafe {
+            let value = ptr::read(&this.inner().value);
+            this.inner().strong.set(0);
+            // drop the implicit weak reference of the strong ones
+            drop(TryRcWeak { ptr: this.ptr });
+            core::mem::forget(this);
+            Ok(value)
+        }
+    }
+
+    /// Creates a new `TryRcWeak` pointer to this allocation.
+    #[inline]
+    pub fn downgrade(this: &Self) -> TryRcWeak<T> {
+        RcBox::<T>::inc(&this.inner().weak);
+        TryRcWeak { ptr: this.ptr }
+    }
+
+    #[inline(always)]
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.get()
+    }
+
+    #[inline(always)]
+    pub fn weak_count(this: &Self) -> usize {
+        this.inner().weak.get() - 1
+    }
+
+    /// Returns `true` if the two `TryRc`s point to the same allocation
+    #[inline(always)]
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<T> TryRc<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// See std::rc::Rc::assume_init
+    #[inline]
+    pub unsafe fn assume_init(self) -> TryRc<T> {
+        let ptr = self.ptr.cast();
+        core::mem::forget(self);
+        TryRc::from_inner(ptr)
+    }
+}
+
+impl<T> TryClone for TryRc<T> {
+    /// Only bumps the reference count, this never fails
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        RcBox::<T>::inc(&self.inner().strong);
+        Ok(Self::from_inner(self.ptr))
+    }
+}
+
+impl<T> Deref for TryRc<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<T> Drop for TryRc<T> {
+    fn drop(&mut self) {
+        let strong = self.inner().strong.get() - 1;
+        self.inner().strong.set(strong);
+        if strong == 0 {
+            unsafe {
+                ptr::drop_in_place(&mut (*self.ptr.as_ptr()).value);
+            }
+            // drop the implicit weak reference of the strong ones
+            drop(TryRcWeak { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TryRc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for TryRc<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for TryRc<T> {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for TryRc<T> {}
+
+impl<T> TryRcWeak<T> {
+    #[inline(always)]
+    fn inner(&self) -> &RcBox<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Attempts to upgrade the `TryRcWeak` pointer to a `TryRc`, returns
+    /// `None` if the inner value has since been dropped.
+    #[inline]
+    pub fn upgrade(&self) -> Option<TryRc<T>> {
+        if self.inner().strong.get() == 0 {
+            None
+        } else {
+            RcBox::<T>::inc(&self.inner().strong);
+            Some(TryRc::from_inner(self.ptr))
+        }
+    }
+
+    #[inline(always)]
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.get()
+    }
+}
+
+impl<T> TryClone for TryRcWeak<T> {
+    /// Only bumps the weak reference count, this never fails
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        RcBox::<T>::inc(&self.inner().weak);
+        Ok(Self { ptr: self.ptr })
+    }
+}
+
+impl<T> Drop for TryRcWeak<T> {
+    fn drop(&mut self) {
+        let weak = self.inner().weak.get() - 1;
+        self.inner().weak.set(weak);
+        if weak == 0 {
+            unsafe { dealloc(self.ptr.as_ptr() as *mut u8, Layout::new::<RcBox<T>>()) }
+        }
+    }
+}
+
+impl<T> fmt::Debug for TryRcWeak<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn fallible_rc() {
         use std::rc::Rc;
@@ -41,4 +300,58 @@ mod test {
         let _y = Rc::clone(&x);
         assert!(Rc::get_mut(&mut x).is_none());
     }
+
+    #[test]
+    fn try_rc() {
+        let mut x = TryRc::try_new(3).unwrap();
+        *TryRc::get_mut(&mut x).unwrap() = 4;
+        assert_eq!(*x, 4);
+
+        let y = x.try_clone().unwrap();
+        assert_eq!(TryRc::strong_count(&x), 2);
+        assert!(TryRc::get_mut(&mut x).is_none());
+        assert!(TryRc::ptr_eq(&x, &y));
+        let x = TryRc::try_unwrap(x).unwrap_err();
+        drop(y);
+        assert_eq!(TryRc::try_unwrap(x), Ok(4));
+    }
+
+    #[test]
+    fn try_rc_weak() {
+        let mut x = TryRc::try_new(String::from("foo")).unwrap();
+        let weak = TryRc::downgrade(&x);
+        assert_eq!(TryRc::weak_count(&x), 1);
+        assert!(TryRc::get_mut(&mut x).is_none());
+        assert_eq!(*weak.upgrade().unwrap(), "foo");
+        drop(x);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    fn try_rc_drop() {
+        let counter = Rc::new(());
+        let x = TryRc::try_new(Rc::clone(&counter)).unwrap();
+        let weak = TryRc::downgrade(&x);
+        let y = x.try_clone().unwrap();
+        drop(x);
+        assert_eq!(Rc::strong_count(&counter), 2);
+        drop(y);
+        assert_eq!(Rc::strong_count(&counter), 1);
+        drop(weak);
+    }
+
+    #[test]
+    fn try_rc_uninit() {
+        let mut x = TryRc::<u32>::try_new_uninit().unwrap();
+        TryRc::get_mut(&mut x).unwrap().write(5);
+        let x = unsafe { x.assume_init() };
+        assert_eq!(*x, 5);
+    }
+
+    #[test]
+    fn try_rc_zst() {
+        let x = TryRc::try_new(()).unwrap();
+        assert_eq!(*x, ());
+    }
 }
@@ -0,0 +1,643 @@
+//! Implement a Fallible vector storing its first elements inline
+//!
+//! `TrySmallVec<[T; N]>` keeps up to `N` elements in place, and spills them
+//! to a fallibly allocated heap buffer when it grows past that. It implements
+//! `FallibleVec`, so code written against that trait works the same with a
+//! `Vec` or a `TrySmallVec`.
+use super::TryClone;
+use crate::vec::{FallibleVec, TryFromIterator};
+use crate::TryReserveError;
+use alloc::vec::Vec;
+use core::mem::{self, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::{fmt, ptr, slice};
+
+/// Types which can be the inline storage of a `TrySmallVec`, which are the
+/// arrays `[T; N]`.
+///
+/// # Safety
+///
+/// The type must be laid out as `CAPACITY` consecutive `Item`s.
+pub unsafe trait Array {
+    /// The type of the elements
+    type Item;
+    /// The number of elements stored inline
+    const CAPACITY: usize;
+}
+
+unsafe impl<T, const N: usize> Array for [T; N] {
+    type Item = T;
+    const CAPACITY: usize = N;
+}
+
+enum Data<A: Array> {
+    Inline(usize, MaybeUninit<A>),
+    Heap(Vec<A::Item>),
+}
+
+/// TrySmallVec is a vector storing up to `A::CAPACITY` elements inline,
+/// before moving them to a heap buffer allocated fallibly.
+///
+/// See the crate documentation for more.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::{FallibleVec, TrySmallVec};
+///
+/// let mut v = TrySmallVec::<[u32; 4]>::new();
+/// v.try_extend_from_slice(&[1, 2, 3]).unwrap();
+/// assert!(!v.spilled());
+/// v.try_push(4).unwrap();
+/// v.try_push(5).unwrap();
+/// assert!(v.spilled());
+/// assert_eq!(*v, [1, 2, 3, 4, 5]);
+/// ```
+pub struct TrySmallVec<A: Array> {
+    data: Data<A>,
+}
+
+impl<A: Array> Default for TrySmallVec<A> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Array> fmt::Debug for TrySmallVec<A>
+where
+    A::Item: fmt::Debug,
+{
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+impl<A: Array> PartialEq for TrySmallVec<A>
+where
+    A::Item: PartialEq,
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<A: Array> Eq for TrySmallVec<A> where A::Item: Eq {}
+
+impl<A: Array> Drop for TrySmallVec<A> {
+    fn drop(&mut self) {
+        if let Data::Inline(..) = self.data {
+            unsafe { ptr::drop_in_place(self.as_mut_slice()) }
+        }
+    }
+}
+
+impl<A: Array> TrySmallVec<A> {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            data: Data::Inline(0, MaybeUninit::uninit()),
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&mut self) -> *mut A::Item {
+        match &mut self.data {
+            Data::Inline(_, buf) => buf.as_mut_ptr() as *mut A::Item,
+            Data::Heap(vec) => vec.as_mut_ptr(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_mut_slice(&mut self) -> &mut [A::Item] {
+        let len = self.len();
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), len) }
+    }
+
+    #[inline(always)]
+    pub fn as_ptr(&self) -> *const A::Item {
+        match &self.data {
+            Data::Inline(_, buf) => buf.as_ptr() as *const A::Item,
+            Data::Heap(vec) => vec.as_ptr(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_slice(&self) -> &[A::Item] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len()) }
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        match &self.data {
+            Data::Inline(..) => A::CAPACITY,
+            Data::Heap(vec) => vec.capacity(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.truncate(0)
+    }
+
+    /// Returns the number of elements which are stored inline.
+    #[inline(always)]
+    pub fn inline_size(&self) -> usize {
+        A::CAPACITY
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        match &self.data {
+            Data::Inline(len, _) => *len,
+            Data::Heap(vec) => vec.len(),
+        }
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<A::Item> {
+        let len = self.len().checked_sub(1)?;
+        unsafe {
+            self.set_len(len);
+            Some(ptr::read(self.as_ptr().add(len)))
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> A::Item {
+        let len = self.len();
+        assert!(index < len, "removal index out of bounds");
+        unsafe {
+            let p = self.as_mut_ptr().add(index);
+            let item = ptr::read(p);
+            ptr::copy(p.add(1), p, len - index - 1);
+            self.set_len(len - 1);
+            item
+        }
+    }
+
+    /// Retains only the elements specified by the predicate.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut A::Item) -> bool,
+    {
+        let len = self.len();
+        let mut deleted = 0;
+        for i in 0..len {
+            if !f(&mut self[i]) {
+                deleted += 1;
+            } else if deleted > 0 {
+                self.swap(i - deleted, i);
+            }
+        }
+        self.truncate(len - deleted);
+    }
+
+    /// # Safety
+    ///
+    /// See std::vec::Vec::set_len
+    #[inline(always)]
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        match &mut self.data {
+            Data::Inline(len, _) => *len = new_len,
+            Data::Heap(vec) => vec.set_len(new_len),
+        }
+    }
+
+    /// Returns whether the elements have been moved to the heap.
+    #[inline(always)]
+    pub fn spilled(&self) -> bool {
+        match self.data {
+            Data::Inline(..) => false,
+            Data::Heap(_) => true,
+        }
+    }
+
+    #[inline]
+    pub fn swap_remove(&mut self, index: usize) -> A::Item {
+        let len = self.len();
+        assert!(index < len, "swap_remove index out of bounds");
+        self.swap(index, len - 1);
+        match self.pop() {
+            Some(item) => item,
+            None => unreachable!(),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        let old_len = self.len();
+        if len < old_len {
+            unsafe {
+                self.set_len(len);
+                let tail = slice::from_raw_parts_mut(self.as_mut_ptr().add(len), old_len - len);
+                ptr::drop_in_place(tail);
+            }
+        }
+    }
+
+    /// Moves the elements to a `Vec`, which is allocated if they are
+    /// stored inline.
+    pub fn try_into_vec(mut self) -> Result<Vec<A::Item>, TryReserveError> {
+        if let Data::Heap(vec) = &mut self.data {
+            return Ok(mem::take(vec));
+        }
+        let len = self.len();
+        let mut vec = <Vec<A::Item> as FallibleVec<A::Item>>::try_with_capacity(len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr(), vec.as_mut_ptr(), len);
+            vec.set_len(len);
+            self.set_len(0);
+        }
+        Ok(vec)
+    }
+
+    /// Pushes `value` at the end, the room for it must be reserved.
+    #[inline(always)]
+    unsafe fn push_reserved(&mut self, value: A::Item) {
+        let len = self.len();
+        debug_assert!(len < self.capacity());
+        ptr::write(self.as_mut_ptr().add(len), value);
+        self.set_len(len + 1);
+    }
+
+    /// Moves the inline elements to a heap buffer of `capacity` elements.
+    fn try_spill(&mut self, capacity: usize) -> Result<(), TryReserveError> {
+        let len = self.len();
+        let mut vec = <Vec<A::Item> as FallibleVec<A::Item>>::try_with_capacity(capacity)?;
+        unsafe {
+            ptr::copy_nonoverlapping(self.as_ptr(), vec.as_mut_ptr(), len);
+            vec.set_len(len);
+        }
+        // the inline elements are not dropped, they now belong to `vec`
+        self.data = Data::Heap(vec);
+        Ok(())
+    }
+}
+
+impl<A: Array> FallibleVec<A::Item> for TrySmallVec<A> {
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        if let Data::Heap(vec) = &mut self.data {
+            return vec.try_reserve(additional);
+        }
+        let needed = self
+            .len()
+            .checked_add(additional)
+            .ok_or_else(crate::make_try_reserve_error)?;
+        if needed > A::CAPACITY {
+            self.try_spill(core::cmp::max(needed, A::CAPACITY.saturating_mul(2)))?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push(&mut self, elem: A::Item) -> Result<(), TryReserveError> {
+        FallibleVec::try_reserve(self, 1)?;
+        unsafe { self.push_reserved(elem) };
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push_give_back(&mut self, elem: A::Item) -> Result<(), (A::Item, TryReserveError)> {
+        if let Err(e) = FallibleVec::try_reserve(self, 1) {
+            return Err((elem, e));
+        }
+        unsafe { self.push_reserved(elem) };
+        Ok(())
+    }
+
+    #[inline]
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError>
+    where
+        Self: core::marker::Sized,
+    {
+        let mut v = Self::new();
+        FallibleVec::try_reserve(&mut v, capacity)?;
+        Ok(v)
+    }
+
+    fn try_insert(
+        &mut self,
+        index: usize,
+        element: A::Item,
+    ) -> Result<(), (A::Item, TryReserveError)> {
+        let len = self.len();
+        assert!(index <= len, "insertion index out of bounds");
+        if let Err(e) = FallibleVec::try_reserve(self, 1) {
+            return Err((element, e));
+        }
+        unsafe {
+            let p = self.as_mut_ptr().add(index);
+            ptr::copy(p, p.add(1), len - index);
+            ptr::write(p, element);
+            self.set_len(len + 1);
+        }
+        Ok(())
+    }
+
+    fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
+        let len = self.len();
+        let other_len = other.len();
+        FallibleVec::try_reserve(self, other_len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().add(len), other_len);
+            other.set_len(0);
+            self.set_len(len + other_len);
+        }
+        Ok(())
+    }
+
+    fn try_resize(&mut self, new_len: usize, value: A::Item) -> Result<(), TryReserveError>
+    where
+        A::Item: Copy + Clone,
+    {
+        self.try_resize_with(new_len, || value)
+    }
+
+    fn try_resize_with<F>(&mut self, new_len: usize, mut f: F) -> Result<(), TryReserveError>
+    where
+        F: FnMut() -> A::Item,
+    {
+        let len = self.len();
+        if new_len > len {
+            FallibleVec::try_reserve(self, new_len - len)?;
+            for _ in len..new_len {
+                unsafe { self.push_reserved(f()) };
+            }
+        } else {
+            self.truncate(new_len);
+        }
+        Ok(())
+    }
+
+    fn try_resize_no_copy(&mut self, new_len: usize, value: A::Item) -> Result<(), TryReserveError>
+    where
+        A::Item: TryClone,
+    {
+        let len = self.len();
+        if new_len > len {
+            FallibleVec::try_reserve(self, new_len - len)?;
+            for _ in len + 1..new_len {
+                match value.try_clone() {
+                    Ok(elem) => unsafe { self.push_reserved(elem) },
+                    Err(e) => {
+                        self.truncate(len);
+                        return Err(e);
+                    }
+                }
+            }
+            unsafe { self.push_reserved(value) };
+        } else {
+            self.truncate(new_len);
+        }
+        Ok(())
+    }
+
+    fn try_extend_from_slice(&mut self, other: &[A::Item]) -> Result<(), TryReserveError>
+    where
+        A::Item: Copy + Clone,
+    {
+        let len = self.len();
+        FallibleVec::try_reserve(self, other.len())?;
+        unsafe {
+            ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().add(len), other.len());
+            self.set_len(len + other.len());
+        }
+        Ok(())
+    }
+
+    fn try_extend_from_slice_no_copy(&mut self, other: &[A::Item]) -> Result<(), TryReserveError>
+    where
+        A::Item: TryClone,
+    {
+        let len = self.len();
+        FallibleVec::try_reserve(self, other.len())?;
+        for elem in other {
+            match elem.try_clone() {
+                Ok(elem) => unsafe { self.push_reserved(elem) },
+                Err(e) => {
+                    self.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A: Array> Deref for TrySmallVec<A> {
+    type Target = [A::Item];
+
+    #[inline(always)]
+    fn deref(&self) -> &[A::Item] {
+        self.as_slice()
+    }
+}
+
+impl<A: Array> DerefMut for TrySmallVec<A> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut [A::Item] {
+        self.as_mut_slice()
+    }
+}
+
+impl<A: Array> TryClone for TrySmallVec<A>
+where
+    A::Item: TryClone,
+{
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        let mut clone = <Self as FallibleVec<A::Item>>::try_with_capacity(self.len())?;
+        clone.try_extend_from_slice_no_copy(self)?;
+        Ok(clone)
+    }
+}
+
+impl<A: Array> TryFromIterator<A::Item> for TrySmallVec<A> {
+    fn try_from_iterator<I: IntoIterator<Item = A::Item>>(
+        iterator: I,
+    ) -> Result<Self, TryReserveError> {
+        let mut v = Self::new();
+        for elem in iterator {
+            v.try_push(elem)?;
+        }
+        Ok(v)
+    }
+}
+
+impl<A: Array> IntoIterator for TrySmallVec<A> {
+    type Item = A::Item;
+    type IntoIter = IntoIter<A>;
+
+    #[inline]
+    fn into_iter(mut self) -> IntoIter<A> {
+        let end = self.len();
+        // the elements now belong to the iterator
+        unsafe { self.set_len(0) };
+        IntoIter {
+            vec: self,
+            start: 0,
+            end,
+        }
+    }
+}
+
+impl<'a, A: Array> IntoIterator for &'a TrySmallVec<A> {
+    type Item = &'a A::Item;
+    type IntoIter = slice::Iter<'a, A::Item>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, A: Array> IntoIterator for &'a mut TrySmallVec<A> {
+    type Item = &'a mut A::Item;
+    type IntoIter = slice::IterMut<'a, A::Item>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// An owning iterator over the elements of a `TrySmallVec`.
+pub struct IntoIter<A: Array> {
+    // the elements in `start..end` are owned by the iterator
+    vec: TrySmallVec<A>,
+    start: usize,
+    end: usize,
+}
+
+impl<A: Array> fmt::Debug for IntoIter<A>
+where
+    A::Item: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rest = unsafe {
+            slice::from_raw_parts(self.vec.as_ptr().add(self.start), self.end - self.start)
+        };
+        f.debug_tuple("IntoIter").field(&rest).finish()
+    }
+}
+
+impl<A: Array> Drop for IntoIter<A> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+impl<A: Array> Iterator for IntoIter<A> {
+    type Item = A::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<A::Item> {
+        if self.start == self.end {
+            return None;
+        }
+        let item = unsafe { ptr::read(self.vec.as_ptr().add(self.start)) };
+        self.start += 1;
+        Some(item)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+}
+
+impl<A: Array> DoubleEndedIterator for IntoIter<A> {
+    #[inline]
+    fn next_back(&mut self) -> Option<A::Item> {
+        if self.start == self.end {
+            return None;
+        }
+        self.end -= 1;
+        Some(unsafe { ptr::read(self.vec.as_ptr().add(self.end)) })
+    }
+}
+
+impl<A: Array> ExactSizeIterator for IntoIter<A> {}
+
+impl<A: Array> core::iter::FusedIterator for IntoIter<A> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TryCollect;
+
+    // written once for both vectors
+    fn fill<V: FallibleVec<u32> + Deref<Target = [u32]>>(v: &mut V) {
+        v.try_push(1).unwrap();
+        v.try_extend_from_slice(&[2, 3]).unwrap();
+        v.try_insert(0, 0).unwrap();
+        v.try_resize(6, 9).unwrap();
+        assert_eq!(**v, [0, 1, 2, 3, 9, 9]);
+    }
+
+    #[test]
+    fn same_as_vec() {
+        let mut vec = Vec::new();
+        fill(&mut vec);
+        let mut small = TrySmallVec::<[u32; 4]>::new();
+        fill(&mut small);
+        assert!(small.spilled());
+        assert_eq!(*small, *vec);
+
+        let mut small = TrySmallVec::<[u32; 8]>::new();
+        fill(&mut small);
+        assert!(!small.spilled());
+        assert_eq!(small.capacity(), 8);
+        assert_eq!(small.try_into_vec().unwrap(), vec);
+    }
+
+    #[test]
+    fn remove_and_retain() {
+        let mut v: TrySmallVec<[u32; 3]> = (0..10).try_collect().unwrap();
+        assert_eq!(v.remove(0), 0);
+        assert_eq!(v.swap_remove(0), 1);
+        assert_eq!(v.pop(), Some(8));
+        v.retain(|x| *x % 2 == 1);
+        assert_eq!(*v, [9, 3, 5, 7]);
+        v.truncate(1);
+        assert_eq!(*v, [9]);
+    }
+
+    #[test]
+    fn drops_elements() {
+        use alloc::rc::Rc;
+
+        let rc = Rc::new(());
+        let mut v = TrySmallVec::<[Rc<()>; 2]>::new();
+        for _ in 0..2 {
+            v.try_push(rc.clone()).unwrap();
+        }
+        let mut w = TrySmallVec::<[Rc<()>; 2]>::new();
+        w.try_push(rc.clone()).unwrap();
+        w.try_append(&mut v).unwrap();
+        assert!(v.is_empty());
+        assert!(w.spilled());
+        assert_eq!(Rc::strong_count(&rc), 4);
+        let mut iter = w.into_iter();
+        iter.next();
+        assert_eq!(Rc::strong_count(&rc), 3);
+        drop(iter);
+        drop(v);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn try_reserve_oom() {
+        let mut v = TrySmallVec::<[u64; 4]>::new();
+        assert!(FallibleVec::try_reserve(&mut v, usize::MAX).is_err());
+        assert!(FallibleVec::try_reserve(&mut v, usize::MAX / 16).is_err());
+        assert!(!v.spilled());
+        assert_eq!(v.try_clone().unwrap(), v);
+    }
+}
@@ -0,0 +1,506 @@
+//! Implement Fallible String
+use super::FallibleVec;
+use super::TryClone;
+use crate::vec::TryVec;
+use crate::{CollectionKind, ReserveError, TryReserveError};
+use alloc::string::String;
+use core::borrow::{Borrow, BorrowMut};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Deref, DerefMut};
+use core::str::Utf8Error;
+
+/// trait implementing all fallible methods on string
+pub trait FallibleString {
+    /// see reserve, the string is left untouched on error (strong guarantee)
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+    /// see with capacity, (Self must be sized by the constraint of Result)
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError>
+    where
+        Self: core::marker::Sized;
+    /// see push, the string is left untouched on error (strong guarantee)
+    fn try_push(&mut self, ch: char) -> Result<(), TryReserveError>;
+    /// see push_str, the string is left untouched on error (strong guarantee)
+    fn try_push_str(&mut self, string: &str) -> Result<(), TryReserveError>;
+    /// see insert, the string is left untouched on error (strong guarantee)
+    fn try_insert(&mut self, idx: usize, ch: char) -> Result<(), TryReserveError>;
+    /// see insert_str, the string is left untouched on error (strong guarantee)
+    fn try_insert_str(&mut self, idx: usize, string: &str) -> Result<(), TryReserveError>;
+}
+
+impl FallibleString for String {
+    #[inline(always)]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.try_reserve(additional)
+    }
+
+    #[inline]
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        let mut s = Self::new();
+        FallibleString::try_reserve(&mut s, capacity)?;
+        Ok(s)
+    }
+
+    #[inline]
+    fn try_push(&mut self, ch: char) -> Result<(), TryReserveError> {
+        self.try_push_str(ch.encode_utf8(&mut [0; 4]))
+    }
+
+    #[inline]
+    fn try_push_str(&mut self, string: &str) -> Result<(), TryReserveError> {
+        // push_str only reallocates when the capacity is too small
+        self.try_reserve(string.len())?;
+        self.push_str(string);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_insert(&mut self, idx: usize, ch: char) -> Result<(), TryReserveError> {
+        self.try_insert_str(idx, ch.encode_utf8(&mut [0; 4]))
+    }
+
+    #[inline]
+    fn try_insert_str(&mut self, idx: usize, string: &str) -> Result<(), TryReserveError> {
+        assert!(self.is_char_boundary(idx));
+        self.try_reserve(string.len())?;
+        self.insert_str(idx, string);
+        Ok(())
+    }
+}
+
+impl TryClone for String {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        let mut s = Self::new();
+        s.try_push_str(self)?;
+        Ok(s)
+    }
+}
+
+/// trait for trying to create owned data from borrowed data, return an
+/// error instead of panic if allocation failed
+pub trait TryToOwned {
+    /// the resulting owned type
+    type Owned;
+
+    /// see to_owned
+    fn try_to_owned(&self) -> Result<Self::Owned, TryReserveError>;
+}
+
+impl TryToOwned for str {
+    type Owned = TryString;
+
+    #[inline]
+    fn try_to_owned(&self) -> Result<TryString, TryReserveError> {
+        TryString::try_from(self)
+    }
+}
+
+impl<T: TryClone> TryToOwned for [T] {
+    type Owned = TryVec<T>;
+
+    #[inline]
+    fn try_to_owned(&self) -> Result<TryVec<T>, TryReserveError> {
+        TryVec::try_from(self)
+    }
+}
+
+/// TryString is a thin wrapper around a `TryVec<u8>` holding valid UTF-8,
+/// to provide support for fallible allocation.
+///
+/// See the crate documentation for more.
+#[derive(Default)]
+pub struct TryString {
+    inner: TryVec<u8>,
+}
+
+/// A possible error value when converting a `TryString` from a UTF-8 byte
+/// vector, see `TryString::try_from_utf8`.
+#[derive(Debug, PartialEq)]
+pub struct FromUtf8Error {
+    bytes: TryVec<u8>,
+    error: Utf8Error,
+}
+
+impl FromUtf8Error {
+    /// Returns the bytes that were attempted to convert to a `TryString`.
+    #[inline(always)]
+    pub fn into_bytes(self) -> TryVec<u8> {
+        self.bytes
+    }
+
+    /// Returns the details of the conversion error.
+    #[inline(always)]
+    pub fn utf8_error(&self) -> Utf8Error {
+        self.error
+    }
+}
+
+impl fmt::Display for FromUtf8Error {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl TryString {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            inner: TryVec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Ok(Self {
+            inner: TryVec::with_capacity(capacity)?,
+        })
+    }
+
+    /// Converts a vector of bytes to a `TryString`, checking that the
+    /// bytes are valid UTF-8. No allocation is done.
+    #[inline]
+    pub fn try_from_utf8(bytes: TryVec<u8>) -> Result<Self, FromUtf8Error> {
+        match core::str::from_utf8(&bytes) {
+            Ok(_) => Ok(Self { inner: bytes }),
+            Err(error) => Err(FromUtf8Error { bytes, error }),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// See std::string::String::from_utf8_unchecked
+    #[inline(always)]
+    pub unsafe fn from_utf8_unchecked(bytes: TryVec<u8>) -> Self {
+        Self { inner: bytes }
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &str {
+        self
+    }
+
+    #[inline(always)]
+    pub fn as_mut_str(&mut self) -> &mut str {
+        self
+    }
+
+    #[inline(always)]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
+
+    #[inline(always)]
+    pub fn into_bytes(self) -> TryVec<u8> {
+        self.inner
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.inner.inner.capacity()
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.chars().next_back()?;
+        let new_len = self.len() - ch.len_utf8();
+        self.inner.inner.truncate(new_len);
+        Some(ch)
+    }
+
+    #[inline]
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len <= self.len() {
+            assert!(self.is_char_boundary(new_len));
+            self.inner.inner.truncate(new_len)
+        }
+    }
+
+    /// Reserves room for at least `additional` more bytes, the string is left
+    /// unchanged on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.inner.reserve(additional)
+    }
+
+    /// Reserves room for at least `additional` more bytes, as `try_reserve`,
+    /// the error telling the length in bytes the string was grown to. The
+    /// string is left unchanged on error (strong guarantee).
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.len().saturating_add(additional);
+        self.try_reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::String)
+                .with_len(len)
+        })
+    }
+
+    /// Appends a char to the string, which is left unchanged on error (strong
+    /// guarantee).
+    #[inline]
+    pub fn try_push(&mut self, ch: char) -> Result<(), TryReserveError> {
+        self.try_push_str(ch.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Appends a string slice to the string, which is left unchanged on error
+    /// (strong guarantee).
+    #[inline]
+    pub fn try_push_str(&mut self, string: &str) -> Result<(), TryReserveError> {
+        self.inner.inner.try_extend_from_slice(string.as_bytes())
+    }
+
+    /// Inserts a char at the byte index `idx`, the string is left unchanged on
+    /// error (strong guarantee).
+    #[inline]
+    pub fn try_insert(&mut self, idx: usize, ch: char) -> Result<(), TryReserveError> {
+        self.try_insert_str(idx, ch.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Inserts a string slice at the byte index `idx`, the string is left
+    /// unchanged on error (strong guarantee).
+    pub fn try_insert_str(&mut self, idx: usize, string: &str) -> Result<(), TryReserveError> {
+        assert!(self.is_char_boundary(idx));
+        let v = &mut self.inner.inner;
+        v.try_extend_from_slice(string.as_bytes())?;
+        v[idx..].rotate_right(string.len());
+        Ok(())
+    }
+}
+
+impl TryClone for TryString {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Self::try_from(self.as_str())
+    }
+}
+
+impl core::convert::TryFrom<&str> for TryString {
+    type Error = TryReserveError;
+
+    #[inline]
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut s = Self::try_with_capacity(value.len())?;
+        s.try_push_str(value)?;
+        Ok(s)
+    }
+}
+
+impl fmt::Write for TryString {
+    /// Report an allocation failure as a `fmt::Error` instead of aborting
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.try_push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl Deref for TryString {
+    type Target = str;
+
+    #[inline(always)]
+    fn deref(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.inner) }
+    }
+}
+
+impl DerefMut for TryString {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut str {
+        unsafe { core::str::from_utf8_unchecked_mut(&mut self.inner) }
+    }
+}
+
+impl AsRef<str> for TryString {
+    #[inline(always)]
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl AsMut<str> for TryString {
+    #[inline(always)]
+    fn as_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl AsRef<[u8]> for TryString {
+    #[inline(always)]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Borrow<str> for TryString {
+    #[inline(always)]
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl BorrowMut<str> for TryString {
+    #[inline(always)]
+    fn borrow_mut(&mut self) -> &mut str {
+        self
+    }
+}
+
+impl fmt::Debug for TryString {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for TryString {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for TryString {
+    #[inline(always)]
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for TryString {}
+
+impl PartialEq<str> for TryString {
+    #[inline(always)]
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for TryString {
+    #[inline(always)]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for TryString {
+    #[inline(always)]
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialOrd for TryString {
+    #[inline(always)]
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TryString {
+    #[inline(always)]
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for TryString {
+    #[inline(always)]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryFrom;
+    use core::fmt::Write;
+
+    #[test]
+    fn try_push_str() {
+        let mut s = TryString::new();
+        s.try_push_str("foo").unwrap();
+        s.try_push('b').unwrap();
+        s.try_push_str("ar").unwrap();
+        assert_eq!(s, "foobar");
+        assert_eq!(s.pop(), Some('r'));
+        assert_eq!(s, "fooba");
+    }
+
+    #[test]
+    fn try_insert_str() {
+        let mut s = TryString::try_from("héllo").unwrap();
+        s.try_insert_str(3, "--").unwrap();
+        assert_eq!(s, "hé--llo");
+        s.try_insert(0, 'ß').unwrap();
+        assert_eq!(s, "ßhé--llo");
+    }
+
+    #[test]
+    fn try_reserve_detailed() {
+        let mut s = TryString::try_from("foo").unwrap();
+        let e = s.try_reserve_detailed(usize::MAX - 1).unwrap_err();
+        assert_eq!(e.collection(), Some(crate::CollectionKind::String));
+        assert_eq!(e.len(), Some(usize::MAX));
+        assert_eq!(s, "foo");
+    }
+
+    #[test]
+    #[should_panic]
+    fn try_insert_str_not_char_boundary() {
+        let mut s = TryString::try_from("é").unwrap();
+        let _ = s.try_insert_str(1, "e");
+    }
+
+    #[test]
+    fn try_from_utf8() {
+        let bytes = TryVec::try_from(b"foo".as_ref()).unwrap();
+        assert_eq!(TryString::try_from_utf8(bytes).unwrap(), "foo");
+        let bytes = TryVec::try_from(b"fo\xff".as_ref()).unwrap();
+        let err = TryString::try_from_utf8(bytes).unwrap_err();
+        assert_eq!(err.utf8_error().valid_up_to(), 2);
+        assert_eq!(err.into_bytes(), b"fo\xff".as_ref());
+    }
+
+    #[test]
+    fn try_clone_string() {
+        let s = TryString::try_from("foo").unwrap();
+        assert_eq!(s.try_clone().unwrap(), s);
+        let s = String::from("foo");
+        assert_eq!(s.try_clone().unwrap(), s);
+        assert_eq!("foo".try_to_owned().unwrap(), s);
+    }
+
+    #[test]
+    fn write_fmt() {
+        let mut s = TryString::new();
+        write!(s, "{}-{:?}", 42, "foo").unwrap();
+        assert_eq!(s, "42-\"foo\"");
+    }
+
+    #[test]
+    fn trystring_oom() {
+        let mut s = TryString::new();
+        assert!(s.try_reserve(usize::MAX).is_err(), "it should be OOM");
+        let mut s = String::new();
+        assert!(
+            FallibleString::try_reserve(&mut s, usize::MAX).is_err(),
+            "it should be OOM"
+        );
+    }
+}
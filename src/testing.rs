@@ -0,0 +1,612 @@
+//! Fault injection to test the fallible paths
+//!
+//! Reserving `usize::MAX` only exercises the capacity overflow checks. To
+//! test what happens when the allocator really returns null in the middle
+//! of an operation, register `FailingAlloc` as the global allocator of the
+//! test binary, and run the code with `fail_nth`, `fail_randomly` or
+//! `check_each_failure`, which make the allocations of the current thread
+//! fail on demand.
+//!
+//! ```
+//! use fallible_collections::testing::{check_each_failure, FailingAlloc};
+//! use fallible_collections::TryVec;
+//!
+//! #[global_allocator]
+//! static GLOBAL: FailingAlloc = FailingAlloc::new(std::alloc::System);
+//!
+//! fn main() {
+//!     // every allocation made by the closure is failed in turn
+//!     let allocations = check_each_failure(|| {
+//!         let mut vec = TryVec::new();
+//!         for i in 0..100 {
+//!             if vec.push(i).is_err() {
+//!                 break;
+//!             }
+//!         }
+//!         assert!(vec.iter().copied().eq(0..vec.len() as u32));
+//!     });
+//!     assert!(allocations > 0);
+//! }
+//! ```
+//!
+//! The closures run under fault injection must only allocate through the
+//! fallible APIs, as the process aborts when an infallible allocation fails.
+//! For the same reason they should not print, and the output buffers of the
+//! test harness would be counted as leaks. The allocations of the other
+//! threads are not failed.
+use core::cell::Cell;
+use core::ptr;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::panic::{self, AssertUnwindSafe};
+
+#[derive(Copy, Clone)]
+enum Plan {
+    Off,
+    Nth(usize),
+    Random { state: u64, one_in: u64 },
+}
+
+#[derive(Copy, Clone)]
+struct State {
+    plan: Plan,
+    // allocations attempted since the plan was armed
+    allocations: usize,
+    injected: bool,
+    // bytes allocated minus bytes freed since the plan was armed
+    live: isize,
+}
+
+const DISARMED: State = State {
+    plan: Plan::Off,
+    allocations: 0,
+    injected: false,
+    live: 0,
+};
+
+std::thread_local! {
+    static STATE: Cell<State> = const { Cell::new(DISARMED) };
+}
+
+/// Counts an allocation attempt of the current thread, returning whether it
+/// must fail.
+fn should_fail() -> bool {
+    // the panic messages are allocated infallibly
+    if std::thread::panicking() {
+        return false;
+    }
+    STATE
+        .try_with(|cell| {
+            let mut state = cell.get();
+            let fail = match state.plan {
+                Plan::Off => return false,
+                Plan::Nth(n) => state.allocations == n,
+                Plan::Random {
+                    state: ref mut rng,
+                    one_in,
+                } => {
+                    // xorshift64
+                    *rng ^= *rng << 13;
+                    *rng ^= *rng >> 7;
+                    *rng ^= *rng << 17;
+                    *rng % one_in == 0
+                }
+            };
+            state.allocations += 1;
+            state.injected |= fail;
+            cell.set(state);
+            fail
+        })
+        .unwrap_or(false)
+}
+
+/// Records that `size` bytes were allocated, or freed if negative, by the
+/// current thread.
+fn record(size: isize) {
+    let _ = STATE.try_with(|cell| {
+        let mut state = cell.get();
+        if let Plan::Off = state.plan {
+            return;
+        }
+        state.live += size;
+        cell.set(state);
+    });
+}
+
+/// A global allocator forwarding to `A`, whose allocations fail as planned
+/// by `fail_nth`, `fail_randomly` and `check_each_failure` on the current
+/// thread.
+#[derive(Debug, Default)]
+pub struct FailingAlloc<A = System> {
+    inner: A,
+}
+
+impl<A> FailingAlloc<A> {
+    #[inline]
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for FailingAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if should_fail() {
+            return ptr::null_mut();
+        }
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if should_fail() {
+            return ptr::null_mut();
+        }
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        record(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if should_fail() {
+            return ptr::null_mut();
+        }
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+/// What happened to the allocations of a closure run under fault injection.
+#[derive(Debug)]
+pub struct Report<R> {
+    /// The value returned by the closure.
+    pub result: R,
+    /// The number of allocations attempted by the closure, including the
+    /// failed ones.
+    pub allocations: usize,
+    /// Whether an allocation was made to fail.
+    pub injected: bool,
+    /// The bytes allocated by the closure minus the bytes it freed, which is
+    /// zero if it dropped everything it allocated.
+    pub leaked_bytes: isize,
+}
+
+/// Runs `f` with `plan` armed on the current thread.
+fn run<R, F: FnOnce() -> R>(plan: Plan, f: F) -> Report<R> {
+    struct Disarm;
+
+    impl Drop for Disarm {
+        fn drop(&mut self) {
+            STATE.with(|cell| cell.set(DISARMED));
+        }
+    }
+
+    STATE.with(|cell| {
+        assert!(
+            matches!(cell.get().plan, Plan::Off),
+            "fault injection runs cannot be nested"
+        );
+        cell.set(State { plan, ..DISARMED });
+    });
+    let disarm = Disarm;
+    let result = f();
+    let state = STATE.with(|cell| cell.get());
+    drop(disarm);
+    Report {
+        result,
+        allocations: state.allocations,
+        injected: state.injected,
+        leaked_bytes: state.live,
+    }
+}
+
+/// Runs `f`, failing its `n`th allocation, counting from zero, through
+/// `FailingAlloc`.
+pub fn fail_nth<R, F: FnOnce() -> R>(n: usize, f: F) -> Report<R> {
+    run(Plan::Nth(n), f)
+}
+
+/// Runs `f`, failing each of its allocations through `FailingAlloc` with a
+/// probability of `1 / one_in`. The failures are the same for a given
+/// `seed`.
+pub fn fail_randomly<R, F: FnOnce() -> R>(seed: u64, one_in: u64, f: F) -> Report<R> {
+    assert!(one_in > 0, "one_in must not be zero");
+    // xorshift gets stuck on zero
+    let state = seed | 1;
+    run(Plan::Random { state, one_in }, f)
+}
+
+/// Runs `f` once for each of its allocations, failing it, until a run
+/// completes without reaching the failed allocation. Panics, with the
+/// index of the failed allocation, if `f` panics or leaks memory in any run,
+/// so `f` should check the invariants of the collections it used after an
+/// error. Returns the number of allocations of
+/// the complete run.
+///
+/// Without the `unstable` feature, the error of the first failed allocation
+/// of the process is built by asking the global allocator for `isize::MAX`
+/// bytes, which counts as one more allocation of that run.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::testing::{check_each_failure, FailingAlloc};
+/// use fallible_collections::TryClone;
+///
+/// #[global_allocator]
+/// static GLOBAL: FailingAlloc = FailingAlloc::new(std::alloc::System);
+///
+/// fn main() {
+///     let words = vec![String::from("fallible"), String::from("collections")];
+///     check_each_failure(|| match words.try_clone() {
+///         Ok(cloned) => assert_eq!(cloned, words),
+///         Err(_) => {}
+///     });
+/// }
+/// ```
+pub fn check_each_failure<F: FnMut()>(mut f: F) -> usize {
+    for n in 0.. {
+        let report = panic::catch_unwind(AssertUnwindSafe(|| fail_nth(n, &mut f)));
+        let report = match report {
+            Ok(report) => report,
+            Err(payload) => {
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => message,
+                    None => payload
+                        .downcast_ref::<std::string::String>()
+                        .map_or("Box<dyn Any>", |message| message.as_str()),
+                };
+                panic!("panicked when failing allocation #{}: {}", n, message)
+            }
+        };
+        assert_eq!(
+            report.leaked_bytes, 0,
+            "leaked memory when failing allocation #{}",
+            n
+        );
+        if !report.injected {
+            return report.allocations;
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boxed::{FallibleBox, FallibleBoxSlice};
+    use crate::{FallibleVec, TryBox, TryClone, TryVec};
+    use alloc::boxed::Box;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    #[global_allocator]
+    static GLOBAL: FailingAlloc<crate::budget::LimitedGlobalAlloc> =
+        FailingAlloc::new(crate::budget::LimitedGlobalAlloc::new(System));
+
+    // the closures below must only allocate fallibly, as an infallible
+    // allocation failure would abort the tests
+    fn try_box(x: u32) -> Result<Box<u32>, crate::TryReserveError> {
+        <Box<u32> as FallibleBox<u32>>::try_new(x)
+    }
+
+    #[test]
+    fn fail_nth_and_randomly() {
+        let report = fail_nth(1, || {
+            let a = TryVec::<u8>::with_capacity(1).is_ok();
+            let b = TryVec::<u8>::with_capacity(1).is_ok();
+            let c = TryVec::<u8>::with_capacity(1).is_ok();
+            (a, b, c)
+        });
+        assert_eq!(report.result, (true, false, true));
+        assert_eq!(report.allocations, 3);
+        assert!(report.injected);
+        assert_eq!(report.leaked_bytes, 0);
+
+        let report = fail_nth(1, || Box::leak(Box::new(0u64)) as *mut u64);
+        assert!(!report.injected);
+        assert_eq!(report.leaked_bytes, 8);
+        drop(unsafe { Box::from_raw(report.result) });
+
+        let run = |seed| {
+            fail_randomly(seed, 4, || {
+                let mut succeeded = [false; 64];
+                for s in succeeded.iter_mut() {
+                    *s = try_box(0).is_ok();
+                }
+                succeeded
+            })
+        };
+        let (a, b) = (run(42), run(42));
+        assert_eq!(a.result, b.result);
+        assert!(a.injected && a.result.contains(&true));
+    }
+
+    #[test]
+    #[should_panic(expected = "panicked when failing allocation #1: second failed")]
+    fn check_each_failure_panic() {
+        check_each_failure(|| {
+            let first = TryVec::<u8>::with_capacity(1);
+            let second = TryVec::<u8>::with_capacity(1);
+            if let (Ok(_), Err(_)) = (first, second) {
+                panic!("second failed");
+            }
+        });
+    }
+
+    #[test]
+    fn try_vec() {
+        let allocations = check_each_failure(|| {
+            let mut vec = TryVec::new();
+            for i in 0..50 {
+                match try_box(i) {
+                    Ok(b) => {
+                        if vec.push(b).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            let len = vec.len();
+            assert!(vec.iter().map(|b| **b).eq(0..len as u32));
+            if let Ok(b) = try_box(100) {
+                if let Err(e) = vec.insert(0, b) {
+                    assert_eq!(*e.into_inner(), 100);
+                    assert_eq!(vec.len(), len);
+                }
+            }
+            if let Ok(cloned) = vec.try_clone() {
+                assert!(cloned.iter().eq(vec.iter()));
+            }
+        });
+        assert!(allocations > 50);
+
+        let word = String::from("fallible");
+        let other: Vec<String> = ["fallible", "collections"].map(String::from).into();
+        check_each_failure(|| {
+            let mut vec: Vec<String> = Vec::new();
+            let value = match word.try_clone() {
+                Ok(value) => value,
+                Err(_) => return,
+            };
+            if vec.try_resize_no_copy(3, value).is_err() {
+                assert!(vec.is_empty());
+            }
+            let len = vec.len();
+            if vec.try_extend_from_slice_no_copy(&other).is_err() {
+                assert_eq!(vec.len(), len);
+            }
+        });
+    }
+
+    /// Runs `op` on a clone of `fixture` failing each allocation in turn,
+    /// checking that the clone is unchanged when `op` fails.
+    fn check_strong<C, T, E>(
+        fixture: &C,
+        same: impl Fn(&C, &C) -> bool,
+        mut op: impl FnMut(&mut C) -> Result<T, E>,
+    ) where
+        C: TryClone,
+    {
+        check_each_failure(|| {
+            let mut c = match fixture.try_clone() {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+            if op(&mut c).is_err() {
+                assert!(same(&c, fixture));
+            }
+        });
+    }
+
+    // the elements are boxed so that cloning them allocates
+    #[allow(clippy::vec_box)]
+    fn boxes(range: core::ops::Range<u32>) -> Vec<Box<u32>> {
+        range.map(Box::new).collect()
+    }
+
+    #[test]
+    fn strong_guarantee() {
+        use crate::{TryBinaryHeap, TryCollect, TryLinkedList, TrySmallVec, TryVecDeque};
+
+        let other = boxes(10..20);
+        let vec = TryVec::from(boxes(0..10));
+        let eq = |a: &TryVec<Box<u32>>, b: &TryVec<Box<u32>>| a == b;
+        check_strong(&vec, eq, |v| v.extend_from_slice(&other));
+        check_strong(&vec, eq, |v| v.try_extend_from_within(2..8));
+        check_strong(&vec, eq, |v| v.split_off(3));
+        check_strong(&vec, eq, |v| {
+            let value = try_box(7)?;
+            v.inner.try_resize_no_copy(30, value)
+        });
+        let vecs = TryVec::from(vec![vec.try_clone().unwrap(), vec.try_clone().unwrap()]);
+        check_each_failure(|| {
+            if let Ok(concat) = vecs.concat() {
+                assert_eq!(concat.len(), 20);
+            }
+        });
+
+        let small: TrySmallVec<[Box<u32>; 4]> = boxes(0..3).try_collect().unwrap();
+        let eq = |a: &TrySmallVec<[Box<u32>; 4]>, b: &TrySmallVec<[Box<u32>; 4]>| a == b;
+        check_strong(&small, eq, |v| v.try_extend_from_slice_no_copy(&other));
+        check_strong(&small, eq, |v| {
+            let value = try_box(7)?;
+            v.try_resize_no_copy(10, value)
+        });
+
+        let deque: TryVecDeque<Box<u32>> = boxes(0..5).try_collect().unwrap();
+        let eq = |a: &TryVecDeque<Box<u32>>, b: &TryVecDeque<Box<u32>>| a == b;
+        check_strong(&deque, eq, |d| d.try_extend_from_slice(&other));
+
+        let list: TryLinkedList<Box<u32>> = boxes(0..5).try_collect().unwrap();
+        let linked: TryLinkedList<Box<u32>> = boxes(5..10).try_collect().unwrap();
+        check_strong(&list, |a, b| a == b, |l| l.try_append_clone(&linked));
+
+        let heap: TryBinaryHeap<u32> = (0..5).try_collect().unwrap();
+        let heaped: TryBinaryHeap<u32> = (5..50).try_collect().unwrap();
+        check_strong(
+            &heap,
+            |a, b| a.iter().eq(b.iter()),
+            |h| h.try_append(&mut heaped.try_clone()?),
+        );
+    }
+
+    #[test]
+    fn fallible_box() {
+        let strings = ["a", "b", "c"].map(String::from);
+        check_each_failure(|| {
+            if let Ok(b) = TryBox::try_new([1u64; 16]) {
+                assert_eq!(*b, [1; 16]);
+            }
+            if let Ok(b) =
+                <Box<[String]> as FallibleBoxSlice<String>>::try_new_slice_from_no_copy(&strings)
+            {
+                assert_eq!(&*b, &strings);
+            }
+            if let Ok(b) = TryBox::try_new_slice_from_no_copy(&strings) {
+                let _ = b.try_clone();
+            }
+        });
+    }
+
+    #[test]
+    fn try_format() {
+        let long = "x".repeat(100);
+        check_each_failure(|| {
+            if let Ok(s) = crate::format::try_format(8, format_args!("{}-{}", long, 42)) {
+                assert_eq!(s.len(), 103);
+            }
+            if let Ok(s) = crate::format::try_format_args(format_args!("{:?}", [1, 2, 3])) {
+                assert_eq!(s, "[1, 2, 3]");
+            }
+        });
+    }
+
+    #[cfg(feature = "hashmap")]
+    #[test]
+    fn try_hash_map() {
+        check_each_failure(|| {
+            let mut map: crate::TryHashMap<_, _> = crate::TryHashMap::default();
+            for i in 0..100 {
+                let b = match try_box(i) {
+                    Ok(b) => b,
+                    Err(_) => break,
+                };
+                let len = map.len();
+                if let Err(e) = map.insert_give_back(i, b) {
+                    assert_eq!(e.into_inner(), (i, Box::new(i)));
+                    assert_eq!(map.len(), len);
+                    break;
+                }
+            }
+            for (k, v) in map.iter() {
+                assert_eq!(k, &**v);
+            }
+            if let Ok(cloned) = map.try_clone() {
+                assert_eq!(cloned.len(), map.len());
+            }
+        });
+
+        let mut map = crate::TryHashMap::with_capacity(1000).unwrap();
+        for i in 0..100 {
+            map.insert(i, Box::new(i)).unwrap();
+        }
+        let same = |a: &crate::TryHashMap<u32, Box<u32>>, b: &crate::TryHashMap<u32, Box<u32>>| {
+            a.len() == b.len() && a.iter().all(|(k, v)| b.get(k) == Some(v))
+        };
+        check_strong(&map, same, |m| m.try_shrink_to(0));
+        check_strong(&map, same, |m| {
+            let value = try_box(1000)?;
+            m.insert(1000, value)
+        });
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    fn btree() {
+        use crate::btree::BTreeMap;
+
+        fn fill(map: &mut BTreeMap<u32, Box<u32>>, keys: impl Iterator<Item = u32>) {
+            for i in keys {
+                let b = match try_box(i) {
+                    Ok(b) => b,
+                    Err(_) => break,
+                };
+                let len = map.len();
+                if map.try_insert(i, b).is_err() {
+                    assert_eq!(map.len(), len);
+                    break;
+                }
+            }
+            map.check();
+        }
+
+        check_each_failure(|| {
+            let mut map = BTreeMap::new();
+            fill(&mut map, (0..300).rev());
+            if let Ok(mut cloned) = map.try_clone() {
+                cloned.check();
+                let mut other = BTreeMap::new();
+                fill(&mut other, 1000..1100);
+                let len = cloned.len() + other.len();
+                match cloned.try_append(&mut other) {
+                    Ok(()) => assert_eq!(cloned.len(), len),
+                    Err(_) => assert_eq!(cloned.len() + other.len(), len),
+                }
+                cloned.check();
+                other.check();
+            }
+            // the values are boxed before reserving, as only the insertions
+            // cannot fail
+            let mut values = TryVec::new();
+            for i in 300..400 {
+                match try_box(i).map(|b| values.push(b)) {
+                    Ok(Ok(())) => {}
+                    _ => return,
+                }
+            }
+            if map.try_reserve(100).is_ok() {
+                for (i, b) in (300..400).zip(values) {
+                    map.try_insert(i, b).unwrap();
+                }
+            }
+            map.check();
+        });
+
+        let mut map = BTreeMap::new();
+        let mut other = BTreeMap::new();
+        for i in 0..300 {
+            map.try_insert(i * 2, Box::new(i)).unwrap();
+            other.try_insert(i * 3, Box::new(i)).unwrap();
+        }
+        let same = |a: &BTreeMap<u32, Box<u32>>, b: &BTreeMap<u32, Box<u32>>| {
+            a.check();
+            a == b
+        };
+        check_strong(&map, same, |m| m.split_off(&100));
+        check_strong(&map, same, |m| {
+            let value = try_box(1001)?;
+            m.try_insert(1001, value)
+        });
+        check_strong(&map, same, |m| {
+            let mut other = other.try_clone()?;
+            let len = other.len();
+            m.try_append(&mut other).map_err(|e| {
+                assert_eq!(other.len(), len);
+                e
+            })
+        });
+    }
+}
@@ -18,7 +18,7 @@ macro_rules! impl_try_clone {
     }
 }
 
-impl_try_clone!(u8, u16, u32, u64, i8, i16, i32, i64, usize, isize, bool);
+impl_try_clone!((), u8, u16, u32, u64, i8, i16, i32, i64, usize, isize, bool);
 
 impl<T: TryClone> TryClone for Option<T> {
     #[inline]
@@ -37,3 +37,53 @@ impl<T: TryClone> TryClone for Option<T> {
 //         Ok(*self)
 //     }
 // }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    std::thread_local! {
+        // the clones `Limited::try_clone` can still make
+        static CLONES: Cell<usize> = const { Cell::new(usize::MAX) };
+        static LIVE: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A value whose clones fail once the budget given to `set_clones` is
+    /// exhausted, and which counts the live values of the thread.
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) struct Limited(pub(crate) u32);
+
+    impl Limited {
+        pub(crate) fn new(value: u32) -> Self {
+            LIVE.with(|live| live.set(live.get() + 1));
+            Limited(value)
+        }
+
+        /// Lets the next `clones` calls to `try_clone` on the current thread
+        /// succeed, and fails the following ones.
+        pub(crate) fn set_clones(clones: usize) {
+            CLONES.with(|c| c.set(clones));
+        }
+
+        /// The number of values alive on the current thread.
+        pub(crate) fn live() -> usize {
+            LIVE.with(Cell::get)
+        }
+    }
+
+    impl TryClone for Limited {
+        fn try_clone(&self) -> Result<Self, TryReserveError> {
+            match CLONES.with(|clones| clones.replace(clones.get().saturating_sub(1))) {
+                0 => Err(crate::make_try_reserve_error()),
+                _ => Ok(Limited::new(self.0)),
+            }
+        }
+    }
+
+    impl Drop for Limited {
+        fn drop(&mut self) {
+            LIVE.with(|live| live.set(live.get() - 1));
+        }
+    }
+}
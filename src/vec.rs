@@ -1,10 +1,15 @@
 //! Implement Fallible Vec
 use super::TryClone;
-use crate::TryReserveError;
+use crate::allocator::{Allocator, Global};
+use crate::{make_alloc_error, AllocError, CollectionKind, ReserveError, TryReserveError};
 #[allow(unused_imports)]
 use alloc::alloc::{alloc, realloc, Layout};
+use alloc::boxed::Box;
 use alloc::vec::Vec;
-use core::convert::TryInto as _;
+#[cfg(not(feature = "unstable"))]
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::{Bound, Range, RangeBounds};
 
 #[cfg(feature = "unstable")]
 #[macro_export]
@@ -24,37 +29,46 @@ macro_rules! try_vec {
 }
 
 /// trait implementing all fallible methods on vec
+///
+/// All the methods leave the vec untouched on error, except that its
+/// capacity may have grown, see the crate documentation on exception
+/// safety.
 pub trait FallibleVec<T> {
-    /// see reserve
+    /// see reserve (strong guarantee)
     fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
-    /// see push
+    /// see push (strong guarantee)
     fn try_push(&mut self, elem: T) -> Result<(), TryReserveError>;
-    /// try push and give back ownership in case of error
+    /// try push and give back ownership in case of error (strong guarantee)
     fn try_push_give_back(&mut self, elem: T) -> Result<(), (T, TryReserveError)>;
     /// see with capacity, (Self must be sized by the constraint of Result)
     fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError>
     where
         Self: core::marker::Sized;
-    /// see insert
+    /// see insert (strong guarantee)
     fn try_insert(&mut self, index: usize, element: T) -> Result<(), (T, TryReserveError)>;
-    /// see append
+    /// see append (strong guarantee)
     fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError>;
     /// see resize, only works when the `value` implements Copy, otherwise, look at try_resize_no_copy
+    /// (strong guarantee)
     fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError>
     where
         T: Copy + Clone;
+    /// see resize_with (strong guarantee)
     fn try_resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
     where
         F: FnMut() -> T;
-    /// resize the vec by trying to clone the value repeatingly
+    /// resize the vec by trying to clone the value repeatingly, the clones
+    /// already made are dropped if one fails (strong guarantee)
     fn try_resize_no_copy(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError>
     where
         T: TryClone;
     /// see resize, only works when the `value` implements Copy, otherwise, look at try_extend_from_slice_no_copy
+    /// (strong guarantee)
     fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError>
     where
         T: Copy + Clone;
-    /// extend the vec by trying to clone the value in `other`
+    /// extend the vec by trying to clone the value in `other`, the clones
+    /// already made are dropped if one fails (strong guarantee)
     fn try_extend_from_slice_no_copy(&mut self, other: &[T]) -> Result<(), TryReserveError>
     where
         T: TryClone;
@@ -64,21 +78,27 @@ pub trait FallibleVec<T> {
 /// fallible allocation.
 ///
 /// See the crate documentation for more.
-#[derive(PartialEq)]
-pub struct TryVec<T> {
-    inner: Vec<T>,
+///
+/// The elements are stored in the allocator `A`. The vector can only be
+/// created in another allocator than `Global` with the `unstable` feature,
+/// as `Vec` takes an allocator parameter only on nightly.
+pub struct TryVec<T, A: Allocator = Global> {
+    #[cfg(feature = "unstable")]
+    pub(crate) inner: Vec<T, A>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) inner: Vec<T>,
+    #[cfg(not(feature = "unstable"))]
+    alloc: PhantomData<A>,
 }
 
 impl<T> Default for TryVec<T> {
     #[inline(always)]
     fn default() -> Self {
-        Self {
-            inner: Default::default(),
-        }
+        Self::new()
     }
 }
 
-impl<T: core::fmt::Debug> core::fmt::Debug for TryVec<T> {
+impl<T: core::fmt::Debug, A: Allocator> core::fmt::Debug for TryVec<T, A> {
     #[inline]
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.inner.fmt(f)
@@ -88,19 +108,123 @@ impl<T: core::fmt::Debug> core::fmt::Debug for TryVec<T> {
 impl<T> TryVec<T> {
     #[inline(always)]
     pub fn new() -> Self {
-        Self { inner: Vec::new() }
+        Self::from(Vec::new())
     }
 
     #[inline]
     pub fn with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
-        Ok(Self {
-            inner: FallibleVec::try_with_capacity(capacity)?,
-        })
+        let inner: Vec<T> = FallibleVec::try_with_capacity(capacity)?;
+        Ok(Self::from(inner))
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> Vec<T> {
+        self.inner
     }
 
+    /// Converts the vector into a boxed slice, the excess capacity is
+    /// released with a fallible reallocation instead of the possibly
+    /// aborting one of into_boxed_slice.
+    #[inline]
+    pub fn try_into_boxed_slice(mut self) -> Result<Box<[T]>, TryReserveError> {
+        try_shrink_to_fit(&mut self.inner)?;
+        Ok(self.inner.into_boxed_slice())
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl<T, A: Allocator> TryVec<T, A> {
+    /// Creates an empty vector allocating in `alloc`.
+    #[inline(always)]
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            inner: Vec::new_in(alloc),
+        }
+    }
+
+    /// Creates a vector with room for `capacity` elements allocated in
+    /// `alloc`.
+    #[inline]
+    pub fn try_with_capacity_in(capacity: usize, alloc: A) -> Result<Self, TryReserveError> {
+        let mut v = Self::new_in(alloc);
+        v.reserve(capacity)?;
+        Ok(v)
+    }
+
+    /// Returns a reference to the allocator of the vector.
+    #[inline(always)]
+    pub fn allocator(&self) -> &A {
+        self.inner.allocator()
+    }
+
+    #[inline(always)]
+    pub fn drain<R>(&mut self, range: R) -> alloc::vec::Drain<'_, T, A>
+    where
+        R: RangeBounds<usize>,
+    {
+        self.inner.drain(range)
+    }
+
+    /// Replaces the elements of `range` by the ones of `replace_with`,
+    /// returning an iterator over the removed elements.
+    ///
+    /// The elements of `replace_with` are first collected fallibly and the
+    /// room needed by them is reserved, so that the returned iterator
+    /// never allocates. The vector is left untouched on error (strong
+    /// guarantee).
+    pub fn splice<R, I>(
+        &mut self,
+        range: R,
+        replace_with: I,
+    ) -> Result<alloc::vec::Splice<'_, alloc::vec::IntoIter<T>, A>, TryReserveError>
+    where
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = T>,
+    {
+        let (range, replace_with) = self.reserve_splice(range, replace_with)?;
+        Ok(self.inner.splice(range, replace_with))
+    }
+}
+
+#[cfg(not(feature = "unstable"))]
+impl<T, A: Allocator> TryVec<T, A> {
+    #[inline(always)]
+    pub fn drain<R>(&mut self, range: R) -> alloc::vec::Drain<'_, T>
+    where
+        R: RangeBounds<usize>,
+    {
+        self.inner.drain(range)
+    }
+
+    /// Replaces the elements of `range` by the ones of `replace_with`,
+    /// returning an iterator over the removed elements.
+    ///
+    /// The elements of `replace_with` are first collected fallibly and the
+    /// room needed by them is reserved, so that the returned iterator
+    /// never allocates. The vector is left untouched on error (strong
+    /// guarantee).
+    pub fn splice<R, I>(
+        &mut self,
+        range: R,
+        replace_with: I,
+    ) -> Result<alloc::vec::Splice<'_, alloc::vec::IntoIter<T>>, TryReserveError>
+    where
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = T>,
+    {
+        let (range, replace_with) = self.reserve_splice(range, replace_with)?;
+        Ok(self.inner.splice(range, replace_with))
+    }
+}
+
+impl<T, A: Allocator> TryVec<T, A> {
+    /// Moves all the elements of `other` into `self`, leaving `other` empty.
+    /// Both vectors are left unchanged on error (strong guarantee).
     #[inline(always)]
     pub fn append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
-        FallibleVec::try_append(&mut self.inner, &mut other.inner)
+        self.reserve(other.len())?;
+        self.inner.append(&mut other.inner);
+        Ok(())
     }
 
     #[inline(always)]
@@ -113,14 +237,51 @@ impl<T> TryVec<T> {
         self
     }
 
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
     #[inline(always)]
     pub fn clear(&mut self) {
         self.inner.clear()
     }
 
-    #[cfg(test)]
-    pub fn into_inner(self) -> Vec<T> {
-        self.inner
+    #[inline(always)]
+    pub fn dedup_by_key<F, K>(&mut self, key: F)
+    where
+        F: FnMut(&mut T) -> K,
+        K: PartialEq,
+    {
+        self.inner.dedup_by_key(key)
+    }
+
+    #[inline(always)]
+    pub fn dedup_by<F>(&mut self, same_bucket: F)
+    where
+        F: FnMut(&mut T, &mut T) -> bool,
+    {
+        self.inner.dedup_by(same_bucket)
+    }
+
+    #[inline(always)]
+    pub fn dedup(&mut self)
+    where
+        T: PartialEq,
+    {
+        self.inner.dedup()
+    }
+
+    /// Inserts an element at position `index`, giving back the element if
+    /// the allocation failed. The vector is left unchanged on error (strong
+    /// guarantee).
+    #[inline]
+    pub fn insert(&mut self, index: usize, element: T) -> Result<(), AllocError<T>> {
+        if let Err(e) = self.reserve(1) {
+            return Err(AllocError::from((element, e)));
+        }
+        self.inner.insert(index, element);
+        Ok(())
     }
 
     #[inline(always)]
@@ -129,47 +290,223 @@ impl<T> TryVec<T> {
     }
 
     #[inline(always)]
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         IterMut {
             inner: self.inner.iter_mut(),
         }
     }
 
     #[inline(always)]
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter {
             inner: self.inner.iter(),
         }
     }
 
+    /// Consumes and leaks the vector, see std::vec::Vec::leak
+    #[inline(always)]
+    pub fn leak<'a>(self) -> &'a mut [T]
+    where
+        A: 'a,
+    {
+        self.inner.leak()
+    }
+
     #[inline(always)]
     pub fn pop(&mut self) -> Option<T> {
         self.inner.pop()
     }
 
-    #[inline(always)]
+    /// Appends an element to the back of the vector, which is left unchanged on
+    /// error (strong guarantee).
+    #[inline]
     pub fn push(&mut self, value: T) -> Result<(), TryReserveError> {
-        FallibleVec::try_push(&mut self.inner, value)
+        if self.inner.len() == self.inner.capacity() {
+            self.reserve(1)?;
+        }
+        self.inner.push(value);
+        Ok(())
+    }
+
+    /// Appends an element to the back of the vector, giving back the
+    /// element if the allocation failed. The vector is left unchanged on error
+    /// (strong guarantee).
+    #[inline]
+    pub fn push_give_back(&mut self, value: T) -> Result<(), AllocError<T>> {
+        if self.inner.len() == self.inner.capacity() {
+            if let Err(e) = self.reserve(1) {
+                return Err(AllocError::from((value, e)));
+            }
+        }
+        self.inner.push(value);
+        Ok(())
     }
 
+    #[inline(always)]
+    pub fn remove(&mut self, index: usize) -> T {
+        self.inner.remove(index)
+    }
+
+    /// Reserves room for at least `additional` more elements, the vector is
+    /// left unchanged on error (strong guarantee).
     #[inline(always)]
     pub fn reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
-        FallibleVec::try_reserve(&mut self.inner, additional)
+        self.inner.try_reserve(additional)
     }
 
+    /// Reserves room for exactly `additional` more elements, the vector is left
+    /// unchanged on error (strong guarantee).
     #[inline(always)]
+    pub fn reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.inner.try_reserve_exact(additional)
+    }
+
+    /// Reserves room for at least `additional` more elements, as `reserve`,
+    /// the error telling the length the vector was grown to. The vector is
+    /// left unchanged on error (strong guarantee).
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.inner.len().saturating_add(additional);
+        self.reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::Vec)
+                .with_len(len)
+        })
+    }
+
+    /// Resizes the vector to `new_len`, filling the new slots with the values
+    /// returned by `f`. The vector is left unchanged on error (strong
+    /// guarantee).
+    #[inline]
     pub fn resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
     where
         F: FnMut() -> T,
     {
-        FallibleVec::try_resize_with(&mut self.inner, new_len, f)
+        let len = self.len();
+        if new_len > len {
+            self.reserve(new_len - len)?;
+        }
+        self.inner.resize_with(new_len, f);
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.inner.retain(f)
+    }
+
+    #[inline(always)]
+    pub fn retain_mut<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        self.inner.retain_mut(f)
+    }
+
+    /// # Safety
+    ///
+    /// See std::vec::Vec::set_len
+    #[inline(always)]
+    pub unsafe fn set_len(&mut self, new_len: usize) {
+        self.inner.set_len(new_len)
+    }
+
+    /// Shrinks the capacity of the vector to its length with a fallible
+    /// reallocation, the vector is left untouched on error (strong guarantee).
+    #[inline(always)]
+    pub fn shrink_to_fit(&mut self) -> Result<(), TryReserveError> {
+        try_shrink_to_fit(&mut self.inner)
+    }
+
+    #[inline(always)]
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<T>] {
+        self.inner.spare_capacity_mut()
+    }
+
+    /// Collects `replace_with` and reserves the room it needs to replace
+    /// `range`, returning both for `splice`.
+    fn reserve_splice<R, I>(
+        &mut self,
+        range: R,
+        replace_with: I,
+    ) -> Result<(Range<usize>, Vec<T>), TryReserveError>
+    where
+        R: RangeBounds<usize>,
+        I: IntoIterator<Item = T>,
+    {
+        let range = slice_range(&range, self.len());
+        let replace_with: Vec<T> = replace_with.try_collect()?;
+        let removed = range.end - range.start;
+        if replace_with.len() > removed {
+            self.reserve(replace_with.len() - removed)?;
+        }
+        Ok((range, replace_with))
+    }
+
+    /// Splits the vector in two at the given index, allocating the
+    /// returned vector fallibly. The vector is left untouched on error (strong
+    /// guarantee).
+    pub fn split_off(&mut self, at: usize) -> Result<Self, TryReserveError>
+    where
+        A: Clone,
+    {
+        let len = self.len();
+        assert!(
+            at <= len,
+            "`at` split index (is {}) should be <= len (is {})",
+            at,
+            len
+        );
+        let mut other = self.try_with_capacity_of(len - at)?;
+        unsafe {
+            self.inner.set_len(at);
+            core::ptr::copy_nonoverlapping(
+                self.inner.as_ptr().add(at),
+                other.inner.as_mut_ptr(),
+                len - at,
+            );
+            other.inner.set_len(len - at);
+        }
+        Ok(other)
+    }
+
+    #[inline(always)]
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        self.inner.swap_remove(index)
+    }
+
+    #[inline(always)]
+    pub fn truncate(&mut self, len: usize) {
+        self.inner.truncate(len)
+    }
+
+    /// Creates an empty vector with room for `capacity` elements, in the
+    /// allocator of this one.
+    fn try_with_capacity_of(&self, capacity: usize) -> Result<Self, TryReserveError>
+    where
+        A: Clone,
+    {
+        #[cfg(feature = "unstable")]
+        let mut inner = Vec::new_in(self.inner.allocator().clone());
+        #[cfg(not(feature = "unstable"))]
+        let mut inner = Vec::new();
+        inner.try_reserve(capacity)?;
+        Ok(Self {
+            inner,
+            #[cfg(not(feature = "unstable"))]
+            alloc: PhantomData,
+        })
     }
 }
 
-impl<T: TryClone> TryClone for TryVec<T> {
+impl<T: TryClone, A: Allocator + Clone> TryClone for TryVec<T, A> {
     #[inline]
     fn try_clone(&self) -> Result<Self, TryReserveError> {
-        self.as_slice().try_into()
+        let mut v = self.try_with_capacity_of(self.len())?;
+        v.extend_from_slice(self)?;
+        Ok(v)
     }
 }
 
@@ -184,14 +521,61 @@ impl<T: TryClone> TryVec<TryVec<T>> {
     }
 }
 
-impl<T: TryClone> TryVec<T> {
-    #[inline(always)]
+impl<T: TryClone, A: Allocator> TryVec<T, A> {
+    /// Clones the elements of `other` and appends them to the vector, the
+    /// vector is left untouched on error (strong guarantee).
     pub fn extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> {
-        self.inner.try_extend_from_slice_no_copy(other)
+        let old_len = self.len();
+        self.reserve(other.len())?;
+        for element in other {
+            match element.try_clone() {
+                // can't reallocate as the room is reserved
+                Ok(element) => self.inner.push(element),
+                Err(e) => {
+                    self.inner.truncate(old_len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Clones the elements of `src` and appends them to the vector, the
+    /// vector is left untouched on error (strong guarantee).
+    pub fn try_extend_from_within<R>(&mut self, src: R) -> Result<(), TryReserveError>
+    where
+        R: RangeBounds<usize>,
+    {
+        let range = slice_range(&src, self.len());
+        let old_len = self.len();
+        self.reserve(range.end - range.start)?;
+        for i in range {
+            match self.inner[i].try_clone() {
+                // can't reallocate as the room is reserved
+                Ok(elem) => self.inner.push(elem),
+                Err(e) => {
+                    self.inner.truncate(old_len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl<T, A: Allocator> IntoIterator for TryVec<T, A> {
+    type Item = T;
+    type IntoIter = alloc::vec::IntoIter<T, A>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
     }
 }
 
-impl<T> IntoIterator for TryVec<T> {
+#[cfg(not(feature = "unstable"))]
+impl<T, A: Allocator> IntoIterator for TryVec<T, A> {
     type Item = T;
     type IntoIter = alloc::vec::IntoIter<T>;
 
@@ -201,7 +585,7 @@ impl<T> IntoIterator for TryVec<T> {
     }
 }
 
-impl<'a, T> IntoIterator for &'a TryVec<T> {
+impl<'a, T, A: Allocator> IntoIterator for &'a TryVec<T, A> {
     type Item = &'a T;
     type IntoIter = alloc::slice::Iter<'a, T>;
 
@@ -299,7 +683,7 @@ pub mod std_io {
 
         #[test]
         fn read_into_try_vec_oom() {
-            let mut src = b"1234567890".take(core::usize::MAX.try_into().expect("usize < u64"));
+            let mut src = b"1234567890".take(usize::MAX.try_into().expect("usize < u64"));
             assert!(src.read_into_try_vec().is_err());
         }
 
@@ -316,35 +700,42 @@ pub mod std_io {
         fn try_read_up_to_oom() {
             let src = b"1234567890";
             let mut buf = TryVec::new();
-            let limit = core::usize::MAX.try_into().expect("usize < u64");
+            let limit = usize::MAX.try_into().expect("usize < u64");
             let res = super::try_read_up_to(&mut src.as_ref(), limit, &mut buf);
             assert!(res.is_err());
         }
     }
 }
 
-impl<T: PartialEq> PartialEq<Vec<T>> for TryVec<T> {
+impl<T: PartialEq, A: Allocator, B: Allocator> PartialEq<TryVec<T, B>> for TryVec<T, A> {
+    #[inline(always)]
+    fn eq(&self, other: &TryVec<T, B>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: PartialEq, A: Allocator> PartialEq<Vec<T>> for TryVec<T, A> {
     #[inline(always)]
     fn eq(&self, other: &Vec<T>) -> bool {
-        self.inner.eq(other)
+        self.as_slice() == other.as_slice()
     }
 }
 
-impl<'a, T: PartialEq> PartialEq<&'a [T]> for TryVec<T> {
+impl<T: PartialEq, A: Allocator> PartialEq<&[T]> for TryVec<T, A> {
     #[inline(always)]
     fn eq(&self, other: &&[T]) -> bool {
-        self.inner.eq(other)
+        self.as_slice() == *other
     }
 }
 
-impl PartialEq<&str> for TryVec<u8> {
+impl<A: Allocator> PartialEq<&str> for TryVec<u8, A> {
     #[inline]
     fn eq(&self, other: &&str) -> bool {
         self.as_slice() == other.as_bytes()
     }
 }
 
-impl core::convert::AsRef<[u8]> for TryVec<u8> {
+impl<A: Allocator> core::convert::AsRef<[u8]> for TryVec<u8, A> {
     #[inline(always)]
     fn as_ref(&self) -> &[u8] {
         self.inner.as_ref()
@@ -354,7 +745,11 @@ impl core::convert::AsRef<[u8]> for TryVec<u8> {
 impl<T> core::convert::From<Vec<T>> for TryVec<T> {
     #[inline(always)]
     fn from(value: Vec<T>) -> Self {
-        Self { inner: value }
+        Self {
+            inner: value,
+            #[cfg(not(feature = "unstable"))]
+            alloc: PhantomData,
+        }
     }
 }
 
@@ -380,7 +775,7 @@ impl core::convert::TryFrom<&str> for TryVec<u8> {
     }
 }
 
-impl<T> core::ops::Deref for TryVec<T> {
+impl<T, A: Allocator> core::ops::Deref for TryVec<T, A> {
     type Target = [T];
 
     #[inline(always)]
@@ -389,7 +784,7 @@ impl<T> core::ops::Deref for TryVec<T> {
     }
 }
 
-impl<T> core::ops::DerefMut for TryVec<T> {
+impl<T, A: Allocator> core::ops::DerefMut for TryVec<T, A> {
     fn deref_mut(&mut self) -> &mut [T] {
         self.inner.deref_mut()
     }
@@ -431,6 +826,92 @@ impl<'a, T> Iterator for IterMut<'a, T> {
     }
 }
 
+/// Resolve `range` on a slice of length `len`, panicking as slice indexing
+/// does when it is out of bounds
+fn slice_range<R: RangeBounds<usize>>(range: &R, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start
+            .checked_add(1)
+            .expect("attempted to index slice from after maximum usize"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end
+            .checked_add(1)
+            .expect("attempted to index slice up to maximum usize"),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end,
+        "slice index starts at {} but ends at {}",
+        start,
+        end
+    );
+    assert!(
+        end <= len,
+        "range end index {} out of range for slice of length {}",
+        end,
+        len
+    );
+    start..end
+}
+
+/// Shrink the capacity of `v` to its length with a fallible reallocation,
+/// `v` is left untouched on error.
+#[cfg(not(feature = "unstable"))]
+fn try_shrink_to_fit<T>(v: &mut Vec<T>) -> Result<(), TryReserveError> {
+    let len = v.len();
+    if v.capacity() == len || core::mem::size_of::<T>() == 0 {
+        return Ok(());
+    }
+    if len == 0 {
+        *v = Vec::new();
+        return Ok(());
+    }
+    // the layouts can't overflow as the buffer is already allocated
+    let old_layout = Layout::array::<T>(v.capacity()).expect("allocated layout");
+    let new_layout = Layout::array::<T>(len).expect("allocated layout");
+    unsafe {
+        let ptr = realloc(v.as_mut_ptr() as *mut u8, old_layout, new_layout.size());
+        if ptr.is_null() {
+            return Err(make_alloc_error(new_layout));
+        }
+        // the old buffer has been released by realloc, don't drop it
+        core::ptr::write(v, Vec::from_raw_parts(ptr as *mut T, len, len));
+    }
+    Ok(())
+}
+
+/// Shrink the capacity of `v` to its length with a fallible reallocation
+/// in its allocator, `v` is left untouched on error.
+#[cfg(feature = "unstable")]
+fn try_shrink_to_fit<T, A: Allocator>(v: &mut Vec<T, A>) -> Result<(), TryReserveError> {
+    let len = v.len();
+    if v.capacity() == len || core::mem::size_of::<T>() == 0 {
+        return Ok(());
+    }
+    // the layouts can't overflow as the buffer is already allocated
+    let old_layout = Layout::array::<T>(v.capacity()).expect("allocated layout");
+    let new_layout = Layout::array::<T>(len).expect("allocated layout");
+    unsafe {
+        let ptr = core::ptr::NonNull::new_unchecked(v.as_mut_ptr() as *mut u8);
+        let ptr = v
+            .allocator()
+            .shrink(ptr, old_layout, new_layout)
+            .map_err(|_| make_alloc_error(new_layout))?;
+        // the old buffer has been released by shrink, don't drop it
+        let old = core::mem::ManuallyDrop::new(core::ptr::read(v));
+        let alloc = core::ptr::read(old.allocator());
+        core::ptr::write(
+            v,
+            Vec::from_raw_parts_in(ptr.as_ptr() as *mut T, len, len, alloc),
+        );
+    }
+    Ok(())
+}
+
 fn needs_to_grow<T>(v: &Vec<T>, len: usize) -> bool {
     v.len()
         .checked_add(len)
@@ -448,7 +929,8 @@ impl<T> FallibleVec<T> for Vec<T> {
         if self.len() == self.capacity() {
             self.try_reserve(1)?;
         }
-        Ok(self.push(elem))
+        self.push(elem);
+        Ok(())
     }
 
     #[inline]
@@ -458,7 +940,8 @@ impl<T> FallibleVec<T> for Vec<T> {
                 return Err((elem, e));
             }
         }
-        Ok(self.push(elem))
+        self.push(elem);
+        Ok(())
     }
 
     #[inline]
@@ -478,13 +961,15 @@ impl<T> FallibleVec<T> for Vec<T> {
                 return Err((element, e));
             }
         }
-        Ok(self.insert(index, element))
+        self.insert(index, element);
+        Ok(())
     }
 
     #[inline]
     fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
         FallibleVec::try_reserve(self, other.len())?;
-        Ok(self.append(other))
+        self.append(other);
+        Ok(())
     }
 
     fn try_resize(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError>
@@ -495,7 +980,8 @@ impl<T> FallibleVec<T> for Vec<T> {
         if new_len > len {
             FallibleVec::try_reserve(self, new_len - len)?;
         }
-        Ok(self.resize(new_len, value))
+        self.resize(new_len, value);
+        Ok(())
     }
 
     fn try_resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
@@ -506,7 +992,8 @@ impl<T> FallibleVec<T> for Vec<T> {
         if new_len > len {
             FallibleVec::try_reserve(self, new_len - len)?;
         }
-        Ok(self.resize_with(new_len, f))
+        self.resize_with(new_len, f);
+        Ok(())
     }
 
     fn try_resize_no_copy(&mut self, new_len: usize, value: T) -> Result<(), TryReserveError>
@@ -518,7 +1005,8 @@ impl<T> FallibleVec<T> for Vec<T> {
         if new_len > len {
             self.try_extend_with(new_len - len, TryExtendElement(value))
         } else {
-            Ok(Truncate::truncate(self, new_len))
+            Truncate::truncate(self, new_len);
+            Ok(())
         }
     }
 
@@ -530,7 +1018,8 @@ impl<T> FallibleVec<T> for Vec<T> {
         if needs_to_grow(self, other.len()) {
             self.try_reserve(other.len())?;
         }
-        Ok(self.extend_from_slice(other))
+        self.extend_from_slice(other);
+        Ok(())
     }
 
     fn try_extend_from_slice_no_copy(&mut self, other: &[T]) -> Result<(), TryReserveError>
@@ -540,11 +1029,18 @@ impl<T> FallibleVec<T> for Vec<T> {
         if needs_to_grow(self, other.len()) {
             self.try_reserve(other.len())?;
         }
-        let mut len = self.len();
-        let mut iterator = other.iter();
-        while let Some(element) = iterator.next() {
+        let old_len = self.len();
+        let mut len = old_len;
+        for element in other.iter() {
+            let element = match element.try_clone() {
+                Ok(element) => element,
+                Err(e) => {
+                    Truncate::truncate(self, old_len);
+                    return Err(e);
+                }
+            };
             unsafe {
-                core::ptr::write(self.as_mut_ptr().add(len), element.try_clone()?);
+                core::ptr::write(self.as_mut_ptr().add(len), element);
                 // NB can't overflow since we would have had to alloc the address space
                 len += 1;
                 self.set_len(len);
@@ -593,10 +1089,19 @@ impl<T> TryExtend<T> for Vec<T> {
         unsafe {
             let mut ptr = self.as_mut_ptr().add(self.len());
 
-            let mut local_len = self.len();
+            let old_len = self.len();
+            let mut local_len = old_len;
             // Write all elements except the last one
             for _ in 1..n {
-                core::ptr::write(ptr, value.next()?);
+                let element = match value.next() {
+                    Ok(element) => element,
+                    Err(e) => {
+                        // drop the elements already written
+                        Truncate::truncate(self, old_len);
+                        return Err(e);
+                    }
+                };
+                core::ptr::write(ptr, element);
                 ptr = ptr.offset(1);
                 // Increment the length in every step in case next() panics
                 local_len += 1;
@@ -693,10 +1198,7 @@ pub trait TryFromIterator<I>: Sized {
 }
 
 impl<I> TryFromIterator<I> for Vec<I> {
-    fn try_from_iterator<T: IntoIterator<Item = I>>(iterator: T) -> Result<Self, TryReserveError>
-    where
-        T: IntoIterator<Item = I>,
-    {
+    fn try_from_iterator<T: IntoIterator<Item = I>>(iterator: T) -> Result<Self, TryReserveError> {
         let mut new = Self::new();
         for i in iterator {
             new.try_push(i)?;
@@ -744,28 +1246,24 @@ mod tests {
     #[test]
     fn try_clone_oom() {
         let layout = Layout::new::<u8>();
-        let v = unsafe {
-            Vec::<u8>::from_raw_parts(
-                alloc(layout),
-                core::isize::MAX as usize,
-                core::isize::MAX as usize,
-            )
-        };
+        let ptr = unsafe { alloc(layout) };
+        // only one byte is allocated, so it is freed with its own layout
+        let v = core::mem::ManuallyDrop::new(unsafe {
+            Vec::<u8>::from_raw_parts(ptr, isize::MAX as usize, isize::MAX as usize)
+        });
         assert!(v.try_clone().is_err());
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
     }
 
     #[test]
     fn tryvec_try_clone_oom() {
         let layout = Layout::new::<u8>();
-        let inner = unsafe {
-            Vec::<u8>::from_raw_parts(
-                alloc(layout),
-                core::isize::MAX as usize,
-                core::isize::MAX as usize,
-            )
-        };
-        let tv = TryVec { inner };
+        let ptr = unsafe { alloc(layout) };
+        let inner =
+            unsafe { Vec::<u8>::from_raw_parts(ptr, isize::MAX as usize, isize::MAX as usize) };
+        let tv = core::mem::ManuallyDrop::new(TryVec::from(inner));
         assert!(tv.try_clone().is_err());
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
     }
 
     // #[test]
@@ -777,27 +1275,25 @@ mod tests {
     #[test]
     fn oom() {
         let mut vec: Vec<char> = Vec::new();
-        match FallibleVec::try_reserve(&mut vec, core::usize::MAX / std::mem::size_of::<char>()) {
-            Ok(_) => panic!("it should be OOM"),
-            _ => (),
-        }
-        match FallibleVec::try_reserve(&mut vec, core::usize::MAX) {
-            Ok(_) => panic!("it should be OOM"),
-            _ => (),
-        }
+        assert!(
+            FallibleVec::try_reserve(&mut vec, usize::MAX / std::mem::size_of::<char>()).is_err(),
+            "it should be OOM"
+        );
+        assert!(
+            FallibleVec::try_reserve(&mut vec, usize::MAX).is_err(),
+            "it should be OOM"
+        );
     }
 
     #[test]
     fn tryvec_oom() {
         let mut vec: TryVec<char> = TryVec::new();
-        match vec.reserve(core::usize::MAX / std::mem::size_of::<char>()) {
-            Ok(_) => panic!("it should be OOM"),
-            _ => (),
-        }
-        match vec.reserve(core::usize::MAX) {
-            Ok(_) => panic!("it should be OOM"),
-            _ => (),
-        }
+        assert!(
+            vec.reserve(usize::MAX / std::mem::size_of::<char>())
+                .is_err(),
+            "it should be OOM"
+        );
+        assert!(vec.reserve(usize::MAX).is_err(), "it should be OOM");
     }
 
     #[test]
@@ -844,19 +1340,19 @@ mod tests {
     #[test]
     fn capacity_overflow() {
         let mut vec: Vec<_> = vec![1];
-        match FallibleVec::try_reserve(&mut vec, core::usize::MAX) {
-            Ok(_) => panic!("capacity calculation should overflow"),
-            _ => (),
-        }
+        assert!(
+            FallibleVec::try_reserve(&mut vec, usize::MAX).is_err(),
+            "capacity calculation should overflow"
+        );
     }
 
     #[test]
     fn tryvec_capacity_overflow() {
         let mut vec: TryVec<_> = vec![1].into();
-        match vec.reserve(core::usize::MAX) {
-            Ok(_) => panic!("capacity calculation should overflow"),
-            _ => (),
-        }
+        assert!(
+            vec.reserve(usize::MAX).is_err(),
+            "capacity calculation should overflow"
+        );
     }
 
     #[test]
@@ -881,9 +1377,190 @@ mod tests {
         assert_eq!(vec, b"foobar".as_ref());
     }
 
+    #[test]
+    fn tryvec_try_into_boxed_slice() {
+        let mut vec: TryVec<u32> = TryVec::with_capacity(10).unwrap();
+        vec.push(1).unwrap();
+        vec.push(2).unwrap();
+        let b = vec.try_into_boxed_slice().unwrap();
+        assert_eq!(&*b, &[1, 2]);
+        let vec: TryVec<u32> = TryVec::with_capacity(10).unwrap();
+        assert!(vec.try_into_boxed_slice().unwrap().is_empty());
+        let mut vec: TryVec<()> = TryVec::new();
+        vec.push(()).unwrap();
+        assert_eq!(vec.try_into_boxed_slice().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tryvec_insert_remove() {
+        let mut vec: TryVec<u32> = vec![1, 2, 3].into();
+        vec.insert(1, 5).unwrap();
+        assert_eq!(vec, vec![1, 5, 2, 3]);
+        assert_eq!(vec.remove(0), 1);
+        assert_eq!(vec.swap_remove(0), 5);
+        assert_eq!(vec, vec![3, 2]);
+        vec.truncate(1);
+        assert_eq!(vec, vec![3]);
+    }
+
+    #[test]
+    fn tryvec_push_give_back() {
+        let mut vec = TryVec::new();
+        vec.push_give_back(String::from("foo")).unwrap();
+        vec.insert(0, String::from("bar")).unwrap();
+        assert_eq!(vec, vec![String::from("bar"), String::from("foo")]);
+    }
+
+    #[test]
+    fn tryvec_retain_dedup() {
+        let mut vec: TryVec<u32> = vec![1, 1, 2, 3, 3, 4].into();
+        vec.dedup();
+        assert_eq!(vec, vec![1, 2, 3, 4]);
+        vec.retain(|x| x % 2 == 0);
+        assert_eq!(vec, vec![2, 4]);
+        vec.retain_mut(|x| {
+            *x += 1;
+            *x > 3
+        });
+        assert_eq!(vec, vec![5]);
+        let mut vec: TryVec<u32> = vec![10, 11, 20, 21, 30].into();
+        vec.dedup_by_key(|x| *x / 10);
+        assert_eq!(vec, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn tryvec_drain() {
+        let mut vec: TryVec<u32> = vec![1, 2, 3, 4].into();
+        assert_eq!(vec.drain(1..3).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(vec, vec![1, 4]);
+    }
+
+    #[test]
+    fn tryvec_split_off() {
+        let mut vec: TryVec<String> = vec!["a".into(), "b".into(), "c".into()].into();
+        let other = vec.split_off(1).unwrap();
+        assert_eq!(vec, vec!["a".to_string()]);
+        assert_eq!(other, vec!["b".to_string(), "c".to_string()]);
+        assert!(vec.split_off(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tryvec_splice() {
+        let mut vec: TryVec<u32> = vec![1, 2, 3, 4].into();
+        let removed: Vec<_> = vec.splice(1..3, [7, 8, 9]).unwrap().collect();
+        assert_eq!(removed, vec![2, 3]);
+        assert_eq!(vec, vec![1, 7, 8, 9, 4]);
+        vec.splice(..4, None).unwrap();
+        assert_eq!(vec, vec![4]);
+    }
+
+    #[test]
+    fn tryvec_extend_from_within() {
+        let mut vec: TryVec<u32> = vec![1, 2, 3].into();
+        vec.try_extend_from_within(1..).unwrap();
+        assert_eq!(vec, vec![1, 2, 3, 2, 3]);
+        vec.try_extend_from_within(..=0).unwrap();
+        assert_eq!(vec, vec![1, 2, 3, 2, 3, 1]);
+    }
+
+    #[test]
+    fn tryvec_capacity() {
+        let mut vec: TryVec<u32> = TryVec::with_capacity(10).unwrap();
+        vec.push(1).unwrap();
+        assert!(vec.capacity() >= 10);
+        vec.shrink_to_fit().unwrap();
+        assert_eq!(vec.capacity(), 1);
+        vec.reserve_exact(3).unwrap();
+        assert!(vec.capacity() >= 4);
+        let spare = vec.spare_capacity_mut();
+        spare[0].write(2);
+        unsafe { vec.set_len(2) };
+        assert_eq!(vec, vec![1, 2]);
+        vec.shrink_to_fit().unwrap();
+        let leaked = vec.leak();
+        assert_eq!(leaked, &[1, 2]);
+        drop(unsafe { Box::from_raw(leaked) });
+    }
+
+    #[test]
+    fn tryvec_reserve_exact_oom() {
+        let mut vec: TryVec<u32> = TryVec::new();
+        assert!(vec.reserve_exact(usize::MAX).is_err(), "it should be OOM");
+    }
+
+    #[test]
+    fn tryvec_reserve_detailed() {
+        use crate::ReserveErrorKind;
+
+        let mut vec: TryVec<u64> = TryVec::new();
+        vec.push(1).unwrap();
+        let e = vec.try_reserve_detailed(usize::MAX).unwrap_err();
+        assert_eq!(e.kind(), ReserveErrorKind::CapacityOverflow);
+        assert_eq!(e.collection(), Some(CollectionKind::Vec));
+        assert_eq!(e.len(), Some(usize::MAX));
+        // the buffer fits in isize::MAX bytes but not in the address space
+        #[cfg(target_pointer_width = "64")]
+        {
+            let e = vec.try_reserve_detailed(1 << 50).unwrap_err();
+            assert_eq!(e.kind(), ReserveErrorKind::AllocError);
+            assert_eq!(e.len(), Some((1 << 50) + 1));
+        }
+        assert_eq!(vec, [1].as_ref());
+    }
+
     #[test]
     fn try_reserve_zst() {
         let mut vec: Vec<()> = Vec::new();
-        assert!(FallibleVec::try_reserve(&mut vec, core::usize::MAX).is_ok());
+        assert!(FallibleVec::try_reserve(&mut vec, usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn no_copy_rollback() {
+        use crate::try_clone::tests::Limited;
+
+        let mut vec: Vec<Limited> = (0..3).map(Limited::new).collect();
+        let other: Vec<Limited> = (3..6).map(Limited::new).collect();
+        let live = Limited::live();
+
+        Limited::set_clones(2);
+        assert!(vec.try_resize_no_copy(10, Limited::new(6)).is_err());
+        assert_eq!(vec.len(), 3);
+        Limited::set_clones(2);
+        assert!(vec.try_extend_from_slice_no_copy(&other).is_err());
+        assert_eq!(vec.len(), 3);
+        let mut try_vec = TryVec::from(vec);
+        Limited::set_clones(2);
+        assert!(try_vec.extend_from_slice(&other).is_err());
+        assert_eq!(try_vec.len(), 3);
+        Limited::set_clones(usize::MAX);
+        // the clones made before the failures were dropped
+        assert_eq!(Limited::live(), live);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn tryvec_custom_allocator() {
+        use crate::allocator::tests::Bounded;
+
+        let alloc = Bounded::new(2);
+        let mut vec = TryVec::try_with_capacity_in(8, &alloc).unwrap();
+        vec.extend_from_slice(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(alloc.live(), 1);
+        let mut other = vec.split_off(2).unwrap();
+        assert_eq!(alloc.live(), 2);
+        assert_eq!(other, [3, 4].as_ref());
+        // the allocator refuses a third block
+        assert!(vec.try_clone().is_err());
+        assert!(vec.shrink_to_fit().is_err());
+        assert_eq!(vec.capacity(), 8);
+        assert!(vec.append(&mut other).is_ok());
+        drop(other);
+        assert_eq!(alloc.live(), 1);
+        vec.truncate(1);
+        vec.shrink_to_fit().unwrap();
+        assert_eq!(vec.capacity(), 1);
+        assert_eq!(alloc.live(), 1);
+        assert!(vec.into_iter().eq([1]));
+        assert_eq!(alloc.live(), 0);
     }
 }
@@ -0,0 +1,541 @@
+//! Implement Fallible VecDeque
+use super::TryClone;
+use crate::vec::TryFromIterator;
+use crate::{AllocError, CollectionKind, ReserveError, TryReserveError};
+use alloc::collections::vec_deque::{Drain, IntoIter, Iter, IterMut, VecDeque};
+use core::ops::{Index, IndexMut, RangeBounds};
+
+/// trait implementing all fallible methods on VecDeque
+pub trait FallibleVecDeque<T> {
+    /// see reserve (strong guarantee)
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
+    /// see push_back (strong guarantee)
+    fn try_push_back(&mut self, elem: T) -> Result<(), TryReserveError>;
+    /// try push_back and give back ownership in case of error (strong
+    /// guarantee)
+    fn try_push_back_give_back(&mut self, elem: T) -> Result<(), (T, TryReserveError)>;
+    /// see push_front (strong guarantee)
+    fn try_push_front(&mut self, elem: T) -> Result<(), TryReserveError>;
+    /// try push_front and give back ownership in case of error (strong
+    /// guarantee)
+    fn try_push_front_give_back(&mut self, elem: T) -> Result<(), (T, TryReserveError)>;
+    /// see with capacity, (Self must be sized by the constraint of Result)
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError>
+    where
+        Self: core::marker::Sized;
+    /// see insert (strong guarantee)
+    fn try_insert(&mut self, index: usize, element: T) -> Result<(), (T, TryReserveError)>;
+    /// see append (strong guarantee)
+    fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError>;
+    /// see resize_with (strong guarantee)
+    fn try_resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
+    where
+        F: FnMut() -> T;
+    /// extend the deque at the back by trying to clone the values in
+    /// `other`, the deque is left untouched on error (strong guarantee)
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError>
+    where
+        T: TryClone;
+}
+
+impl<T> FallibleVecDeque<T> for VecDeque<T> {
+    #[inline(always)]
+    fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.try_reserve(additional)
+    }
+
+    #[inline]
+    fn try_push_back(&mut self, elem: T) -> Result<(), TryReserveError> {
+        if self.len() == self.capacity() {
+            self.try_reserve(1)?;
+        }
+        self.push_back(elem);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push_back_give_back(&mut self, elem: T) -> Result<(), (T, TryReserveError)> {
+        if self.len() == self.capacity() {
+            if let Err(e) = self.try_reserve(1) {
+                return Err((elem, e));
+            }
+        }
+        self.push_back(elem);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push_front(&mut self, elem: T) -> Result<(), TryReserveError> {
+        if self.len() == self.capacity() {
+            self.try_reserve(1)?;
+        }
+        self.push_front(elem);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_push_front_give_back(&mut self, elem: T) -> Result<(), (T, TryReserveError)> {
+        if self.len() == self.capacity() {
+            if let Err(e) = self.try_reserve(1) {
+                return Err((elem, e));
+            }
+        }
+        self.push_front(elem);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError>
+    where
+        Self: core::marker::Sized,
+    {
+        let mut n = Self::new();
+        FallibleVecDeque::try_reserve(&mut n, capacity)?;
+        Ok(n)
+    }
+
+    #[inline]
+    fn try_insert(&mut self, index: usize, element: T) -> Result<(), (T, TryReserveError)> {
+        if self.len() == self.capacity() {
+            if let Err(e) = self.try_reserve(1) {
+                return Err((element, e));
+            }
+        }
+        self.insert(index, element);
+        Ok(())
+    }
+
+    #[inline]
+    fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_reserve(self, other.len())?;
+        self.append(other);
+        Ok(())
+    }
+
+    fn try_resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
+    where
+        F: FnMut() -> T,
+    {
+        let len = self.len();
+        if new_len > len {
+            FallibleVecDeque::try_reserve(self, new_len - len)?;
+        }
+        self.resize_with(new_len, f);
+        Ok(())
+    }
+
+    fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError>
+    where
+        T: TryClone,
+    {
+        let len = self.len();
+        FallibleVecDeque::try_reserve(self, other.len())?;
+        for element in other.iter() {
+            match element.try_clone() {
+                // can't reallocate as the room is reserved
+                Ok(element) => self.push_back(element),
+                Err(e) => {
+                    self.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: TryClone> TryClone for VecDeque<T> {
+    fn try_clone(&self) -> Result<Self, TryReserveError>
+    where
+        Self: core::marker::Sized,
+    {
+        let mut deque = <Self as FallibleVecDeque<T>>::try_with_capacity(self.len())?;
+        for element in self.iter() {
+            deque.push_back(element.try_clone()?);
+        }
+        Ok(deque)
+    }
+}
+
+/// TryVecDeque is a thin wrapper around alloc::collections::VecDeque to
+/// provide support for fallible allocation.
+///
+/// See the crate documentation for more.
+///
+/// # Examples
+///
+/// ```
+/// use fallible_collections::TryVecDeque;
+///
+/// let mut deque = TryVecDeque::new();
+/// deque.try_push_back(2).unwrap();
+/// deque.try_push_front(1).unwrap();
+/// deque.try_extend_from_slice(&[3, 4]).unwrap();
+/// assert_eq!(deque.make_contiguous(), [1, 2, 3, 4]);
+/// assert_eq!(deque.pop_front(), Some(1));
+/// ```
+#[derive(PartialEq, Eq)]
+pub struct TryVecDeque<T> {
+    inner: VecDeque<T>,
+}
+
+impl<T> Default for TryVecDeque<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+        }
+    }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Debug for TryVecDeque<T> {
+    #[inline]
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T> TryVecDeque<T> {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self {
+            inner: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Ok(Self {
+            inner: FallibleVecDeque::try_with_capacity(capacity)?,
+        })
+    }
+
+    #[inline(always)]
+    pub fn as_mut_slices(&mut self) -> (&mut [T], &mut [T]) {
+        self.inner.as_mut_slices()
+    }
+
+    #[inline(always)]
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        self.inner.as_slices()
+    }
+
+    #[inline(always)]
+    pub fn back(&self) -> Option<&T> {
+        self.inner.back()
+    }
+
+    #[inline(always)]
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        self.inner.back_mut()
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    #[inline(always)]
+    pub fn drain<R>(&mut self, range: R) -> Drain<'_, T>
+    where
+        R: RangeBounds<usize>,
+    {
+        self.inner.drain(range)
+    }
+
+    #[inline(always)]
+    pub fn front(&self) -> Option<&T> {
+        self.inner.front()
+    }
+
+    #[inline(always)]
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        self.inner.front_mut()
+    }
+
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.inner.get(index)
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.inner.get_mut(index)
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> VecDeque<T> {
+        self.inner
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.inner.iter()
+    }
+
+    #[inline(always)]
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.inner.iter_mut()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Rearranges the elements so that they are stored in one contiguous
+    /// slice, which is returned. This never allocates.
+    #[inline(always)]
+    pub fn make_contiguous(&mut self) -> &mut [T] {
+        self.inner.make_contiguous()
+    }
+
+    #[inline(always)]
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.inner.pop_back()
+    }
+
+    #[inline(always)]
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.inner.pop_front()
+    }
+
+    #[inline(always)]
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        self.inner.remove(index)
+    }
+
+    #[inline(always)]
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.inner.retain(f)
+    }
+
+    #[inline(always)]
+    pub fn swap(&mut self, i: usize, j: usize) {
+        self.inner.swap(i, j)
+    }
+
+    #[inline(always)]
+    pub fn truncate(&mut self, len: usize) {
+        self.inner.truncate(len)
+    }
+
+    /// Moves all the elements of `other` into `self`, leaving `other` empty.
+    /// Both deques are left unchanged on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_append(&mut self, other: &mut Self) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_append(&mut self.inner, &mut other.inner)
+    }
+
+    /// Inserts an element at `index`, the deque is left unchanged on error
+    /// (strong guarantee).
+    #[inline(always)]
+    pub fn try_insert(&mut self, index: usize, element: T) -> Result<(), TryReserveError> {
+        self.try_insert_give_back(index, element)
+            .map_err(TryReserveError::from)
+    }
+
+    /// Inserts an element at `index`, giving back the element if the
+    /// allocation failed. The deque is left unchanged on error (strong
+    /// guarantee).
+    #[inline(always)]
+    pub fn try_insert_give_back(&mut self, index: usize, element: T) -> Result<(), AllocError<T>> {
+        FallibleVecDeque::try_insert(&mut self.inner, index, element).map_err(AllocError::from)
+    }
+
+    /// Appends an element to the back of the deque, which is left unchanged on
+    /// error (strong guarantee).
+    #[inline(always)]
+    pub fn try_push_back(&mut self, value: T) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_push_back(&mut self.inner, value)
+    }
+
+    /// Appends an element to the back of the deque, giving back the
+    /// element if the allocation failed. The deque is left unchanged on error
+    /// (strong guarantee).
+    #[inline(always)]
+    pub fn try_push_back_give_back(&mut self, value: T) -> Result<(), AllocError<T>> {
+        FallibleVecDeque::try_push_back_give_back(&mut self.inner, value).map_err(AllocError::from)
+    }
+
+    /// Prepends an element to the front of the deque, which is left unchanged
+    /// on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_push_front(&mut self, value: T) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_push_front(&mut self.inner, value)
+    }
+
+    /// Prepends an element to the front of the deque, giving back the
+    /// element if the allocation failed. The deque is left unchanged on error
+    /// (strong guarantee).
+    #[inline(always)]
+    pub fn try_push_front_give_back(&mut self, value: T) -> Result<(), AllocError<T>> {
+        FallibleVecDeque::try_push_front_give_back(&mut self.inner, value).map_err(AllocError::from)
+    }
+
+    /// Reserves room for at least `additional` more elements, the deque is left
+    /// unchanged on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_reserve(&mut self.inner, additional)
+    }
+
+    /// Reserves room for at least `additional` more elements, as
+    /// `try_reserve`, the error telling the length the deque was grown to.
+    /// The deque is left unchanged on error (strong guarantee).
+    pub fn try_reserve_detailed(&mut self, additional: usize) -> Result<(), ReserveError> {
+        let len = self.len().saturating_add(additional);
+        self.try_reserve(additional).map_err(|e| {
+            ReserveError::from(e)
+                .with_collection(CollectionKind::VecDeque)
+                .with_len(len)
+        })
+    }
+
+    /// Resizes the deque to `new_len`, filling the new slots with the values
+    /// returned by `f`. The deque is left unchanged on error (strong
+    /// guarantee).
+    #[inline(always)]
+    pub fn try_resize_with<F>(&mut self, new_len: usize, f: F) -> Result<(), TryReserveError>
+    where
+        F: FnMut() -> T,
+    {
+        FallibleVecDeque::try_resize_with(&mut self.inner, new_len, f)
+    }
+}
+
+impl<T: TryClone> TryVecDeque<T> {
+    /// Clones the elements of `other` and appends them to the back of the
+    /// deque, the deque is left untouched on error (strong guarantee).
+    #[inline(always)]
+    pub fn try_extend_from_slice(&mut self, other: &[T]) -> Result<(), TryReserveError> {
+        FallibleVecDeque::try_extend_from_slice(&mut self.inner, other)
+    }
+}
+
+impl<T: TryClone> TryClone for TryVecDeque<T> {
+    #[inline]
+    fn try_clone(&self) -> Result<Self, TryReserveError> {
+        Ok(Self {
+            inner: self.inner.try_clone()?,
+        })
+    }
+}
+
+impl<T> TryFromIterator<T> for TryVecDeque<T> {
+    fn try_from_iterator<I: IntoIterator<Item = T>>(iterator: I) -> Result<Self, TryReserveError> {
+        let mut deque = Self::new();
+        for element in iterator {
+            deque.try_push_back(element)?;
+        }
+        Ok(deque)
+    }
+}
+
+impl<T> Index<usize> for TryVecDeque<T> {
+    type Output = T;
+
+    #[inline(always)]
+    fn index(&self, index: usize) -> &T {
+        &self.inner[index]
+    }
+}
+
+impl<T> IndexMut<usize> for TryVecDeque<T> {
+    #[inline(always)]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.inner[index]
+    }
+}
+
+impl<T> IntoIterator for TryVecDeque<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TryVecDeque<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    #[inline(always)]
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TryCollect;
+
+    #[test]
+    fn push_pop_both_ends() {
+        let mut deque = TryVecDeque::try_with_capacity(2).unwrap();
+        for i in 0..10 {
+            deque.try_push_back(i).unwrap();
+            deque.try_push_front(-i).unwrap();
+        }
+        assert_eq!(deque.len(), 20);
+        assert_eq!(deque.front(), Some(&-9));
+        assert_eq!(deque.back(), Some(&9));
+        deque.try_insert(10, 100).unwrap();
+        assert_eq!(deque[10], 100);
+        assert_eq!(deque.pop_front(), Some(-9));
+        assert_eq!(deque.pop_back(), Some(9));
+        let slice = deque.make_contiguous();
+        assert_eq!(slice.len(), 19);
+        assert_eq!(slice[9], 100);
+    }
+
+    #[test]
+    fn try_reserve_detailed() {
+        let mut deque: TryVecDeque<u32> = (0..4).try_collect().unwrap();
+        let e = deque.try_reserve_detailed(usize::MAX).unwrap_err();
+        assert_eq!(e.kind(), crate::ReserveErrorKind::CapacityOverflow);
+        assert_eq!(e.collection(), Some(CollectionKind::VecDeque));
+        assert_eq!(e.len(), Some(usize::MAX));
+        assert_eq!(deque.len(), 4);
+    }
+
+    #[test]
+    fn append_extend_resize() {
+        let mut a: TryVecDeque<u32> = (0..4).try_collect().unwrap();
+        let mut b: TryVecDeque<u32> = (4..8).try_collect().unwrap();
+        a.try_append(&mut b).unwrap();
+        assert!(b.is_empty());
+        a.try_extend_from_slice(&[8, 9]).unwrap();
+        assert!(a.iter().copied().eq(0..10));
+        a.try_resize_with(12, || 42).unwrap();
+        assert_eq!(a.back(), Some(&42));
+        a.try_resize_with(3, || 0).unwrap();
+        assert_eq!(a.try_clone().unwrap(), a);
+        assert!(a.into_iter().eq(0..3));
+    }
+
+    #[test]
+    fn tryvecdeque_oom() {
+        let mut deque = TryVecDeque::<u64>::new();
+        assert!(deque.try_reserve(usize::MAX).is_err());
+        assert!(TryVecDeque::<u64>::try_with_capacity(usize::MAX).is_err());
+        deque.try_push_back(1).unwrap();
+        assert_eq!(deque.len(), 1);
+    }
+}